@@ -0,0 +1,87 @@
+//! Deserializers for the accounts owned by the vesting program
+
+use anchor_lang::{AccountDeserialize, Result};
+
+use crate::vesting_accounts::{
+    BackendAccountData, SessionIdsAccount, VaultAccount, VestingSession, VestingSessionsAccount,
+};
+
+/// Deserializes the data of a program account, checking its discriminator
+pub fn deserialize<T: AccountDeserialize>(mut data: &[u8]) -> Result<T> {
+    T::try_deserialize(&mut data)
+}
+
+pub fn backend_data(data: &[u8]) -> Result<BackendAccountData> {
+    deserialize(data)
+}
+
+pub fn vault(data: &[u8]) -> Result<VaultAccount> {
+    deserialize(data)
+}
+
+pub fn vesting_sessions(data: &[u8]) -> Result<VestingSessionsAccount> {
+    deserialize(data)
+}
+
+pub fn vesting_session(data: &[u8]) -> Result<VestingSession> {
+    deserialize(data)
+}
+
+pub fn session_ids(data: &[u8]) -> Result<SessionIdsAccount> {
+    deserialize(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::{error::ErrorCode, prelude::Pubkey, AccountSerialize};
+
+    fn serialize<T: AccountSerialize>(account: &T) -> Vec<u8> {
+        let mut data = Vec::new();
+        account.try_serialize(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn deserializes_vesting_session() {
+        let session = VestingSession {
+            id: 7,
+            user: Pubkey::new_unique(),
+            vesting_sessions_account: Pubkey::new_unique(),
+            amount: 1_000,
+            amount_withdrawn: 250,
+            start_date: 1_700_000_000,
+            last_withdraw_at: 1_700_000_600,
+            cancelled_at: 0,
+            position_mint: Some(Pubkey::new_unique()),
+            amount_owed: 0,
+            amount_unlocked: 0,
+        };
+
+        let decoded = vesting_session(&serialize(&session)).unwrap();
+        assert_eq!(decoded.id, session.id);
+        assert_eq!(decoded.user, session.user);
+        assert_eq!(decoded.amount_withdrawn, session.amount_withdrawn);
+        assert_eq!(decoded.position_mint, session.position_mint);
+    }
+
+    #[test]
+    fn rejects_accounts_of_another_type() {
+        let sessions = VestingSessionsAccount {
+            last_session_id: 2,
+            user: Pubkey::new_unique(),
+            active_sessions: 1,
+        };
+        let data = serialize(&sessions);
+
+        assert_eq!(vesting_sessions(&data).unwrap().last_session_id, 2);
+        assert_eq!(
+            vesting_session(&data).err(),
+            Some(ErrorCode::AccountDiscriminatorMismatch.into())
+        );
+        assert_eq!(
+            vault(&data[..4]).err(),
+            Some(ErrorCode::AccountDiscriminatorNotFound.into())
+        );
+    }
+}
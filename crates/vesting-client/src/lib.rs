@@ -0,0 +1,90 @@
+//! Rust client for the vesting contract: PDA derivations, instruction builders, account
+//! deserializers and an off-chain simulator of the vesting sessions. Build with the `cpi-events`
+//! feature when the deployed program was built with it, the instructions then carry the event
+//! authority accounts.
+
+pub mod accounts;
+pub mod instructions;
+pub mod pda;
+pub mod simulator;
+
+use anchor_lang::prelude::Pubkey;
+use anchor_spl::associated_token::get_associated_token_address_with_program_id;
+
+pub use vesting_contract::{events, vesting_accounts, ID};
+
+/// Token-2022 program, which owns every escrow and position mint
+pub const TOKEN_PROGRAM_ID: Pubkey = anchor_spl::token_2022::ID;
+
+/// Mints and token program identifying a vault
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VaultKeys {
+    pub valued_token_mint: Pubkey,
+    pub escrow_token_mint: Pubkey,
+    pub valued_token_program: Pubkey, // Token or Token-2022 program owning the valued mint
+}
+
+impl VaultKeys {
+    pub fn new(
+        valued_token_mint: Pubkey,
+        escrow_token_mint: Pubkey,
+        valued_token_program: Pubkey,
+    ) -> Self {
+        Self {
+            valued_token_mint,
+            escrow_token_mint,
+            valued_token_program,
+        }
+    }
+
+    /// Keys of the vault created for an app, whose escrow mint is derived from the app id
+    pub fn for_app(valued_token_mint: Pubkey, valued_token_program: Pubkey, app_id: &str) -> Self {
+        let (escrow_token_mint, _) = pda::escrow_mint_address(&valued_token_mint, app_id);
+        Self::new(valued_token_mint, escrow_token_mint, valued_token_program)
+    }
+
+    pub fn vault(&self) -> Pubkey {
+        pda::vault_address(&self.valued_token_mint, &self.escrow_token_mint).0
+    }
+
+    pub fn valued_vault_token_account(&self) -> Pubkey {
+        self.valued_token_account(&self.vault())
+    }
+
+    pub fn escrow_vault_token_account(&self) -> Pubkey {
+        self.escrow_token_account(&self.vault())
+    }
+
+    /// Associated valued token account of an owner
+    pub fn valued_token_account(&self, owner: &Pubkey) -> Pubkey {
+        get_associated_token_address_with_program_id(
+            owner,
+            &self.valued_token_mint,
+            &self.valued_token_program,
+        )
+    }
+
+    /// Associated escrow token account of an owner
+    pub fn escrow_token_account(&self, owner: &Pubkey) -> Pubkey {
+        get_associated_token_address_with_program_id(
+            owner,
+            &self.escrow_token_mint,
+            &TOKEN_PROGRAM_ID,
+        )
+    }
+
+    /// Collection tracking the vesting sessions of a user in the vault
+    pub fn vesting_sessions(&self, user: &Pubkey) -> Pubkey {
+        pda::vesting_sessions_address(&self.vault(), user).0
+    }
+
+    /// Account keeping the last session id of a user whose collection was closed
+    pub fn session_ids(&self, user: &Pubkey) -> Pubkey {
+        pda::session_ids_address(&self.vault(), user).0
+    }
+
+    /// Vesting session of a user in the vault
+    pub fn vesting_session(&self, user: &Pubkey, session_id: u64) -> Pubkey {
+        pda::vesting_session_address(&self.vesting_sessions(user), session_id).0
+    }
+}
@@ -0,0 +1,180 @@
+//! Addresses of the accounts derived by the vesting program
+
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::solana_program::bpf_loader_upgradeable;
+
+use crate::ID;
+
+pub const BACKEND_DATA_SEED: &[u8] = b"davincij15_seed";
+pub const VAULT_SEED: &[u8] = b"token_vault";
+pub const ESCROW_MINT_SEED: &[u8] = b"escrow_mint";
+pub const VESTING_SESSIONS_SEED: &[u8] = b"user_vesting_session_collection";
+pub const VESTING_SESSION_SEED: &[u8] = b"user_vesting_session";
+pub const SESSION_IDS_SEED: &[u8] = b"user_session_ids";
+pub const POSITION_MINT_SEED: &[u8] = b"position_mint";
+pub const EVENT_AUTHORITY_SEED: &[u8] = b"__event_authority";
+
+/// Account holding the backend configuration
+pub fn backend_data_address() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[BACKEND_DATA_SEED], &ID)
+}
+
+/// Program data account of the upgradeable loader, whose upgrade authority is the program admin
+pub fn program_data_address() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[ID.as_ref()], &bpf_loader_upgradeable::id())
+}
+
+/// Vault exchanging a valued token for an escrow token
+pub fn vault_address(valued_token_mint: &Pubkey, escrow_token_mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            VAULT_SEED,
+            valued_token_mint.as_ref(),
+            escrow_token_mint.as_ref(),
+        ],
+        &ID,
+    )
+}
+
+/// Escrow token mint created for an app
+pub fn escrow_mint_address(valued_token_mint: &Pubkey, app_id: &str) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            ESCROW_MINT_SEED,
+            valued_token_mint.as_ref(),
+            app_id.as_bytes(),
+        ],
+        &ID,
+    )
+}
+
+/// Collection tracking the vesting sessions of a user in a vault
+pub fn vesting_sessions_address(vault_account: &Pubkey, user: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[VESTING_SESSIONS_SEED, vault_account.as_ref(), user.as_ref()],
+        &ID,
+    )
+}
+
+/// Account keeping the last session id of a user in a vault once their collection is closed
+pub fn session_ids_address(vault_account: &Pubkey, user: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[SESSION_IDS_SEED, vault_account.as_ref(), user.as_ref()],
+        &ID,
+    )
+}
+
+/// Vesting session of a collection, sessions are numbered from the `last_session_id` of the
+/// collection, or of the [`session_ids_address`] account when it is higher, when they are created
+pub fn vesting_session_address(vesting_sessions_account: &Pubkey, session_id: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            VESTING_SESSION_SEED,
+            vesting_sessions_account.as_ref(),
+            session_id.to_le_bytes().as_ref(),
+        ],
+        &ID,
+    )
+}
+
+/// Position token mint of a tokenized vesting session
+pub fn position_mint_address(vesting_session_account: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[POSITION_MINT_SEED, vesting_session_account.as_ref()], &ID)
+}
+
+/// Authority signing the self CPI of events when the program is built with `cpi-events`
+pub fn event_authority_address() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[EVENT_AUTHORITY_SEED], &ID)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Seeds as written in the account constraints of the program
+    #[test]
+    fn derivations_match_program_seeds() {
+        let valued_token_mint = Pubkey::new_unique();
+        let user = Pubkey::new_unique();
+
+        assert_eq!(
+            backend_data_address(),
+            Pubkey::find_program_address(&[b"davincij15_seed"], &ID)
+        );
+
+        let (escrow_token_mint, _) = escrow_mint_address(&valued_token_mint, "app");
+        assert_eq!(
+            escrow_token_mint,
+            Pubkey::find_program_address(
+                &[b"escrow_mint", valued_token_mint.as_ref(), b"app"],
+                &ID
+            )
+            .0
+        );
+
+        let (vault_account, _) = vault_address(&valued_token_mint, &escrow_token_mint);
+        assert_eq!(
+            vault_account,
+            Pubkey::find_program_address(
+                &[
+                    b"token_vault",
+                    valued_token_mint.as_ref(),
+                    escrow_token_mint.as_ref()
+                ],
+                &ID
+            )
+            .0
+        );
+
+        let (vesting_sessions, _) = vesting_sessions_address(&vault_account, &user);
+        assert_eq!(
+            vesting_sessions,
+            Pubkey::find_program_address(
+                &[
+                    b"user_vesting_session_collection",
+                    vault_account.as_ref(),
+                    user.as_ref()
+                ],
+                &ID
+            )
+            .0
+        );
+
+        assert_eq!(
+            session_ids_address(&vault_account, &user).0,
+            Pubkey::find_program_address(
+                &[b"user_session_ids", vault_account.as_ref(), user.as_ref()],
+                &ID
+            )
+            .0
+        );
+
+        let (vesting_session, _) = vesting_session_address(&vesting_sessions, 3);
+        assert_eq!(
+            vesting_session,
+            Pubkey::find_program_address(
+                &[
+                    b"user_vesting_session",
+                    vesting_sessions.as_ref(),
+                    &3u64.to_le_bytes()
+                ],
+                &ID
+            )
+            .0
+        );
+
+        assert_eq!(
+            position_mint_address(&vesting_session),
+            Pubkey::find_program_address(&[b"position_mint", vesting_session.as_ref()], &ID)
+        );
+    }
+
+    #[test]
+    fn session_addresses_are_unique_per_id() {
+        let vesting_sessions = Pubkey::new_unique();
+        assert_ne!(
+            vesting_session_address(&vesting_sessions, 0).0,
+            vesting_session_address(&vesting_sessions, 1).0
+        );
+    }
+}
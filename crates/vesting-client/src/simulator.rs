@@ -0,0 +1,170 @@
+//! Off-chain simulation of vesting sessions, sharing the release math of the program. Amounts are
+//! escrow token amounts released by the schedule, payouts reduced by a vault shortfall are not
+//! simulated.
+
+use std::num::NonZeroU64;
+
+pub use vesting_math::{MathError, Schedule, VESTING_PERIOD_SECONDS};
+
+use crate::vesting_accounts::VestingSession;
+
+/// Amount claimable at a point in time
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnlockPoint {
+    pub time: u64,
+    pub claimable: u64,
+}
+
+/// Withdrawal executed by the program
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Withdrawal {
+    pub time: u64,
+    pub amount: u64,
+    pub amount_withdrawn: u64, // Amount withdrawn from the session after this withdrawal
+}
+
+/// Amount a withdrawal at `now` would release, nothing for cancelled sessions
+pub fn claimable(session: &VestingSession, now: u64) -> Result<u64, MathError> {
+    if session.cancelled_at != 0 {
+        return Ok(0);
+    }
+    vesting_math::amount_to_release(&Schedule::from(session), now)
+}
+
+/// Claimable amounts every `interval` seconds from `from` until the end of the vesting period,
+/// assuming nothing is withdrawn meanwhile. The last point is the end of the period, when every
+/// remaining token is claimable.
+pub fn unlock_timeline(
+    session: &VestingSession,
+    from: u64,
+    interval: NonZeroU64,
+) -> Result<Vec<UnlockPoint>, MathError> {
+    let end_date = Schedule::from(session).end_date()?.max(from);
+    let mut timeline = Vec::new();
+    let mut time = from;
+    while time < end_date {
+        timeline.push(UnlockPoint {
+            time,
+            claimable: claimable(session, time)?,
+        });
+        time = time.saturating_add(interval.get());
+    }
+    timeline.push(UnlockPoint {
+        time: end_date,
+        claimable: claimable(session, end_date)?,
+    });
+    Ok(timeline)
+}
+
+/// Replays withdrawals attempted at ascending `times` as the program executes them. Attempts
+/// releasing nothing fail on-chain and are left out.
+pub fn simulate_withdrawals(
+    session: &VestingSession,
+    times: &[u64],
+) -> Result<Vec<Withdrawal>, MathError> {
+    if session.cancelled_at != 0 {
+        return Ok(Vec::new());
+    }
+
+    let mut schedule = Schedule::from(session);
+    let mut withdrawals = Vec::new();
+    for &time in times {
+        let amount = vesting_math::amount_to_release(&schedule, time)?;
+        if amount == 0 {
+            continue;
+        }
+        vesting_math::record_withdrawal(&mut schedule, amount, time)?;
+        withdrawals.push(Withdrawal {
+            time,
+            amount,
+            amount_withdrawn: schedule.amount_withdrawn,
+        });
+    }
+    Ok(withdrawals)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::prelude::Pubkey;
+
+    const START: u64 = 1_700_000_000;
+    const DAY: u64 = 24 * 60 * 60;
+
+    fn session(amount: u64) -> VestingSession {
+        VestingSession {
+            id: 0,
+            user: Pubkey::new_unique(),
+            vesting_sessions_account: Pubkey::new_unique(),
+            amount,
+            amount_withdrawn: 0,
+            start_date: START,
+            last_withdraw_at: 0,
+            cancelled_at: 0,
+            position_mint: None,
+            amount_owed: 0,
+            amount_unlocked: 0,
+        }
+    }
+
+    #[test]
+    fn timeline_releases_everything_at_the_end_of_the_period() {
+        let session = session(1_000_000_000);
+        let timeline =
+            unlock_timeline(&session, START, NonZeroU64::new(30 * DAY).unwrap()).unwrap();
+
+        assert_eq!(timeline.len(), 7);
+        assert_eq!(
+            timeline[0],
+            UnlockPoint {
+                time: START,
+                claimable: 0
+            }
+        );
+        assert!(timeline
+            .windows(2)
+            .all(|w| w[0].claimable <= w[1].claimable));
+        assert_eq!(
+            timeline.last(),
+            Some(&UnlockPoint {
+                time: START + VESTING_PERIOD_SECONDS,
+                claimable: 1_000_000_000,
+            })
+        );
+    }
+
+    #[test]
+    fn replays_withdrawals_as_the_program() {
+        let session = session(1_000_000_000);
+        let times = [
+            START + 30,
+            START + DAY + 30,
+            START + DAY + 60,
+            START + 200 * DAY,
+        ];
+        let withdrawals = simulate_withdrawals(&session, &times).unwrap();
+
+        // The first attempt releases nothing, the seconds of the started minute are lost on the
+        // second one so the third one releases nothing either
+        let per_minute = 1_000_000_000 / vesting_math::VESTING_PERIOD_MINUTES;
+        assert_eq!(withdrawals.len(), 2);
+        assert_eq!(withdrawals[0].time, START + DAY + 30);
+        assert_eq!(withdrawals[0].amount, per_minute * 24 * 60);
+        assert_eq!(withdrawals[1].amount_withdrawn, 1_000_000_000);
+        assert_eq!(
+            withdrawals.iter().map(|w| w.amount).sum::<u64>(),
+            session.amount
+        );
+    }
+
+    #[test]
+    fn cancelled_sessions_release_nothing() {
+        let mut session = session(1_000_000_000);
+        session.cancelled_at = START + DAY;
+        assert_eq!(claimable(&session, START + 200 * DAY), Ok(0));
+        assert_eq!(
+            simulate_withdrawals(&session, &[START + 200 * DAY]),
+            Ok(vec![])
+        );
+    }
+}
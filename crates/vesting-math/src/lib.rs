@@ -0,0 +1,416 @@
+//! Release math of the vesting contract. Every function takes the current time as an argument, so
+//! the program and off-chain clients compute exactly the same amounts.
+
+#![no_std]
+
+/// Length of the vesting period in minutes (180 days * 24 hours * 60 minutes)
+pub const VESTING_PERIOD_MINUTES: u64 = 180 * 24 * 60;
+/// Length of the vesting period in seconds
+pub const VESTING_PERIOD_SECONDS: u64 = VESTING_PERIOD_MINUTES * 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MathError {
+    ArithmeticOverflow,
+    DivisionByZero,
+    InvalidSplitAmount,
+}
+
+pub type Result<T> = core::result::Result<T, MathError>;
+
+/// Release schedule of a vesting session, times are unix timestamps in seconds
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Schedule {
+    pub amount: u64,           // Total amount of tokens of the session
+    pub amount_withdrawn: u64, // Amount of tokens already withdrawn
+    pub start_date: u64,
+    pub last_withdraw_at: u64, // Zero if nothing was withdrawn yet
+    pub amount_unlocked: u64,  // Released tokens of folded schedules, not part of `amount`
+}
+
+impl Schedule {
+    /// End of the vesting period, when every remaining token is released
+    pub fn end_date(&self) -> Result<u64> {
+        self.start_date
+            .checked_add(VESTING_PERIOD_SECONDS)
+            .ok_or(MathError::ArithmeticOverflow)
+    }
+
+    /// Tokens not withdrawn yet, including the unlocked ones
+    pub fn remaining_amount(&self) -> u64 {
+        self.locked_amount().saturating_add(self.amount_unlocked)
+    }
+
+    /// Tokens of `amount` not withdrawn yet, released by the schedule
+    pub fn locked_amount(&self) -> u64 {
+        self.amount.saturating_sub(self.amount_withdrawn)
+    }
+
+    /// Time releases are counted from, the last withdrawal or the start date
+    pub fn release_reference(&self) -> u64 {
+        if self.last_withdraw_at > 0 {
+            self.last_withdraw_at
+        } else {
+            self.start_date
+        }
+    }
+}
+
+/// Calculates the amount of tokens released at `now`.
+///
+/// Tokens are released every whole minute since the last withdrawal, so the seconds of a started
+/// minute are lost when withdrawing. Every remaining token is released once the period ended, and
+/// unlocked tokens are released at any time.
+pub fn amount_to_release(schedule: &Schedule, now: u64) -> Result<u64> {
+    // Check if vesting period has ended
+    if now >= schedule.end_date()? {
+        return Ok(schedule.remaining_amount());
+    }
+
+    // Calculate elapsed time since last withdrawal or start
+    let elapsed_seconds = now.saturating_sub(schedule.release_reference());
+
+    // Calculate amount to be released
+    let amount_per_minute = schedule
+        .amount
+        .checked_div(VESTING_PERIOD_MINUTES)
+        .ok_or(MathError::DivisionByZero)?;
+    let amount_released = elapsed_seconds
+        .checked_div(60)
+        .ok_or(MathError::DivisionByZero)?
+        .checked_mul(amount_per_minute)
+        .ok_or(MathError::ArithmeticOverflow)?;
+
+    // Ensure we're not releasing more than what's available
+    core::cmp::min(amount_released, schedule.locked_amount())
+        .checked_add(schedule.amount_unlocked)
+        .ok_or(MathError::ArithmeticOverflow)
+}
+
+/// Records a withdrawal of `amount` tokens at `now`, unlocked tokens are withdrawn first
+pub fn record_withdrawal(schedule: &mut Schedule, amount: u64, now: u64) -> Result<()> {
+    let unlocked_withdrawn = core::cmp::min(amount, schedule.amount_unlocked);
+    schedule.amount_unlocked -= unlocked_withdrawn;
+    schedule.amount_withdrawn = schedule
+        .amount_withdrawn
+        .checked_add(amount - unlocked_withdrawn)
+        .ok_or(MathError::ArithmeticOverflow)?;
+    schedule.last_withdraw_at = now;
+    Ok(())
+}
+
+/// Valued token balances a vault owes to its escrow holders
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VaultLedger {
+    pub expected_balance: u64, // Valued tokens the vault should hold for its escrow holders
+    pub owed_balance: u64,     // Part of them owed to sessions paid short by a shortfall
+}
+
+/// Books a claim of `claim` released tokens of a session against a vault holding `balance`
+/// valued tokens and returns the amount to pay out.
+///
+/// A vault holding less than expected pays the claim pro-rated by the coverage of the claims not
+/// booked yet, and books the full claim. Every holder gets the same share whatever the order of
+/// the withdrawals, the unpaid part is added to `session_owed` and to the owed balance of the
+/// vault, outside of that coverage. Once the vault is refilled the owed tokens are paid in full
+/// with the next claim of the session.
+pub fn book_payout(
+    ledger: &mut VaultLedger,
+    balance: u64,
+    claim: u64,
+    session_owed: &mut u64,
+) -> Result<u64> {
+    if balance >= ledger.expected_balance {
+        let payout = claim
+            .checked_add(*session_owed)
+            .ok_or(MathError::ArithmeticOverflow)?;
+        ledger.owed_balance = ledger
+            .owed_balance
+            .checked_sub(*session_owed)
+            .ok_or(MathError::ArithmeticOverflow)?;
+        ledger.expected_balance = ledger
+            .expected_balance
+            .checked_sub(payout)
+            .ok_or(MathError::ArithmeticOverflow)?;
+        *session_owed = 0;
+        return Ok(payout);
+    }
+
+    let outstanding_claims = ledger
+        .expected_balance
+        .checked_sub(ledger.owed_balance)
+        .ok_or(MathError::ArithmeticOverflow)?;
+    let payout = if balance < outstanding_claims {
+        let prorated = (claim as u128)
+            .checked_mul(balance as u128)
+            .ok_or(MathError::ArithmeticOverflow)?
+            .checked_div(outstanding_claims as u128)
+            .ok_or(MathError::DivisionByZero)?;
+        u64::try_from(prorated).map_err(|_| MathError::ArithmeticOverflow)?
+    } else {
+        claim
+    };
+    let unpaid = claim - payout;
+
+    ledger.expected_balance = ledger
+        .expected_balance
+        .checked_sub(payout)
+        .ok_or(MathError::ArithmeticOverflow)?;
+    ledger.owed_balance = ledger
+        .owed_balance
+        .checked_add(unpaid)
+        .ok_or(MathError::ArithmeticOverflow)?;
+    *session_owed = session_owed
+        .checked_add(unpaid)
+        .ok_or(MathError::ArithmeticOverflow)?;
+    Ok(payout)
+}
+
+/// Folds a new tranche of tokens into an ongoing vesting session.
+///
+/// The tokens both schedules released at `now` stay claimable as unlocked tokens. The tokens still
+/// locked are re-based into a single schedule releasing from `now` until the latest end of the two,
+/// so the combined session never releases a token before the schedule it came from would have.
+/// Ended schedules fold too, all their tokens are unlocked. As on withdrawals, the seconds of a
+/// started minute are lost.
+pub fn fold_tranche(schedule: &mut Schedule, tranche: &Schedule, now: u64) -> Result<()> {
+    let released_amount = amount_to_release(schedule, now)?;
+    let tranche_released_amount = amount_to_release(tranche, now)?;
+    let locked_amount = schedule.remaining_amount() - released_amount;
+    let tranche_locked_amount = tranche.remaining_amount() - tranche_released_amount;
+
+    // The latest start of the schedules still locking tokens sets the end of the combined one
+    let new_start_date = match (locked_amount > 0, tranche_locked_amount > 0) {
+        (true, true) => schedule.start_date.max(tranche.start_date),
+        (false, true) => tranche.start_date,
+        _ => schedule.start_date,
+    };
+
+    schedule.amount = locked_amount
+        .checked_add(tranche_locked_amount)
+        .ok_or(MathError::ArithmeticOverflow)?;
+    schedule.amount_withdrawn = 0;
+    schedule.amount_unlocked = released_amount
+        .checked_add(tranche_released_amount)
+        .ok_or(MathError::ArithmeticOverflow)?;
+    schedule.start_date = new_start_date;
+    // Releases are counted from now, at the pace of the re-based amount
+    schedule.last_withdraw_at = if now > new_start_date { now } else { 0 };
+
+    Ok(())
+}
+
+/// Carves `amount` of the locked tokens off a vesting session keeping the same schedule.
+///
+/// Total and withdrawn amounts are split proportionally, so both parts keep releasing at the
+/// same pace, unlocked tokens stay in the session. Returns the total and withdrawn amounts of the
+/// carved off part.
+pub fn split_amounts(schedule: &mut Schedule, amount: u64) -> Result<(u64, u64)> {
+    let remaining_amount = schedule
+        .amount
+        .checked_sub(schedule.amount_withdrawn)
+        .ok_or(MathError::ArithmeticOverflow)?;
+    if amount == 0 || amount >= remaining_amount {
+        return Err(MathError::InvalidSplitAmount);
+    }
+
+    let split_amount = (schedule.amount as u128)
+        .checked_mul(amount as u128)
+        .ok_or(MathError::ArithmeticOverflow)?
+        .checked_div(remaining_amount as u128)
+        .ok_or(MathError::DivisionByZero)?;
+    let split_amount = u64::try_from(split_amount).map_err(|_| MathError::ArithmeticOverflow)?;
+    let split_amount_withdrawn = split_amount
+        .checked_sub(amount)
+        .ok_or(MathError::ArithmeticOverflow)?;
+
+    schedule.amount = schedule
+        .amount
+        .checked_sub(split_amount)
+        .ok_or(MathError::ArithmeticOverflow)?;
+    schedule.amount_withdrawn = schedule
+        .amount_withdrawn
+        .checked_sub(split_amount_withdrawn)
+        .ok_or(MathError::ArithmeticOverflow)?;
+
+    Ok((split_amount, split_amount_withdrawn))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: u64 = 1_700_000_000;
+
+    fn schedule(amount: u64) -> Schedule {
+        Schedule {
+            amount,
+            start_date: START,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn releases_whole_minutes() {
+        let schedule = schedule(VESTING_PERIOD_MINUTES * 10);
+        assert_eq!(amount_to_release(&schedule, START), Ok(0));
+        assert_eq!(amount_to_release(&schedule, START + 59), Ok(0));
+        assert_eq!(amount_to_release(&schedule, START + 60), Ok(10));
+        assert_eq!(amount_to_release(&schedule, START + 150), Ok(20));
+    }
+
+    #[test]
+    fn releases_everything_once_the_period_ended() {
+        let mut schedule = schedule(1_000_003);
+        record_withdrawal(&mut schedule, 400_000, START + 600).unwrap();
+        assert_eq!(
+            amount_to_release(&schedule, START + VESTING_PERIOD_SECONDS),
+            Ok(600_003)
+        );
+    }
+
+    #[test]
+    fn counts_from_the_last_withdrawal() {
+        let mut schedule = schedule(VESTING_PERIOD_MINUTES);
+        let released = amount_to_release(&schedule, START + 90).unwrap();
+        assert_eq!(released, 1);
+        record_withdrawal(&mut schedule, released, START + 90).unwrap();
+
+        // The 30 seconds of the started minute were lost by withdrawing
+        assert_eq!(amount_to_release(&schedule, START + 120), Ok(0));
+        assert_eq!(amount_to_release(&schedule, START + 150), Ok(1));
+    }
+
+    /// Total amount released at `now` by schedules withdrawn at every sampled time
+    fn released(schedule: &Schedule, now: u64) -> u64 {
+        schedule.amount_withdrawn + amount_to_release(schedule, now).unwrap()
+    }
+
+    #[test]
+    fn shares_a_shortfall_whatever_the_withdrawal_order() {
+        // Two holders of 50 tokens each, half of the vault balance was drained
+        let mut ledger = VaultLedger {
+            expected_balance: 100,
+            owed_balance: 0,
+        };
+        let mut balance = 50;
+        let (mut owed_a, mut owed_b) = (0, 0);
+
+        // The first holder withdraws part of its claim, then the rest
+        for claim in [20, 30] {
+            let paid = book_payout(&mut ledger, balance, claim, &mut owed_a).unwrap();
+            assert_eq!(paid, claim / 2);
+            balance -= paid;
+        }
+        // Withdrawing again while degraded pays nothing more
+        assert_eq!(book_payout(&mut ledger, balance, 0, &mut owed_a), Ok(0));
+
+        // The second holder gets the same share
+        let paid = book_payout(&mut ledger, balance, 50, &mut owed_b).unwrap();
+        assert_eq!(paid, 25);
+        balance -= paid;
+        assert_eq!((owed_a, owed_b), (25, 25));
+        assert_eq!(balance, 0);
+        assert_eq!(
+            ledger,
+            VaultLedger {
+                expected_balance: 50,
+                owed_balance: 50,
+            }
+        );
+
+        // Once refilled the owed tokens are paid in full
+        balance += 50;
+        assert_eq!(book_payout(&mut ledger, balance, 0, &mut owed_a), Ok(25));
+        balance -= 25;
+        assert_eq!(book_payout(&mut ledger, balance, 0, &mut owed_b), Ok(25));
+        assert_eq!((owed_a, owed_b), (0, 0));
+        assert_eq!(ledger, VaultLedger::default());
+    }
+
+    #[test]
+    fn folded_tranches_never_release_early() {
+        // Top up of a session close to the end of its period
+        let top_up_at = START + VESTING_PERIOD_SECONDS - 600;
+        let mut original = schedule(VESTING_PERIOD_MINUTES * 10);
+        let released_before = amount_to_release(&original, top_up_at).unwrap();
+        record_withdrawal(&mut original, released_before, top_up_at).unwrap();
+        let tranche = Schedule {
+            amount: VESTING_PERIOD_MINUTES * 30,
+            start_date: top_up_at,
+            ..Default::default()
+        };
+        let mut session = original;
+        fold_tranche(&mut session, &tranche, top_up_at).unwrap();
+        assert_eq!(session.amount, original.remaining_amount() + tranche.amount);
+        assert_eq!(session.amount_withdrawn, 0);
+
+        // Never ahead of both schedules released separately, and the tranche tokens are not
+        // all claimable before the tranche start plus the vesting period
+        let tranche_end = tranche.end_date().unwrap();
+        let mut now = top_up_at;
+        while now < tranche_end {
+            let separately = amount_to_release(&original, now).unwrap() + released(&tranche, now);
+            let combined = amount_to_release(&session, now).unwrap();
+            assert!(combined <= separately, "released early at {}", now);
+            assert!(combined < session.amount);
+            now += 3_600;
+        }
+        assert!(amount_to_release(&session, tranche_end - 1).unwrap() < session.amount);
+        assert_eq!(amount_to_release(&session, tranche_end), Ok(session.amount));
+    }
+
+    #[test]
+    fn keeps_released_tokens_claimable_when_folding() {
+        let now = START + 6_000;
+        let mut session = schedule(VESTING_PERIOD_MINUTES * 10);
+        let ended = Schedule {
+            amount: 3_000,
+            start_date: START - VESTING_PERIOD_SECONDS,
+            ..Default::default()
+        };
+        let claimable_before =
+            amount_to_release(&session, now).unwrap() + amount_to_release(&ended, now).unwrap();
+        assert_eq!(claimable_before, 4_000);
+
+        // Released tokens of both schedules, ended ones included, are unlocked
+        fold_tranche(&mut session, &ended, now).unwrap();
+        assert_eq!(session.amount, VESTING_PERIOD_MINUTES * 10 - 1_000);
+        assert_eq!(session.amount_unlocked, 4_000);
+        assert_eq!(session.start_date, START);
+        assert_eq!(amount_to_release(&session, now), Ok(claimable_before));
+        assert_eq!(amount_to_release(&session, now + 60), Ok(4_009));
+
+        // Unlocked tokens are withdrawn first, the locked ones keep releasing from now
+        record_withdrawal(&mut session, 4_000, now).unwrap();
+        assert_eq!(session.amount_unlocked, 0);
+        assert_eq!(session.amount_withdrawn, 0);
+        assert_eq!(amount_to_release(&session, now + 60), Ok(9));
+        assert_eq!(
+            amount_to_release(&session, session.end_date().unwrap()),
+            Ok(session.amount)
+        );
+
+        // Fully withdrawn schedules fold without changing the schedule of the other one
+        let mut withdrawn = ended;
+        record_withdrawal(&mut withdrawn, 3_000, now).unwrap();
+        let mut folded = session;
+        fold_tranche(&mut folded, &withdrawn, now).unwrap();
+        assert_eq!(folded, session);
+    }
+
+    #[test]
+    fn splits_amounts_proportionally() {
+        let mut session = Schedule {
+            amount_withdrawn: 400,
+            ..schedule(1_000)
+        };
+        assert_eq!(split_amounts(&mut session, 300), Ok((500, 200)));
+        assert_eq!(session.amount, 500);
+        assert_eq!(session.amount_withdrawn, 200);
+
+        assert_eq!(
+            split_amounts(&mut session, 300),
+            Err(MathError::InvalidSplitAmount)
+        );
+    }
+}
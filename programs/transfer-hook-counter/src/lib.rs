@@ -0,0 +1,103 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount};
+use spl_tlv_account_resolution::{
+    account::ExtraAccountMeta, seeds::Seed, state::ExtraAccountMetaList,
+};
+use spl_transfer_hook_interface::instruction::ExecuteInstruction;
+
+declare_id!("5fbRrkwjPYsuHyXh3PL2patYut1tGUdPfC4hDxYYnecs");
+
+/// Transfer hook counting the transfers of a mint in an extra account, so the tests can check the
+/// vesting contract forwards the extra accounts required by hook enabled valued tokens
+#[program]
+pub mod transfer_hook_counter {
+    use super::*;
+
+    /// Registers the counter of the mint as the extra account required on every transfer
+    pub fn initialize_extra_account_meta_list(
+        ctx: Context<InitializeExtraAccountMetaList>,
+    ) -> Result<()> {
+        ExtraAccountMetaList::init::<ExecuteInstruction>(
+            &mut ctx.accounts.extra_account_meta_list.try_borrow_mut_data()?,
+            &extra_account_metas()?,
+        )?;
+        Ok(())
+    }
+
+    #[interface(spl_transfer_hook_interface::execute)]
+    pub fn transfer_hook(ctx: Context<TransferHook>, amount: u64) -> Result<()> {
+        let counter_account = &mut ctx.accounts.counter_account;
+        counter_account.transfers += 1;
+        counter_account.amount_transferred += amount;
+        Ok(())
+    }
+}
+
+/// Counter account of the mint, derived from the mint at index 1 of the execute instruction
+fn extra_account_metas() -> Result<Vec<ExtraAccountMeta>> {
+    Ok(vec![ExtraAccountMeta::new_with_seeds(
+        &[
+            Seed::Literal {
+                bytes: b"counter".to_vec(),
+            },
+            Seed::AccountKey { index: 1 },
+        ],
+        false,
+        true,
+    )?])
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct CounterAccount {
+    pub transfers: u64,
+    pub amount_transferred: u64,
+}
+
+#[derive(Accounts)]
+pub struct InitializeExtraAccountMetaList<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: Extra account metas of the mint, written by the instruction
+    #[account(
+        init,
+        payer = payer,
+        seeds = [b"extra-account-metas", mint.key().as_ref()],
+        bump,
+        space = ExtraAccountMetaList::size_of(extra_account_metas()?.len())?
+    )]
+    pub extra_account_meta_list: UncheckedAccount<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init,
+        payer = payer,
+        seeds = [b"counter", mint.key().as_ref()],
+        bump,
+        space = 8 + CounterAccount::INIT_SPACE
+    )]
+    pub counter_account: Account<'info, CounterAccount>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// Accounts of the execute instruction, in the order set by the transfer hook interface
+#[derive(Accounts)]
+pub struct TransferHook<'info> {
+    #[account(token::mint = mint)]
+    pub source_token: InterfaceAccount<'info, TokenAccount>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(token::mint = mint)]
+    pub destination_token: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: Owner or delegate of the source account, possibly a PDA
+    pub owner: UncheckedAccount<'info>,
+
+    /// CHECK: Extra account metas of the mint
+    #[account(seeds = [b"extra-account-metas", mint.key().as_ref()], bump)]
+    pub extra_account_meta_list: UncheckedAccount<'info>,
+
+    #[account(mut, seeds = [b"counter", mint.key().as_ref()], bump)]
+    pub counter_account: Account<'info, CounterAccount>,
+}
@@ -1,4 +1,5 @@
 use anchor_lang::{error_code, prelude::ProgramError};
+use vesting_math::MathError;
 
 /// Custom error codes for the contract
 #[error_code]
@@ -23,6 +24,86 @@ pub enum VestingErrorCode {
     InvalidMeta,
     #[msg("Received valued token implements a token extension not supported by the vesting app")]
     UnsupportedTokenExtension,
+    #[msg("Vesting period of the session already ended")]
+    VestingPeriodEnded,
+    #[msg("Invalid list of vesting sessions to merge")]
+    InvalidSessionsToMerge,
+    #[msg("Invalid amount to split from the vesting session")]
+    InvalidSplitAmount,
+    #[msg("Vesting sessions of this vault can not be transferred to other users")]
+    SessionsNotTransferable,
+    #[msg("Vesting session can not be transferred to its current owner")]
+    InvalidTransferRecipient,
+    #[msg("Vesting session is tokenized and can only be operated by its position token holder")]
+    SessionIsTokenized,
+    #[msg("Invalid vesting position token")]
+    InvalidPositionToken,
+    #[msg("Vesting session still has tokens to release")]
+    SessionNotSettled,
+    #[msg("Vesting sessions collection still has open sessions")]
+    SessionsCollectionNotEmpty,
+    #[msg("Vault still has open vesting sessions, settled sessions must be closed first")]
+    VaultHasActiveSessions,
+    #[msg("Escrow tokens are still held outside of the vault")]
+    EscrowSupplyOutstanding,
+    #[msg("Transfer hook program of the valued token is not trusted by the vault")]
+    UntrustedTransferHook,
+    #[msg("Too many trusted transfer hook programs")]
+    TooManyTrustedHookPrograms,
+    #[msg("Unknown token extension type")]
+    InvalidTokenExtension,
+    #[msg("Too many allowed token extensions")]
+    TooManyAllowedTokenExtensions,
+    #[msg("Valued token extensions changed since the vault was created")]
+    ValuedMintExtensionsChanged,
+    #[msg("Valued token freeze authority changed since the vault was created")]
+    ValuedMintFreezeAuthorityChanged,
+    #[msg("Valued token permanent delegate changed since the vault was created")]
+    ValuedMintPermanentDelegateChanged,
+    #[msg("Valued token transfer hook program changed since the vault was created")]
+    ValuedMintTransferHookChanged,
+    #[msg("Vault valued token balance is below the balance owed to its escrow holders")]
+    VaultDegraded,
+    #[msg("Escrow token account is frozen")]
+    EscrowAccountFrozen,
+    #[msg("Vault exchanged amount cap reached")]
+    SupplyCapReached,
+    #[msg("Metadata key length is out of bounds")]
+    InvalidMetadataKeyLength,
+    #[msg("Metadata value is too long")]
+    MetadataValueTooLong,
+    #[msg("Rent refund destination does not match the configured one")]
+    InvalidRentRefundDestination,
+    #[msg("Current escrow metadata authority is missing or does not match")]
+    InvalidMetadataAuthority,
+    #[msg("Escrow metadata update authority was handed off from the vault")]
+    EscrowMetadataAuthorityDelegated,
+    #[msg("Metadata batch must contain between 1 and 10 updates")]
+    InvalidMetadataBatchSize,
+    #[msg("Metadata rules are invalid")]
+    InvalidMetadataRules,
+    #[msg("Metadata name is longer than allowed")]
+    MetadataNameTooLong,
+    #[msg("Metadata symbol is longer than allowed")]
+    MetadataSymbolTooLong,
+    #[msg("Metadata URI is longer than allowed")]
+    MetadataUriTooLong,
+    #[msg("Metadata URI scheme is not allowed")]
+    UnsupportedMetadataUriScheme,
+    #[msg("Metadata symbol must only contain printable ASCII characters")]
+    NonAsciiMetadataSymbol,
+    #[msg("Metadata symbol is missing the required escrow prefix")]
+    MissingEscrowSymbolPrefix,
+    #[msg("Metadata symbol is missing the required escrow suffix")]
+    MissingEscrowSymbolSuffix,
+    #[msg("Position token of the vesting session must be burned first")]
+    PositionTokenNotBurned,
+    #[msg("Vault is still open, its sessions collections must be closed through the vault")]
+    VaultNotClosed,
+    #[msg("Account already uses the current layout")]
+    AccountAlreadyMigrated,
+    #[msg("Account layout is unknown and can not be migrated")]
+    UnknownAccountLayout,
 }
 
 // Implementation to convert ErrorCode to ProgramError
@@ -31,3 +112,13 @@ impl From<VestingErrorCode> for ProgramError {
         ProgramError::Custom(e as u32)
     }
 }
+
+impl From<MathError> for VestingErrorCode {
+    fn from(e: MathError) -> VestingErrorCode {
+        match e {
+            MathError::ArithmeticOverflow => VestingErrorCode::ArithmeticOverflow,
+            MathError::DivisionByZero => VestingErrorCode::DivisionByZero,
+            MathError::InvalidSplitAmount => VestingErrorCode::InvalidSplitAmount,
+        }
+    }
+}
@@ -1,66 +1,454 @@
 use anchor_lang::prelude::*;
 
+use crate::vesting_accounts::{ChangeEscrowMetadataParams, MetadataRules, MintConfigSnapshot};
+
+/// Emits an event. With the `cpi-events` feature the event is sent through a self CPI and can be
+/// recovered from the inner instructions, otherwise it is written to the program logs
+#[macro_export]
+macro_rules! emit_event {
+    ($ctx:ident, $event:expr) => {{
+        let event = $event;
+        #[cfg(feature = "cpi-events")]
+        {
+            let ctx = &$ctx;
+            anchor_lang::emit_cpi!(event);
+        }
+        #[cfg(not(feature = "cpi-events"))]
+        anchor_lang::emit!(event);
+    }};
+}
+
+/// Version of the event layouts below, carried as the first field of every event so decoders
+/// can pick the layout before reading anything else.
+///
+/// Event layouts are append-only: fields are never removed, reordered or retyped, new fields are
+/// only added at the end of an event and this version is bumped in the same change. Fields that
+/// are no longer meaningful stay in place and their doc starts with `Deprecated:`, which is
+/// carried to the IDL. A decoder built for an older version reads the prefix it knows and ignores
+/// the trailing bytes. The layouts are pinned by `tests/fixtures/event-schema.json`.
+#[constant]
+pub const EVENT_SCHEMA_VERSION: u8 = 1;
+
 #[event]
 pub struct EscrowCreatedEvent {
+    pub version: u8,
     pub creator: Pubkey,
     pub valued_token_mint: Pubkey,
     pub escrow_token_mint: Pubkey,
     pub vault_account: Pubkey,
     pub app_id: String,
+    pub actor: Pubkey,
+    pub time: u64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct BackendAccountSetEvent {
+    pub version: u8,
+    pub actor: Pubkey,
+    pub previous_backend_account: Pubkey,
+    pub backend_account: Pubkey,
+    pub change_authority: Option<Pubkey>,
+    pub time: u64,
+    pub slot: u64,
 }
 
 #[event]
 pub struct VaultAccountInitializedEvent {
+    pub version: u8,
     pub vault_account: Pubkey,
     pub valued_vault_token_account: Pubkey,
     pub escrow_vault_token_account: Pubkey,
+    pub actor: Pubkey,
+    pub valued_token_mint: Pubkey,
+    pub escrow_token_mint: Pubkey,
+    pub time: u64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct VaultConfigUpdatedEvent {
+    pub version: u8,
+    pub vault_account: Pubkey,
+    pub sessions_transferable: bool,
+    pub trusted_hook_programs: Vec<Pubkey>,
+    pub max_exchanged_amount: Option<u64>,
+    pub actor: Pubkey,
+    pub valued_token_mint: Pubkey,
+    pub escrow_token_mint: Pubkey,
+    pub time: u64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct AllowedTokenExtensionsUpdatedEvent {
+    pub version: u8,
+    /// None when updating the backend configuration
+    pub vault_account: Option<Pubkey>,
+    pub allowed_token_extensions: Option<Vec<u16>>,
+    pub actor: Pubkey,
+    pub time: u64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct AccountMigratedEvent {
+    pub version: u8,
+    pub account: Pubkey,
+    pub layout_version_before: u8,
+    pub layout_version: u8,
+    pub actor: Pubkey,
+    pub time: u64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct ValuedMintConfigRefreshedEvent {
+    pub version: u8,
+    pub vault_account: Pubkey,
+    pub previous_valued_mint_config: MintConfigSnapshot,
+    pub valued_mint_config: MintConfigSnapshot,
+    pub actor: Pubkey,
+    pub time: u64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct MetadataRulesUpdatedEvent {
+    pub version: u8,
+    /// None when removing the rules
+    pub metadata_rules: Option<MetadataRules>,
+    pub actor: Pubkey,
+    pub time: u64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct RentRefundDestinationUpdatedEvent {
+    pub version: u8,
+    pub rent_refund_destination: Option<Pubkey>,
+    pub actor: Pubkey,
+    pub time: u64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct VaultShortfallEvent {
+    pub version: u8,
+    pub vault_account: Pubkey,
+    pub expected_balance: u64,
+    pub actual_balance: u64,
+    pub shortfall: u64,
+    pub time: u64,
+    pub actor: Pubkey,
+    pub valued_token_mint: Pubkey,
+    pub escrow_token_mint: Pubkey,
+    pub slot: u64,
+}
+
+#[event]
+pub struct VaultClosedEvent {
+    pub version: u8,
+    pub vault_account: Pubkey,
+    pub creator: Pubkey,
+    pub valued_amount_swept: u64,
+    pub escrow_amount_burned: u64,
+    pub escrow_mint_closed: bool,
+    pub actor: Pubkey,
+    pub valued_token_mint: Pubkey,
+    pub escrow_token_mint: Pubkey,
+    pub time: u64,
+    pub slot: u64,
 }
 
 #[event]
 pub struct EscrowMetadataChangedEvent {
+    pub version: u8,
     pub escrow_token_mint: Pubkey,
     pub field_updated: String,
     pub value: String,
+    pub lamports_refunded: u64,
+    pub actor: Pubkey,
+    pub vault_account: Pubkey,
+    pub valued_token_mint: Pubkey,
+    pub time: u64,
+    pub slot: u64,
 }
 
 #[event]
 pub struct ExchangedEvent {
+    pub version: u8,
     pub vault_account: Pubkey,
     pub amount: u64,
+    pub user: Pubkey,
+    pub actor: Pubkey,
+    pub valued_token_mint: Pubkey,
+    pub escrow_token_mint: Pubkey,
+    pub valued_vault_balance_before: u64,
+    pub valued_vault_balance_after: u64,
+    pub time: u64,
+    pub slot: u64,
 }
 
 #[event]
 pub struct CreatedVestingSessionEvent {
+    pub version: u8,
     pub vault_account: Pubkey,
     pub vesting_session: Pubkey,
     pub user: Pubkey,
     pub amount: u64,
+    pub actor: Pubkey,
+    pub valued_token_mint: Pubkey,
+    pub escrow_token_mint: Pubkey,
+    pub session_id: u64,
+    pub start_date: u64,
+    pub user_escrow_balance_before: u64,
+    pub user_escrow_balance_after: u64,
+    pub time: u64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct VestingPositionMintedEvent {
+    pub version: u8,
+    pub vault_account: Pubkey,
+    pub vesting_session: Pubkey,
+    pub position_mint: Pubkey,
+    pub holder: Pubkey,
+    pub actor: Pubkey,
+    pub valued_token_mint: Pubkey,
+    pub escrow_token_mint: Pubkey,
+    pub session_id: u64,
+    pub time: u64,
+    pub slot: u64,
 }
 
 #[event]
 pub struct SessionWithdrawnEvent {
+    pub version: u8,
     pub vault_account: Pubkey,
     pub vesting_session: Pubkey,
     pub user: Pubkey,
     pub amount: u64,
     pub time: u64,
+    pub actor: Pubkey,
+    pub valued_token_mint: Pubkey,
+    pub escrow_token_mint: Pubkey,
+    pub session_id: u64,
+    pub amount_withdrawn_before: u64,
+    pub amount_withdrawn_after: u64,
+    pub slot: u64,
 }
 
 #[event]
 pub struct SessionCancelEvent {
+    pub version: u8,
     pub vault_account: Pubkey,
     pub vesting_session: Pubkey,
     pub user: Pubkey,
     pub valued_amount: u64,
     pub escrow_amount: u64,
     pub time: u64,
+    pub actor: Pubkey,
+    pub valued_token_mint: Pubkey,
+    pub escrow_token_mint: Pubkey,
+    pub session_id: u64,
+    pub amount_withdrawn_before: u64,
+    pub amount_withdrawn_after: u64,
+    pub slot: u64,
 }
 
 #[event]
 pub struct SessionExitEvent {
+    pub version: u8,
+    pub vault_account: Pubkey,
+    pub vesting_session: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub time: u64,
+    pub actor: Pubkey,
+    pub valued_token_mint: Pubkey,
+    pub escrow_token_mint: Pubkey,
+    pub session_id: u64,
+    pub amount_withdrawn_before: u64,
+    pub amount_withdrawn_after: u64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct SessionToppedUpEvent {
+    pub version: u8,
+    pub vault_account: Pubkey,
+    pub vesting_session: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub total_amount: u64,
+    pub start_date: u64,
+    pub time: u64,
+    pub actor: Pubkey,
+    pub valued_token_mint: Pubkey,
+    pub escrow_token_mint: Pubkey,
+    pub session_id: u64,
+    pub total_amount_before: u64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct SessionsMergedEvent {
+    pub version: u8,
     pub vault_account: Pubkey,
     pub vesting_session: Pubkey,
     pub user: Pubkey,
+    pub merged_sessions: Vec<Pubkey>,
+    pub total_amount: u64,
+    /// Deprecated: always 0, merging re-bases the session and keeps the released tokens in
+    /// `amount_unlocked`
+    pub amount_withdrawn: u64,
+    pub start_date: u64,
+    pub time: u64,
+    pub actor: Pubkey,
+    pub valued_token_mint: Pubkey,
+    pub escrow_token_mint: Pubkey,
+    pub session_id: u64,
+    pub total_amount_before: u64,
+    pub slot: u64,
+    /// Released tokens of the merged sessions which stay claimable at once
+    pub amount_unlocked: u64,
+}
+
+#[event]
+pub struct SessionSplitEvent {
+    pub version: u8,
+    pub vault_account: Pubkey,
+    pub vesting_session: Pubkey,
+    pub new_vesting_session: Pubkey,
+    pub user: Pubkey,
+    pub recipient: Pubkey,
+    pub amount: u64,
+    pub split_amount: u64,
+    pub split_amount_withdrawn: u64,
+    pub time: u64,
+    pub actor: Pubkey,
+    pub valued_token_mint: Pubkey,
+    pub escrow_token_mint: Pubkey,
+    pub session_id: u64,
+    pub new_session_id: u64,
+    pub amount_before: u64,
+    pub amount_after: u64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct SessionTransferredEvent {
+    pub version: u8,
+    pub vault_account: Pubkey,
+    pub vesting_session: Pubkey,
+    pub new_vesting_session: Pubkey,
+    pub user: Pubkey,
+    pub new_user: Pubkey,
+    pub amount: u64,
+    pub amount_withdrawn: u64,
+    pub time: u64,
+    pub actor: Pubkey,
+    pub valued_token_mint: Pubkey,
+    pub escrow_token_mint: Pubkey,
+    pub session_id: u64,
+    pub new_session_id: u64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct SessionClosedEvent {
+    pub version: u8,
+    pub vault_account: Pubkey,
+    pub vesting_session: Pubkey,
+    pub user: Pubkey,
+    pub time: u64,
+    pub actor: Pubkey,
+    pub valued_token_mint: Pubkey,
+    pub escrow_token_mint: Pubkey,
+    pub session_id: u64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct SessionsCollectionClosedEvent {
+    pub version: u8,
+    pub vault_account: Pubkey,
+    pub vesting_sessions_account: Pubkey,
+    pub user: Pubkey,
+    pub time: u64,
+    pub actor: Pubkey,
+    pub valued_token_mint: Pubkey,
+    pub escrow_token_mint: Pubkey,
+    pub slot: u64,
+}
+
+#[event]
+pub struct EscrowAccountFrozenEvent {
+    pub version: u8,
+    pub vault_account: Pubkey,
+    pub escrow_token_account: Pubkey,
+    pub owner: Pubkey,
+    pub actor: Pubkey,
+    pub reason: u16,
+    pub time: u64,
+    pub valued_token_mint: Pubkey,
+    pub escrow_token_mint: Pubkey,
     pub amount: u64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct EscrowAccountThawedEvent {
+    pub version: u8,
+    pub vault_account: Pubkey,
+    pub escrow_token_account: Pubkey,
+    pub owner: Pubkey,
+    pub actor: Pubkey,
+    pub reason: u16,
+    pub time: u64,
+    pub valued_token_mint: Pubkey,
+    pub escrow_token_mint: Pubkey,
+    pub amount: u64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct EscrowMetadataBatchChangedEvent {
+    pub version: u8,
+    pub escrow_token_mint: Pubkey,
+    pub updates: Vec<ChangeEscrowMetadataParams>,
+    pub lamports_refunded: u64,
+    pub actor: Pubkey,
+    pub vault_account: Pubkey,
+    pub valued_token_mint: Pubkey,
+    pub time: u64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct EscrowMetadataAuthorityChangedEvent {
+    pub version: u8,
+    pub vault_account: Pubkey,
+    pub escrow_token_mint: Pubkey,
+    pub update_authority: Pubkey,
+    pub metadata_pointer_authority: Pubkey,
+    pub actor: Pubkey,
+    pub valued_token_mint: Pubkey,
+    pub time: u64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct EscrowMetadataKeyRemovedEvent {
+    pub version: u8,
+    pub escrow_token_mint: Pubkey,
+    pub key: String,
+    pub lamports_refunded: u64,
+    pub actor: Pubkey,
+    pub vault_account: Pubkey,
+    pub valued_token_mint: Pubkey,
     pub time: u64,
+    pub slot: u64,
 }
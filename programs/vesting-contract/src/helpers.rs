@@ -1,12 +1,35 @@
-use crate::{VaultAccount, VestingErrorCode, VestingSession};
+use crate::{
+    BackendAccountData, ChangeEscrowMetadataParams, MetadataRules, SessionIdsAccount, VaultAccount,
+    VestingErrorCode, VestingSession, VestingSessionsAccount,
+};
 /// Helper functions for the contract
 use anchor_lang::{
     prelude::*,
-    solana_program::{program::invoke, system_instruction::transfer},
+    solana_program::{
+        program::{invoke, invoke_signed},
+        system_instruction::transfer,
+    },
+    system_program::{allocate, assign, create_account, Allocate, Assign, CreateAccount},
+};
+use anchor_spl::token_2022::spl_token_2022::{
+    self,
+    extension::{
+        mint_close_authority::MintCloseAuthority,
+        transfer_fee::{TransferFeeAmount, TransferFeeConfig},
+        BaseStateWithExtensions, ExtensionType, StateWithExtensions,
+    },
+    onchain::invoke_transfer_checked,
 };
 use anchor_spl::token_interface::{
-    mint_to, transfer_checked, Mint, MintTo, Token2022, TokenAccount, TransferChecked,
+    burn, harvest_withheld_tokens_to_mint, initialize_mint2, metadata_pointer_initialize,
+    mint_close_authority_initialize, mint_to, non_transferable_mint_initialize, transfer_checked,
+    transfer_checked_with_fee, Burn, HarvestWithheldTokensToMint, InitializeMint2,
+    MetadataPointerInitialize, Mint, MintCloseAuthorityInitialize, MintTo,
+    NonTransferableMintInitialize, Token2022, TokenAccount, TransferChecked,
+    TransferCheckedWithFee,
 };
+use spl_token_metadata_interface::state::Field;
+use vesting_math::{Schedule, VaultLedger};
 
 ///  update the account's lamports to the minimum balance required by the rent sysvar
 pub fn update_account_lamports_to_minimum_balance<'info>(
@@ -26,25 +49,417 @@ pub fn update_account_lamports_to_minimum_balance<'info>(
     Ok(())
 }
 
-pub fn transfer_escrow_from_vault<'info>(
+/// Moves the lamports of a Token-2022 account above its rent exempt minimum to `destination`.
+/// Returns the amount of lamports refunded
+pub fn refund_excess_lamports<'info>(
+    token_program: AccountInfo<'info>,
+    account: AccountInfo<'info>,
+    destination: AccountInfo<'info>,
+    authority: AccountInfo<'info>,
+    pda_signer: &[&[&[u8]]],
+) -> Result<u64> {
+    let account_required_min_balance = Rent::get()?.minimum_balance(account.data_len());
+    let excess_lamports = account
+        .get_lamports()
+        .saturating_sub(account_required_min_balance);
+    if excess_lamports == 0 {
+        return Ok(0);
+    }
+
+    invoke_signed(
+        &spl_token_2022::instruction::withdraw_excess_lamports(
+            token_program.key,
+            account.key,
+            destination.key,
+            authority.key,
+            &[],
+        )?,
+        &[account, destination, authority, token_program],
+        pda_signer,
+    )?;
+
+    Ok(excess_lamports)
+}
+
+/// Rebalances the lamports of a Token-2022 account to the minimum balance required by the rent
+/// sysvar in both directions: tops it up from `payer` when short and moves the surplus to
+/// `refund_destination` when its data shrank. Returns the amount of lamports refunded
+pub fn rebalance_account_lamports<'info>(
+    token_program: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+    account: AccountInfo<'info>,
+    payer: AccountInfo<'info>,
+    refund_destination: AccountInfo<'info>,
+    authority: AccountInfo<'info>,
+    pda_signer: &[&[&[u8]]],
+) -> Result<u64> {
+    update_account_lamports_to_minimum_balance(account.clone(), payer, system_program)?;
+    refund_excess_lamports(
+        token_program,
+        account,
+        refund_destination,
+        authority,
+        pda_signer,
+    )
+}
+
+/// Resolves the account receiving rent surplus: the backend configured destination when set,
+/// otherwise the payer
+pub fn rent_refund_destination<'info>(
+    backend_data: &BackendAccountData,
+    configured_destination: Option<&UncheckedAccount<'info>>,
+    payer: AccountInfo<'info>,
+) -> Result<AccountInfo<'info>> {
+    match backend_data.rent_refund_destination {
+        Some(_) => Ok(configured_destination
+            .ok_or(VestingErrorCode::InvalidRentRefundDestination)?
+            .to_account_info()),
+        None => Ok(payer),
+    }
+}
+
+/// Maximum length of the key of an additional escrow metadata field
+pub const MAX_METADATA_KEY_LENGTH: usize = 32;
+
+/// Maximum length of an escrow metadata value
+pub const MAX_METADATA_VALUE_LENGTH: usize = 200;
+
+/// Validates an escrow metadata update against the metadata rules, if configured, and returns
+/// the token metadata field it targets. Keys other than `name`, `symbol` and `uri` are
+/// additional metadata fields
+pub fn escrow_metadata_field(
+    rules: Option<&MetadataRules>,
+    update: &ChangeEscrowMetadataParams,
+) -> Result<Field> {
+    require!(!update.value.is_empty(), VestingErrorCode::InvalidMeta);
+    require!(
+        update.value.len() <= MAX_METADATA_VALUE_LENGTH,
+        VestingErrorCode::MetadataValueTooLong
+    );
+
+    let field = match update.param_key.as_str() {
+        "name" => {
+            if let Some(rules) = rules {
+                metadata_validations::validate_name(rules, &update.value)?;
+            }
+            Field::Name
+        }
+        "symbol" => {
+            if let Some(rules) = rules {
+                metadata_validations::validate_symbol(rules, &update.value)?;
+            }
+            Field::Symbol
+        }
+        "uri" => {
+            if let Some(rules) = rules {
+                metadata_validations::validate_uri(rules, &update.value)?;
+            }
+            Field::Uri
+        }
+        key => {
+            require!(
+                !key.is_empty() && key.len() <= MAX_METADATA_KEY_LENGTH,
+                VestingErrorCode::InvalidMetadataKeyLength
+            );
+            Field::Key(key.to_string())
+        }
+    };
+    Ok(field)
+}
+
+/// Checks whether an account is a program admin: the program upgrade authority or the backend
+/// change authority
+pub fn is_program_admin(
+    program_data: &ProgramData,
+    backend_data: &BackendAccountData,
+    account: &Pubkey,
+) -> bool {
+    let is_program_authority = match &program_data.upgrade_authority_address {
+        Some(authority) => account == authority,
+        None => false,
+    };
+    let is_backend_change_authority = match &backend_data.change_authority {
+        Some(authority) => account == authority,
+        None => false,
+    };
+
+    is_program_authority || is_backend_change_authority
+}
+
+/// Returns the close authority of a Token-2022 mint, if it implements the `MintCloseAuthority` extension
+pub fn mint_close_authority(mint_acc_info: &AccountInfo) -> Result<Option<Pubkey>> {
+    let mint_data = mint_acc_info.data.borrow();
+    let mint = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+
+    match mint.get_extension::<MintCloseAuthority>() {
+        Ok(extension) => Ok(Option::<Pubkey>::from(extension.close_authority)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Returns the fee charged by the Token-2022 `TransferFeeConfig` extension of a mint when
+/// transferring `amount` tokens in the current epoch, if the mint implements it
+pub fn calculate_transfer_fee(mint_acc_info: &AccountInfo, amount: u64) -> Result<Option<u64>> {
+    if mint_acc_info.owner != &spl_token_2022::id() {
+        return Ok(None);
+    }
+
+    let mint_data = mint_acc_info.data.borrow();
+    let mint = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+
+    match mint.get_extension::<TransferFeeConfig>() {
+        Ok(transfer_fee_config) => {
+            let fee = transfer_fee_config
+                .calculate_epoch_fee(Clock::get()?.epoch, amount)
+                .ok_or(VestingErrorCode::ArithmeticOverflow)?;
+            Ok(Some(fee))
+        }
+        Err(_) => Ok(None),
+    }
+}
+
+/// Moves the transfer fees withheld in a Token-2022 token account to its mint, since token
+/// accounts still withholding fees cannot be closed. Returns the amount harvested
+pub fn harvest_withheld_fees<'info>(
+    token_program: AccountInfo<'info>,
+    mint: AccountInfo<'info>,
+    token_account: AccountInfo<'info>,
+) -> Result<u64> {
+    if token_account.owner != &spl_token_2022::id() {
+        return Ok(0);
+    }
+
+    let withheld_amount = {
+        let account_data = token_account.data.borrow();
+        let account = StateWithExtensions::<spl_token_2022::state::Account>::unpack(&account_data)?;
+        match account.get_extension::<TransferFeeAmount>() {
+            Ok(transfer_fee_amount) => u64::from(transfer_fee_amount.withheld_amount),
+            Err(_) => 0,
+        }
+    };
+
+    // Harvesting is permissionless, no signature of the account owner is needed
+    if withheld_amount > 0 {
+        harvest_withheld_tokens_to_mint(
+            CpiContext::new(
+                token_program.clone(),
+                HarvestWithheldTokensToMint {
+                    token_program_id: token_program,
+                    mint,
+                },
+            ),
+            vec![token_account],
+        )?;
+    }
+
+    Ok(withheld_amount)
+}
+
+/// Creates an escrow mint implementing the `NonTransferable` extension, along with the same
+/// metadata pointer and close authority extensions set up for regular escrow mints
+pub fn create_non_transferable_escrow_mint<'info>(
     token_program: &Program<'info, Token2022>,
+    system_program: &Program<'info, System>,
+    payer: AccountInfo<'info>,
+    escrow_token_mint: AccountInfo<'info>,
     vault_account: &Account<'info, VaultAccount>,
-    escrow_vault_token_account: &InterfaceAccount<'info, TokenAccount>,
-    user_escrow_token_account: &InterfaceAccount<'info, TokenAccount>,
-    valued_token_mint: &InterfaceAccount<'info, Mint>,
-    escrow_token_mint: &InterfaceAccount<'info, Mint>,
+    escrow_mint_signer: &[&[&[u8]]],
+    decimals: u8,
+) -> Result<()> {
+    let mint_space = ExtensionType::try_calculate_account_len::<spl_token_2022::state::Mint>(&[
+        ExtensionType::NonTransferable,
+        ExtensionType::MetadataPointer,
+        ExtensionType::MintCloseAuthority,
+    ])?;
+    let mint_min_balance = Rent::get()?.minimum_balance(mint_space);
+    let mint_lamports = escrow_token_mint.get_lamports();
+    if mint_lamports == 0 {
+        create_account(
+            CpiContext::new_with_signer(
+                system_program.to_account_info(),
+                CreateAccount {
+                    from: payer,
+                    to: escrow_token_mint.clone(),
+                },
+                escrow_mint_signer,
+            ),
+            mint_min_balance,
+            mint_space as u64,
+            token_program.key,
+        )?;
+    } else {
+        // The mint address is derived from public seeds, lamports sent to it beforehand would make
+        // `create_account` fail, so the account is topped up, allocated and assigned instead
+        if mint_lamports < mint_min_balance {
+            invoke(
+                &transfer(
+                    payer.key,
+                    escrow_token_mint.key,
+                    mint_min_balance - mint_lamports,
+                ),
+                &[
+                    payer,
+                    escrow_token_mint.clone(),
+                    system_program.to_account_info(),
+                ],
+            )?;
+        }
+        allocate(
+            CpiContext::new_with_signer(
+                system_program.to_account_info(),
+                Allocate {
+                    account_to_allocate: escrow_token_mint.clone(),
+                },
+                escrow_mint_signer,
+            ),
+            mint_space as u64,
+        )?;
+        assign(
+            CpiContext::new_with_signer(
+                system_program.to_account_info(),
+                Assign {
+                    account_to_assign: escrow_token_mint.clone(),
+                },
+                escrow_mint_signer,
+            ),
+            token_program.key,
+        )?;
+    }
+
+    // Extensions must be initialized before the mint itself
+    non_transferable_mint_initialize(CpiContext::new(
+        token_program.to_account_info(),
+        NonTransferableMintInitialize {
+            token_program_id: token_program.to_account_info(),
+            mint: escrow_token_mint.clone(),
+        },
+    ))?;
+    metadata_pointer_initialize(
+        CpiContext::new(
+            token_program.to_account_info(),
+            MetadataPointerInitialize {
+                token_program_id: token_program.to_account_info(),
+                mint: escrow_token_mint.clone(),
+            },
+        ),
+        Some(vault_account.key()),
+        Some(escrow_token_mint.key()),
+    )?;
+    mint_close_authority_initialize(
+        CpiContext::new(
+            token_program.to_account_info(),
+            MintCloseAuthorityInitialize {
+                token_program_id: token_program.to_account_info(),
+                mint: escrow_token_mint.clone(),
+            },
+        ),
+        Some(&vault_account.key()),
+    )?;
+    initialize_mint2(
+        CpiContext::new(
+            token_program.to_account_info(),
+            InitializeMint2 {
+                mint: escrow_token_mint,
+            },
+        ),
+        decimals,
+        &vault_account.key(),
+        Some(&vault_account.key()),
+    )?;
+
+    Ok(())
+}
+
+/// Accounts moving escrow tokens between a user and the vault
+pub struct EscrowTransferAccounts<'a, 'info> {
+    pub token_program: &'a Program<'info, Token2022>,
+    pub vault_account: &'a Account<'info, VaultAccount>,
+    pub escrow_vault_token_account: &'a InterfaceAccount<'info, TokenAccount>,
+    pub user_escrow_token_account: &'a InterfaceAccount<'info, TokenAccount>,
+    pub escrow_token_mint: &'a InterfaceAccount<'info, Mint>,
+}
+
+/// Moves escrow tokens from a user back to the vault. Non transferable escrow tokens are burned
+/// instead, the vault mints them again when returning escrow to users
+pub fn transfer_escrow_to_vault<'info>(
+    accounts: EscrowTransferAccounts<'_, 'info>,
+    user: AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    let EscrowTransferAccounts {
+        token_program,
+        vault_account,
+        escrow_vault_token_account,
+        user_escrow_token_account,
+        escrow_token_mint,
+    } = accounts;
+
+    require!(
+        !user_escrow_token_account.is_frozen(),
+        VestingErrorCode::EscrowAccountFrozen
+    );
+
+    if vault_account.escrow_non_transferable {
+        return burn(
+            CpiContext::new(
+                token_program.to_account_info(),
+                Burn {
+                    mint: escrow_token_mint.to_account_info(),
+                    from: user_escrow_token_account.to_account_info(),
+                    authority: user,
+                },
+            ),
+            amount,
+        );
+    }
+
+    transfer_tokens(
+        TokenTransferAccounts {
+            from: user_escrow_token_account,
+            mint: escrow_token_mint,
+            to: escrow_vault_token_account,
+            token_program,
+            authority: user,
+            pda_signer: None,
+            remaining_accounts: &[],
+        },
+        amount,
+    )
+}
+
+/// Returns escrow tokens from the vault to a user, minting what the vault does not hold
+pub fn transfer_escrow_from_vault(
+    accounts: EscrowTransferAccounts,
     vault_bump: u8,
     amount: u64,
 ) -> Result<()> {
+    let EscrowTransferAccounts {
+        token_program,
+        vault_account,
+        escrow_vault_token_account,
+        user_escrow_token_account,
+        escrow_token_mint,
+    } = accounts;
+
     let vault_seed = &[
         b"token_vault".as_ref(),
-        &valued_token_mint.key().to_bytes(),
+        &vault_account.valued_token_mint.to_bytes(),
         &escrow_token_mint.key().to_bytes(),
         &[vault_bump],
     ];
     let vault_signer = &[&vault_seed[..]];
 
-    let vault_escrow_balance = escrow_vault_token_account.amount;
+    require!(
+        !user_escrow_token_account.is_frozen(),
+        VestingErrorCode::EscrowAccountFrozen
+    );
+
+    // Non transferable escrow is never held by the vault, it is always minted back to users
+    let vault_escrow_balance = if vault_account.escrow_non_transferable {
+        0
+    } else {
+        escrow_vault_token_account.amount
+    };
     let amount_to_transfer = if vault_escrow_balance >= amount {
         amount
     } else if vault_escrow_balance > 0 {
@@ -58,13 +473,16 @@ pub fn transfer_escrow_from_vault<'info>(
 
     if amount_to_transfer > 0 {
         transfer_tokens(
-            &escrow_vault_token_account,
-            &escrow_token_mint,
-            &user_escrow_token_account,
-            &token_program,
+            TokenTransferAccounts {
+                from: escrow_vault_token_account,
+                mint: escrow_token_mint,
+                to: user_escrow_token_account,
+                token_program,
+                authority: vault_account.to_account_info(),
+                pda_signer: Some(vault_signer),
+                remaining_accounts: &[],
+            },
             amount_to_transfer,
-            vault_account.to_account_info(),
-            Some(vault_signer),
         )?;
     }
 
@@ -86,22 +504,74 @@ pub fn transfer_escrow_from_vault<'info>(
     Ok(())
 }
 
+/// Accounts of a token transfer, signed by `pda_signer` when the authority is a program account
+pub struct TokenTransferAccounts<'a, 'info> {
+    pub from: &'a InterfaceAccount<'info, TokenAccount>,
+    pub mint: &'a InterfaceAccount<'info, Mint>,
+    pub to: &'a InterfaceAccount<'info, TokenAccount>,
+    pub token_program: &'a AccountInfo<'info>,
+    pub authority: AccountInfo<'info>,
+    pub pda_signer: Option<&'a [&'a [&'a [u8]]; 1]>,
+    pub remaining_accounts: &'a [AccountInfo<'info>], // Extra accounts of transfer hook mints
+}
+
 /// Helper function to transfer tokens
-pub fn transfer_tokens<'info>(
-    from: &InterfaceAccount<'info, TokenAccount>,
-    mint: &InterfaceAccount<'info, Mint>,
-    to: &InterfaceAccount<'info, TokenAccount>,
-    token_program: &AccountInfo<'info>,
-    amount: u64,
-    authority: AccountInfo<'info>,
-    pda_signer: Option<&[&[&[u8]]; 1]>,
-) -> Result<()> {
+pub fn transfer_tokens(accounts: TokenTransferAccounts, amount: u64) -> Result<()> {
+    let TokenTransferAccounts {
+        from,
+        mint,
+        to,
+        token_program,
+        authority,
+        pda_signer,
+        remaining_accounts,
+    } = accounts;
+
+    // Hook enabled mints need the extra accounts required by the hook program forwarded.
+    // The fee of fee bearing mints is still withheld by the token program on this path
+    if token_2022_validations::transfer_hook_program_id(&mint.to_account_info())?.is_some() {
+        invoke_transfer_checked(
+            token_program.key,
+            from.to_account_info(),
+            mint.to_account_info(),
+            to.to_account_info(),
+            authority,
+            remaining_accounts,
+            amount,
+            mint.decimals,
+            pda_signer.map(|signer| &signer[..]).unwrap_or(&[]),
+        )?;
+
+        return Ok(());
+    }
+
+    // Fee bearing mints are transferred asserting the fee withheld from the recipient
+    if let Some(fee) = calculate_transfer_fee(&mint.to_account_info(), amount)? {
+        let cpi_accounts = TransferCheckedWithFee {
+            token_program_id: token_program.to_account_info(),
+            source: from.to_account_info(),
+            mint: mint.to_account_info(),
+            destination: to.to_account_info(),
+            authority,
+        };
+        let cpi_program = token_program.to_account_info();
+
+        let cpi_ctx = match pda_signer {
+            Some(signer) => CpiContext::new_with_signer(cpi_program, cpi_accounts, signer),
+            None => CpiContext::new(cpi_program, cpi_accounts),
+        };
+
+        transfer_checked_with_fee(cpi_ctx, amount, mint.decimals, fee)?;
+
+        return Ok(());
+    }
+
     // Set up the accounts for the transfer
     let cpi_accounts = TransferChecked {
         from: from.to_account_info(),
         mint: mint.to_account_info(),
         to: to.to_account_info(),
-        authority: authority,
+        authority,
     };
     let cpi_program = token_program.to_account_info();
 
@@ -116,77 +586,179 @@ pub fn transfer_tokens<'info>(
     Ok(())
 }
 
+fn apply_session_schedule(vesting_session: &mut VestingSession, schedule: Schedule) {
+    vesting_session.amount = schedule.amount;
+    vesting_session.amount_withdrawn = schedule.amount_withdrawn;
+    vesting_session.start_date = schedule.start_date;
+    vesting_session.last_withdraw_at = schedule.last_withdraw_at;
+    vesting_session.amount_unlocked = schedule.amount_unlocked;
+}
+
+/// ID of the next vesting session of a collection, never below the ids saved when a previous
+/// collection of the same user was closed
+pub fn next_session_id(
+    vesting_sessions_account: &VestingSessionsAccount,
+    session_ids_account: &AccountInfo,
+) -> u64 {
+    let saved_last_session_id = if session_ids_account.owner == &crate::ID {
+        SessionIdsAccount::try_deserialize(&mut &session_ids_account.data.borrow()[..])
+            .map(|session_ids| session_ids.last_session_id)
+            .unwrap_or_default()
+    } else {
+        0
+    };
+    vesting_sessions_account
+        .last_session_id
+        .max(saved_last_session_id)
+}
+
 /// Calculates the amount of tokens to release in a vesting session
 pub fn calculate_amount_to_release(vesting_session: &VestingSession) -> Result<u64> {
-    // Constants
-    const SIX_MONTHS_IN_MINUTES: u64 = 180 * 24 * 60; // 180 days * 24 hours * 60 minutes
-    const SIX_MONTHS_IN_SECONDS: u64 = SIX_MONTHS_IN_MINUTES * 60; // SIX_MONTHS_IN_MINUTES * 60 seconds
-
-    // Get current time
-    let clock = Clock::get()?;
-    let current_time_seconds = clock.unix_timestamp as u64;
-
-    // Calculate vesting end time
-    let vesting_start = vesting_session.start_date;
-    let vesting_end_time = vesting_start
-        .checked_add(SIX_MONTHS_IN_SECONDS)
-        .ok_or(VestingErrorCode::ArithmeticOverflow)?;
+    let current_time_seconds = Clock::get()?.unix_timestamp as u64;
+    vesting_math::amount_to_release(&Schedule::from(vesting_session), current_time_seconds)
+        .map_err(|e| VestingErrorCode::from(e).into())
+}
+
+/// Records a withdrawal of `amount` tokens from a vesting session at `withdrawn_at`
+pub fn record_session_withdrawal(
+    vesting_session: &mut VestingSession,
+    amount: u64,
+    withdrawn_at: u64,
+) -> Result<()> {
+    let mut schedule = Schedule::from(&*vesting_session);
+    vesting_math::record_withdrawal(&mut schedule, amount, withdrawn_at)
+        .map_err(VestingErrorCode::from)?;
+    apply_session_schedule(vesting_session, schedule);
+    Ok(())
+}
 
-    // Check if vesting period has ended
-    if current_time_seconds >= vesting_end_time {
-        // Vesting period has ended, return full remaining amount
-        return Ok(vesting_session
-            .amount
-            .saturating_sub(vesting_session.amount_withdrawn));
+/// Updates the degraded state of a vault comparing its valued token balance with the balance
+/// expected from the program operations. Returns the shortfall when it is first detected
+pub fn update_vault_shortfall(
+    vault_account: &mut VaultAccount,
+    valued_vault_balance: u64,
+) -> Option<u64> {
+    if valued_vault_balance >= vault_account.expected_valued_balance {
+        vault_account.degraded = false;
+        return None;
+    }
+    if vault_account.degraded {
+        return None;
     }
 
-    // Calculate elapsed time since last withdrawal or start
-    let last_withdraw_seconds = vesting_session.last_withdraw_at;
-    let elapsed_seconds = if last_withdraw_seconds > 0 {
-        current_time_seconds.saturating_sub(last_withdraw_seconds)
-    } else {
-        current_time_seconds.saturating_sub(vesting_start)
+    vault_account.degraded = true;
+    Some(vault_account.expected_valued_balance - valued_vault_balance)
+}
+
+/// Books a claim of `amount` released valued tokens of a vesting session and returns the amount
+/// to pay out, see [`vesting_math::book_payout`]. Degraded vaults pay the same share of every
+/// claim and owe the rest to the session, healthy ones pay its owed tokens with the claim
+pub fn book_valued_payout(
+    vault_account: &mut VaultAccount,
+    vesting_session: &mut VestingSession,
+    valued_vault_balance: u64,
+    amount: u64,
+) -> Result<u64> {
+    let mut ledger = VaultLedger {
+        expected_balance: vault_account.expected_valued_balance,
+        owed_balance: vault_account.owed_valued_balance,
     };
+    let payout = vesting_math::book_payout(
+        &mut ledger,
+        valued_vault_balance,
+        amount,
+        &mut vesting_session.amount_owed,
+    )
+    .map_err(VestingErrorCode::from)?;
 
-    // Calculate amount to be released
-    let amount_per_minute = vesting_session
-        .amount
-        .checked_div(SIX_MONTHS_IN_MINUTES)
-        .ok_or(VestingErrorCode::DivisionByZero)?;
-    let amount_released = elapsed_seconds
-        .checked_div(60)
-        .ok_or(VestingErrorCode::DivisionByZero)?
-        .checked_mul(amount_per_minute)
-        .ok_or(VestingErrorCode::ArithmeticOverflow)?;
+    vault_account.expected_valued_balance = ledger.expected_balance;
+    vault_account.owed_valued_balance = ledger.owed_balance;
+    Ok(payout)
+}
 
-    // Ensure we're not releasing more than what's available
-    let available_to_withdraw = vesting_session
-        .amount
-        .saturating_sub(vesting_session.amount_withdrawn);
-    let amount_to_release = std::cmp::min(amount_released, available_to_withdraw);
+/// Folds a new tranche of tokens into an ongoing vesting session, see
+/// [`vesting_math::fold_tranche`]
+pub fn fold_tranche_into_session(
+    vesting_session: &mut VestingSession,
+    tranche: &Schedule,
+) -> Result<()> {
+    let current_time_seconds = Clock::get()?.unix_timestamp as u64;
+    let mut schedule = Schedule::from(&*vesting_session);
+    vesting_math::fold_tranche(&mut schedule, tranche, current_time_seconds)
+        .map_err(VestingErrorCode::from)?;
+    apply_session_schedule(vesting_session, schedule);
+    Ok(())
+}
 
-    Ok(amount_to_release)
+/// Carves `amount` of the locked tokens off a vesting session keeping the same schedule, see
+/// [`vesting_math::split_amounts`]. Returns the total and withdrawn amounts of the carved off part.
+pub fn split_session_amounts(
+    vesting_session: &mut VestingSession,
+    amount: u64,
+) -> Result<(u64, u64)> {
+    let mut schedule = Schedule::from(&*vesting_session);
+    let split =
+        vesting_math::split_amounts(&mut schedule, amount).map_err(VestingErrorCode::from)?;
+    apply_session_schedule(vesting_session, schedule);
+    Ok(split)
 }
 
 pub mod token_2022_validations {
-    use crate::VestingErrorCode;
-    use anchor_lang::err;
-    use anchor_lang::prelude::AccountInfo;
+    use crate::{
+        BackendAccountData, MintConfigSnapshot, VaultAccount, VestingErrorCode,
+        MAX_ALLOWED_TOKEN_EXTENSIONS,
+    };
+    use anchor_lang::prelude::{msg, AccountInfo, Pubkey};
+    use anchor_lang::{err, error, require};
     use anchor_spl::token::spl_token;
     use anchor_spl::token_2022::spl_token_2022;
+    use anchor_spl::token_interface::spl_token_2022::extension::permanent_delegate::PermanentDelegate;
+    use anchor_spl::token_interface::spl_token_2022::extension::transfer_hook;
     use anchor_spl::token_interface::spl_token_2022::extension::ExtensionType;
     use anchor_spl::token_interface::spl_token_2022::extension::{
         BaseStateWithExtensions, StateWithExtensions,
     };
 
+    // TransferHook is left out, hook enabled mints are opt-in through the allowed extensions
     const VALID_LIQUIDITY_TOKEN_EXTENSIONS: &[ExtensionType] = &[
         ExtensionType::MintCloseAuthority,
         ExtensionType::MetadataPointer,
         ExtensionType::PermanentDelegate,
         ExtensionType::TokenMetadata,
+        ExtensionType::TransferFeeConfig,
     ];
 
-    pub fn validate_token_extensions(mint_acc_info: &AccountInfo) -> anchor_lang::Result<()> {
+    /// Returns the extensions allowed for the valued token of a vault. The vault override takes
+    /// precedence over the backend configuration, `None` means the built-in defaults apply
+    pub fn allowed_token_extensions<'a>(
+        backend_data: &'a BackendAccountData,
+        vault_account: &'a VaultAccount,
+    ) -> Option<&'a [u16]> {
+        vault_account
+            .allowed_token_extensions
+            .as_deref()
+            .or(backend_data.allowed_token_extensions.as_deref())
+    }
+
+    /// Validates a list of allowed extensions only contains known extension types
+    pub fn validate_allowed_token_extensions(
+        allowed_token_extensions: &[u16],
+    ) -> anchor_lang::Result<()> {
+        require!(
+            allowed_token_extensions.len() <= MAX_ALLOWED_TOKEN_EXTENSIONS,
+            VestingErrorCode::TooManyAllowedTokenExtensions
+        );
+        for extension in allowed_token_extensions {
+            ExtensionType::try_from(*extension)
+                .map_err(|_| error!(VestingErrorCode::InvalidTokenExtension))?;
+        }
+        Ok(())
+    }
+
+    pub fn validate_token_extensions(
+        mint_acc_info: &AccountInfo,
+        allowed_token_extensions: Option<&[u16]>,
+    ) -> anchor_lang::Result<()> {
         if mint_acc_info.owner == &spl_token::id() {
             return Ok(());
         }
@@ -195,10 +767,310 @@ pub mod token_2022_validations {
         let mint = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
 
         for mint_ext in mint.get_extension_types()? {
-            if !VALID_LIQUIDITY_TOKEN_EXTENSIONS.contains(&mint_ext) {
+            let is_allowed = match allowed_token_extensions {
+                Some(allowed_token_extensions) => {
+                    allowed_token_extensions.contains(&u16::from(mint_ext))
+                }
+                None => VALID_LIQUIDITY_TOKEN_EXTENSIONS.contains(&mint_ext),
+            };
+            if !is_allowed {
+                msg!(
+                    "Rejected token extension: {:?} ({})",
+                    mint_ext,
+                    u16::from(mint_ext)
+                );
                 return err!(VestingErrorCode::UnsupportedTokenExtension);
             }
         }
         Ok(())
     }
+
+    /// Returns the program invoked on every transfer of a mint implementing the `TransferHook` extension
+    pub fn transfer_hook_program_id(
+        mint_acc_info: &AccountInfo,
+    ) -> anchor_lang::Result<Option<Pubkey>> {
+        if mint_acc_info.owner == &spl_token::id() {
+            return Ok(None);
+        }
+
+        let mint_data = mint_acc_info.data.borrow();
+        let mint = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+
+        Ok(transfer_hook::get_program_id(&mint))
+    }
+
+    /// Validates the transfer hook program of a mint, if any, is one of the trusted programs
+    pub fn validate_transfer_hook(
+        mint_acc_info: &AccountInfo,
+        trusted_hook_programs: &[Pubkey],
+    ) -> anchor_lang::Result<()> {
+        if let Some(hook_program_id) = transfer_hook_program_id(mint_acc_info)? {
+            if !trusted_hook_programs.contains(&hook_program_id) {
+                return err!(VestingErrorCode::UntrustedTransferHook);
+            }
+        }
+        Ok(())
+    }
+
+    /// Records the extensions and critical authorities of a mint
+    pub fn snapshot_mint_config(
+        mint_acc_info: &AccountInfo,
+    ) -> anchor_lang::Result<MintConfigSnapshot> {
+        let mint_data = mint_acc_info.data.borrow();
+        let mint = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+
+        let permanent_delegate = match mint.get_extension::<PermanentDelegate>() {
+            Ok(extension) => Option::<Pubkey>::from(extension.delegate),
+            Err(_) => None,
+        };
+
+        Ok(MintConfigSnapshot {
+            extensions: mint
+                .get_extension_types()?
+                .into_iter()
+                .map(u16::from)
+                .collect(),
+            freeze_authority: mint.base.freeze_authority.into(),
+            permanent_delegate,
+            transfer_hook_program: transfer_hook::get_program_id(&mint),
+        })
+    }
+
+    /// Validates a mint kept the configuration recorded in a snapshot. Added extensions are left to
+    /// the allowed extensions check and revoked authorities only make the mint safer, any other
+    /// change is refused until an admin refreshes the snapshot
+    pub fn validate_mint_config(
+        mint_acc_info: &AccountInfo,
+        snapshot: &MintConfigSnapshot,
+    ) -> anchor_lang::Result<()> {
+        let current = snapshot_mint_config(mint_acc_info)?;
+        let kept_or_revoked = |current: Option<Pubkey>, recorded: Option<Pubkey>| {
+            current.is_none() || current == recorded
+        };
+
+        require!(
+            snapshot
+                .extensions
+                .iter()
+                .all(|extension| current.extensions.contains(extension)),
+            VestingErrorCode::ValuedMintExtensionsChanged
+        );
+        require!(
+            kept_or_revoked(current.freeze_authority, snapshot.freeze_authority),
+            VestingErrorCode::ValuedMintFreezeAuthorityChanged
+        );
+        require!(
+            kept_or_revoked(current.permanent_delegate, snapshot.permanent_delegate),
+            VestingErrorCode::ValuedMintPermanentDelegateChanged
+        );
+        require!(
+            kept_or_revoked(
+                current.transfer_hook_program,
+                snapshot.transfer_hook_program
+            ),
+            VestingErrorCode::ValuedMintTransferHookChanged
+        );
+        Ok(())
+    }
+}
+
+pub mod metadata_validations {
+    use crate::{
+        BackendAccountData, MetadataRules, VestingErrorCode, MAX_ALLOWED_URI_SCHEMES,
+        MAX_METADATA_RULE_LENGTH,
+    };
+    use anchor_lang::prelude::Result;
+    use anchor_lang::require;
+
+    use super::MAX_METADATA_VALUE_LENGTH;
+
+    /// Returns the escrow metadata rules configured in the backend. Metadata is not checked
+    /// against any rules until an admin sets them
+    pub fn metadata_rules(backend_data: &BackendAccountData) -> Option<&MetadataRules> {
+        backend_data.metadata_rules.as_ref()
+    }
+
+    /// Validates metadata rules are consistent before storing them
+    pub fn validate_metadata_rules(rules: &MetadataRules) -> Result<()> {
+        require!(
+            rules.max_name_length > 0 && rules.max_symbol_length > 0 && rules.max_uri_length > 0,
+            VestingErrorCode::InvalidMetadataRules
+        );
+        // Changed values are capped at MAX_METADATA_VALUE_LENGTH, longer limits could only be
+        // reached when the escrow token is created
+        require!(
+            [
+                rules.max_name_length,
+                rules.max_symbol_length,
+                rules.max_uri_length,
+            ]
+            .iter()
+            .all(|max_length| usize::from(*max_length) <= MAX_METADATA_VALUE_LENGTH),
+            VestingErrorCode::InvalidMetadataRules
+        );
+        require!(
+            rules.allowed_uri_schemes.len() <= MAX_ALLOWED_URI_SCHEMES,
+            VestingErrorCode::InvalidMetadataRules
+        );
+        let is_valid_rule_string =
+            |value: &String| !value.is_empty() && value.len() <= MAX_METADATA_RULE_LENGTH;
+        require!(
+            rules.allowed_uri_schemes.iter().all(is_valid_rule_string),
+            VestingErrorCode::InvalidMetadataRules
+        );
+        require!(
+            rules.symbol_prefix.iter().all(is_valid_rule_string)
+                && rules.symbol_suffix.iter().all(is_valid_rule_string),
+            VestingErrorCode::InvalidMetadataRules
+        );
+        Ok(())
+    }
+
+    /// Validates the name, symbol and URI of an escrow token against the configured rules, if any
+    pub fn validate_metadata(
+        rules: Option<&MetadataRules>,
+        name: &str,
+        symbol: &str,
+        uri: &str,
+    ) -> Result<()> {
+        let Some(rules) = rules else {
+            return Ok(());
+        };
+        require!(
+            !name.is_empty() && !symbol.is_empty() && !uri.is_empty(),
+            VestingErrorCode::InvalidMeta
+        );
+        validate_name(rules, name)?;
+        validate_symbol(rules, symbol)?;
+        validate_uri(rules, uri)
+    }
+
+    /// Validates the name, symbol and URI of a vesting position token. Position tokens always
+    /// need non-empty values within the maximum metadata value length
+    pub fn validate_position_metadata(
+        rules: Option<&MetadataRules>,
+        name: &str,
+        symbol: &str,
+        uri: &str,
+    ) -> Result<()> {
+        require!(
+            !name.is_empty() && !symbol.is_empty() && !uri.is_empty(),
+            VestingErrorCode::InvalidMeta
+        );
+        require!(
+            [name, symbol, uri]
+                .iter()
+                .all(|value| value.len() <= MAX_METADATA_VALUE_LENGTH),
+            VestingErrorCode::MetadataValueTooLong
+        );
+        validate_metadata(rules, name, symbol, uri)
+    }
+
+    pub fn validate_name(rules: &MetadataRules, name: &str) -> Result<()> {
+        require!(
+            name.len() <= usize::from(rules.max_name_length),
+            VestingErrorCode::MetadataNameTooLong
+        );
+        Ok(())
+    }
+
+    pub fn validate_symbol(rules: &MetadataRules, symbol: &str) -> Result<()> {
+        require!(
+            symbol.len() <= usize::from(rules.max_symbol_length),
+            VestingErrorCode::MetadataSymbolTooLong
+        );
+        if rules.ascii_symbol {
+            require!(
+                symbol.bytes().all(|byte| byte.is_ascii_graphic()),
+                VestingErrorCode::NonAsciiMetadataSymbol
+            );
+        }
+        if let Some(prefix) = &rules.symbol_prefix {
+            require!(
+                symbol.starts_with(prefix.as_str()),
+                VestingErrorCode::MissingEscrowSymbolPrefix
+            );
+        }
+        if let Some(suffix) = &rules.symbol_suffix {
+            require!(
+                symbol.ends_with(suffix.as_str()),
+                VestingErrorCode::MissingEscrowSymbolSuffix
+            );
+        }
+        Ok(())
+    }
+
+    pub fn validate_uri(rules: &MetadataRules, uri: &str) -> Result<()> {
+        require!(
+            uri.len() <= usize::from(rules.max_uri_length),
+            VestingErrorCode::MetadataUriTooLong
+        );
+        if !rules.allowed_uri_schemes.is_empty() {
+            let is_allowed_scheme = uri.split_once("://").is_some_and(|(scheme, _)| {
+                rules
+                    .allowed_uri_schemes
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(scheme))
+            });
+            require!(
+                is_allowed_scheme,
+                VestingErrorCode::UnsupportedMetadataUriScheme
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Reads and rewrites program accounts created with a legacy layout
+pub mod account_migrations {
+    use crate::VestingErrorCode;
+    use anchor_lang::error::ErrorCode;
+    use anchor_lang::prelude::*;
+    use anchor_lang::{Discriminator, Space};
+
+    use super::update_account_lamports_to_minimum_balance;
+
+    /// Reads a program account of type `T` still written with the version 0 layout `L`
+    pub fn read_v0_account<T, L>(account: &AccountInfo) -> Result<L>
+    where
+        T: Discriminator + Space,
+        L: AnchorDeserialize + Space,
+    {
+        require_keys_eq!(
+            *account.owner,
+            crate::ID,
+            ErrorCode::AccountOwnedByWrongProgram
+        );
+        let data = account.try_borrow_data()?;
+        require!(
+            data.len() >= 8 && data[..8] == T::DISCRIMINATOR,
+            ErrorCode::AccountDiscriminatorMismatch
+        );
+        require!(
+            data.len() != 8 + T::INIT_SPACE,
+            VestingErrorCode::AccountAlreadyMigrated
+        );
+        require!(
+            data.len() == 8 + L::INIT_SPACE,
+            VestingErrorCode::UnknownAccountLayout
+        );
+        Ok(L::deserialize(&mut &data[8..])?)
+    }
+
+    /// Reallocates a program account to the current layout of `T`, topping up its rent from
+    /// `payer`, and writes `account_data` to it
+    pub fn write_migrated_account<'info, T>(
+        account: &AccountInfo<'info>,
+        payer: AccountInfo<'info>,
+        system_program: AccountInfo<'info>,
+        account_data: &T,
+    ) -> Result<()>
+    where
+        T: AccountSerialize + Space,
+    {
+        account.realloc(8 + T::INIT_SPACE, false)?;
+        update_account_lamports_to_minimum_balance(account.clone(), payer, system_program)?;
+        let mut data = account.try_borrow_mut_data()?;
+        account_data.try_serialize(&mut &mut data[..])
+    }
 }
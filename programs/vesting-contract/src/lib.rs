@@ -15,39 +15,51 @@ declare_id!("GZ5Q5XdSv4PARXMn5ZGAvF7KjafLStsCGwEAzjowpqsw");
 pub mod vesting_contract {
 
     use crate::helpers::{
-        calculate_amount_to_release, token_2022_validations, transfer_escrow_from_vault,
-        transfer_tokens, update_account_lamports_to_minimum_balance,
+        account_migrations, book_valued_payout, calculate_amount_to_release,
+        create_non_transferable_escrow_mint, escrow_metadata_field, fold_tranche_into_session,
+        harvest_withheld_fees, is_program_admin, metadata_validations, mint_close_authority,
+        next_session_id, rebalance_account_lamports, record_session_withdrawal,
+        rent_refund_destination, split_session_amounts, token_2022_validations,
+        transfer_escrow_from_vault, transfer_escrow_to_vault, transfer_tokens,
+        update_account_lamports_to_minimum_balance, update_vault_shortfall, EscrowTransferAccounts,
+        TokenTransferAccounts, MAX_METADATA_KEY_LENGTH,
     };
 
+    use anchor_lang::solana_program::program::invoke_signed;
+    use anchor_spl::token_2022::spl_token_2022::extension::ExtensionType;
+    use anchor_spl::token_2022_extensions::spl_pod::optional_keys::OptionalNonZeroPubkey;
     use anchor_spl::token_interface::{
-        token_metadata_initialize, token_metadata_update_field, TokenMetadataInitialize,
-        TokenMetadataUpdateField,
+        burn, close_account, freeze_account, mint_to, set_authority,
+        spl_token_2022::instruction::AuthorityType, thaw_account, token_metadata_initialize,
+        token_metadata_update_field, Burn, CloseAccount, FreezeAccount, MintTo, SetAuthority,
+        ThawAccount, TokenMetadataInitialize, TokenMetadataUpdateField,
     };
-    use spl_token_metadata_interface::state::Field;
+    use spl_token_metadata_interface::{
+        instruction::{remove_key, update_authority},
+        state::Field,
+    };
+    use vesting_math::Schedule;
 
     use super::*;
 
     /// Minimum value which a currency can provide amounts every minute (equivalent to the amount of minutes in 6 months)
-    const MIN_DIVISIBLE_BY_VESTING_PERIOD: u64 = 180 * 24 * 60;
+    const MIN_DIVISIBLE_BY_VESTING_PERIOD: u64 = vesting_math::VESTING_PERIOD_MINUTES;
+
+    /// Maximum amount of escrow metadata fields which can be updated in a single instruction
+    const MAX_METADATA_UPDATES_PER_BATCH: usize = 10;
+
+    /// Maximum amount of sessions which can be merged into another one in a single instruction
+    const MAX_SESSIONS_PER_MERGE: usize = 10;
 
     pub fn set_backend_account(
         ctx: Context<SetBackendAccountCtx>,
         metadata: SetBackendAccountParams,
     ) -> Result<()> {
-        let program_authority = &ctx.accounts.program_data.upgrade_authority_address;
         let tx_payer = &ctx.accounts.payer.key();
         let backend_data = &mut ctx.accounts.backend_data;
+        let previous_backend_account = backend_data.backend_account;
 
-        let is_program_authority = match program_authority {
-            Some(authority) => tx_payer == authority,
-            None => false,
-        };
-        let is_backend_change_authority = match &backend_data.change_authority {
-            Some(authority) => tx_payer == authority,
-            None => false,
-        };
-
-        if is_program_authority || is_backend_change_authority {
+        if is_program_admin(&ctx.accounts.program_data, backend_data, tx_payer) {
             backend_data.backend_account = metadata.new_backend_account;
             if metadata.new_authority.is_some() {
                 backend_data.change_authority = metadata.new_authority;
@@ -56,6 +68,425 @@ pub mod vesting_contract {
             return err!(VestingErrorCode::UnathorizedToExecute);
         }
 
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            BackendAccountSetEvent {
+                version: EVENT_SCHEMA_VERSION,
+                actor: *tx_payer,
+                previous_backend_account,
+                backend_account: backend_data.backend_account,
+                change_authority: backend_data.change_authority,
+                time: clock.unix_timestamp as u64,
+                slot: clock.slot,
+            }
+        );
+        Ok(())
+    }
+
+    /// Sets the token extensions allowed for the valued tokens of every vault
+    pub fn set_allowed_token_extensions(
+        ctx: Context<UpdateBackendConfig>,
+        params: AllowedTokenExtensionsParams,
+    ) -> Result<()> {
+        require!(
+            is_program_admin(
+                &ctx.accounts.program_data,
+                &ctx.accounts.backend_data,
+                &ctx.accounts.admin.key()
+            ),
+            VestingErrorCode::UnathorizedToExecute
+        );
+        if let Some(allowed_token_extensions) = &params.allowed_token_extensions {
+            token_2022_validations::validate_allowed_token_extensions(allowed_token_extensions)?;
+        }
+
+        ctx.accounts.backend_data.allowed_token_extensions = params.allowed_token_extensions;
+
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            AllowedTokenExtensionsUpdatedEvent {
+                version: EVENT_SCHEMA_VERSION,
+                vault_account: None,
+                allowed_token_extensions: ctx
+                    .accounts
+                    .backend_data
+                    .allowed_token_extensions
+                    .clone(),
+                actor: ctx.accounts.admin.key(),
+                time: clock.unix_timestamp as u64,
+                slot: clock.slot,
+            }
+        );
+        Ok(())
+    }
+
+    /// Sets the validation rules for the name, symbol and URI of escrow tokens. None removes
+    /// the rules
+    pub fn set_metadata_rules(
+        ctx: Context<UpdateBackendConfig>,
+        params: MetadataRulesParams,
+    ) -> Result<()> {
+        require!(
+            is_program_admin(
+                &ctx.accounts.program_data,
+                &ctx.accounts.backend_data,
+                &ctx.accounts.admin.key()
+            ),
+            VestingErrorCode::UnathorizedToExecute
+        );
+        if let Some(metadata_rules) = &params.metadata_rules {
+            metadata_validations::validate_metadata_rules(metadata_rules)?;
+        }
+
+        ctx.accounts.backend_data.metadata_rules = params.metadata_rules.clone();
+
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            MetadataRulesUpdatedEvent {
+                version: EVENT_SCHEMA_VERSION,
+                metadata_rules: params.metadata_rules,
+                actor: ctx.accounts.admin.key(),
+                time: clock.unix_timestamp as u64,
+                slot: clock.slot,
+            }
+        );
+        Ok(())
+    }
+
+    /// Sets the account receiving the rent surplus of escrow mints when their metadata shrinks.
+    /// The payer of each metadata change is refunded when not set
+    pub fn set_rent_refund_destination(
+        ctx: Context<UpdateBackendConfig>,
+        params: RentRefundDestinationParams,
+    ) -> Result<()> {
+        require!(
+            is_program_admin(
+                &ctx.accounts.program_data,
+                &ctx.accounts.backend_data,
+                &ctx.accounts.admin.key()
+            ),
+            VestingErrorCode::UnathorizedToExecute
+        );
+
+        ctx.accounts.backend_data.rent_refund_destination = params.rent_refund_destination;
+
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            RentRefundDestinationUpdatedEvent {
+                version: EVENT_SCHEMA_VERSION,
+                rent_refund_destination: params.rent_refund_destination,
+                actor: ctx.accounts.admin.key(),
+                time: clock.unix_timestamp as u64,
+                slot: clock.slot,
+            }
+        );
+        Ok(())
+    }
+
+    /// Overrides the token extensions allowed for the valued token of a vault
+    pub fn set_vault_allowed_token_extensions(
+        ctx: Context<SetVaultAllowedTokenExtensions>,
+        params: AllowedTokenExtensionsParams,
+    ) -> Result<()> {
+        require!(
+            is_program_admin(
+                &ctx.accounts.program_data,
+                &ctx.accounts.backend_data,
+                &ctx.accounts.admin.key()
+            ),
+            VestingErrorCode::UnathorizedToExecute
+        );
+        if let Some(allowed_token_extensions) = &params.allowed_token_extensions {
+            token_2022_validations::validate_allowed_token_extensions(allowed_token_extensions)?;
+        }
+
+        ctx.accounts.vault_account.allowed_token_extensions = params.allowed_token_extensions;
+
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            AllowedTokenExtensionsUpdatedEvent {
+                version: EVENT_SCHEMA_VERSION,
+                vault_account: Some(ctx.accounts.vault_account.key()),
+                allowed_token_extensions: ctx
+                    .accounts
+                    .vault_account
+                    .allowed_token_extensions
+                    .clone(),
+                actor: ctx.accounts.admin.key(),
+                time: clock.unix_timestamp as u64,
+                slot: clock.slot,
+            }
+        );
+        Ok(())
+    }
+
+    /// Records the current configuration of the valued token mint of a vault, accepting a change
+    /// refused by the mint configuration checks
+    pub fn refresh_valued_mint_config(ctx: Context<RefreshValuedMintConfig>) -> Result<()> {
+        require!(
+            is_program_admin(
+                &ctx.accounts.program_data,
+                &ctx.accounts.backend_data,
+                &ctx.accounts.admin.key()
+            ),
+            VestingErrorCode::UnathorizedToExecute
+        );
+        let valued_token_mint = ctx.accounts.valued_token_mint.to_account_info();
+        token_2022_validations::validate_token_extensions(
+            &valued_token_mint,
+            token_2022_validations::allowed_token_extensions(
+                &ctx.accounts.backend_data,
+                &ctx.accounts.vault_account,
+            ),
+        )?;
+        token_2022_validations::validate_transfer_hook(
+            &valued_token_mint,
+            &ctx.accounts.vault_account.trusted_hook_programs,
+        )?;
+
+        let valued_mint_config = token_2022_validations::snapshot_mint_config(&valued_token_mint)?;
+        let previous_valued_mint_config = std::mem::replace(
+            &mut ctx.accounts.vault_account.valued_mint_config,
+            valued_mint_config,
+        );
+
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            ValuedMintConfigRefreshedEvent {
+                version: EVENT_SCHEMA_VERSION,
+                vault_account: ctx.accounts.vault_account.key(),
+                previous_valued_mint_config,
+                valued_mint_config: ctx.accounts.vault_account.valued_mint_config.clone(),
+                actor: ctx.accounts.admin.key(),
+                time: clock.unix_timestamp as u64,
+                slot: clock.slot,
+            }
+        );
+        Ok(())
+    }
+
+    /// Migrates the backend configuration written with a legacy layout. Settings added since
+    /// then are left unset, so the built-in defaults apply
+    pub fn migrate_backend_data(ctx: Context<MigrateBackendData>) -> Result<()> {
+        let legacy = account_migrations::read_v0_account::<BackendAccountData, BackendAccountDataV0>(
+            &ctx.accounts.backend_data,
+        )?;
+        let backend_data = BackendAccountData {
+            backend_account: legacy.backend_account,
+            change_authority: legacy.change_authority,
+            allowed_token_extensions: None,
+            rent_refund_destination: None,
+            metadata_rules: None,
+        };
+        require!(
+            is_program_admin(
+                &ctx.accounts.program_data,
+                &backend_data,
+                &ctx.accounts.admin.key()
+            ),
+            VestingErrorCode::UnathorizedToExecute
+        );
+        account_migrations::write_migrated_account(
+            &ctx.accounts.backend_data,
+            ctx.accounts.admin.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            &backend_data,
+        )?;
+
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            AccountMigratedEvent {
+                version: EVENT_SCHEMA_VERSION,
+                account: ctx.accounts.backend_data.key(),
+                layout_version_before: 0,
+                layout_version: ACCOUNT_LAYOUT_VERSION,
+                actor: ctx.accounts.admin.key(),
+                time: clock.unix_timestamp as u64,
+                slot: clock.slot,
+            }
+        );
+        Ok(())
+    }
+
+    /// Migrates a vault written with a legacy layout. The valued token mint configuration is
+    /// recorded as it is now, and the balances are backfilled from the escrow supply: legacy
+    /// exchanges minted one escrow token per valued token and never burned any. Escrow locked in
+    /// sessions is added to the expected balance as each session is migrated
+    pub fn migrate_vault(ctx: Context<MigrateVault>) -> Result<()> {
+        require!(
+            is_program_admin(
+                &ctx.accounts.program_data,
+                &ctx.accounts.backend_data,
+                &ctx.accounts.admin.key()
+            ),
+            VestingErrorCode::UnathorizedToExecute
+        );
+        let legacy = account_migrations::read_v0_account::<VaultAccount, VaultAccountV0>(
+            &ctx.accounts.vault_account,
+        )?;
+
+        let escrow_supply = ctx.accounts.escrow_token_mint.supply;
+        let escrow_non_transferable = token_2022_validations::snapshot_mint_config(
+            &ctx.accounts.escrow_token_mint.to_account_info(),
+        )?
+        .extensions
+        .contains(&u16::from(ExtensionType::NonTransferable));
+        let vault_account = VaultAccount {
+            creator: legacy.creator,
+            valued_token_mint: legacy.valued_token_mint,
+            escrow_token_mint: legacy.escrow_token_mint,
+            app_id: legacy.app_id,
+            sessions_transferable: false,
+            // Counted as the sessions collections are migrated
+            active_sessions: 0,
+            trusted_hook_programs: vec![],
+            allowed_token_extensions: None,
+            valued_mint_config: token_2022_validations::snapshot_mint_config(
+                &ctx.accounts.valued_token_mint.to_account_info(),
+            )?,
+            // Escrow held outside of the vault, claimable once vested
+            expected_valued_balance: escrow_supply
+                .checked_sub(ctx.accounts.escrow_vault_token_account.amount)
+                .ok_or(VestingErrorCode::ArithmeticOverflow)?,
+            degraded: false,
+            owed_valued_balance: 0,
+            escrow_non_transferable,
+            max_exchanged_amount: None,
+            total_exchanged_amount: escrow_supply,
+            metadata_update_authority: None,
+            metadata_pointer_authority: None,
+        };
+        account_migrations::write_migrated_account(
+            &ctx.accounts.vault_account,
+            ctx.accounts.admin.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            &vault_account,
+        )?;
+
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            AccountMigratedEvent {
+                version: EVENT_SCHEMA_VERSION,
+                account: ctx.accounts.vault_account.key(),
+                layout_version_before: 0,
+                layout_version: ACCOUNT_LAYOUT_VERSION,
+                actor: ctx.accounts.admin.key(),
+                time: clock.unix_timestamp as u64,
+                slot: clock.slot,
+            }
+        );
+        Ok(())
+    }
+
+    /// Migrates a sessions collection written with a legacy layout, once its vault is migrated.
+    /// Legacy sessions could not be closed, so every id handed out is still an open session
+    pub fn migrate_sessions_collection(ctx: Context<MigrateSessionsCollection>) -> Result<()> {
+        let legacy = account_migrations::read_v0_account::<
+            VestingSessionsAccount,
+            VestingSessionsAccountV0,
+        >(&ctx.accounts.vesting_sessions_account)?;
+        require_keys_eq!(
+            legacy.user,
+            ctx.accounts.user.key(),
+            anchor_lang::error::ErrorCode::ConstraintHasOne
+        );
+
+        let vault_account = &mut ctx.accounts.vault_account;
+        vault_account.active_sessions = vault_account
+            .active_sessions
+            .checked_add(legacy.last_session_id)
+            .ok_or(VestingErrorCode::ArithmeticOverflow)?;
+        account_migrations::write_migrated_account(
+            &ctx.accounts.vesting_sessions_account,
+            ctx.accounts.backend.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            &VestingSessionsAccount {
+                last_session_id: legacy.last_session_id,
+                user: legacy.user,
+                active_sessions: legacy.last_session_id,
+            },
+        )?;
+
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            AccountMigratedEvent {
+                version: EVENT_SCHEMA_VERSION,
+                account: ctx.accounts.vesting_sessions_account.key(),
+                layout_version_before: 0,
+                layout_version: ACCOUNT_LAYOUT_VERSION,
+                actor: ctx.accounts.backend.key(),
+                time: clock.unix_timestamp as u64,
+                slot: clock.slot,
+            }
+        );
+        Ok(())
+    }
+
+    /// Migrates a vesting session written with a legacy layout, once its collection is migrated.
+    /// The escrow still locked in the session is added to the valued balance the vault owes
+    pub fn migrate_vesting_session(ctx: Context<MigrateVestingSession>) -> Result<()> {
+        let legacy = account_migrations::read_v0_account::<VestingSession, VestingSessionV0>(
+            &ctx.accounts.vesting_session_account,
+        )?;
+        require_keys_eq!(
+            legacy.vesting_sessions_account,
+            ctx.accounts.vesting_sessions_account.key(),
+            anchor_lang::error::ErrorCode::ConstraintHasOne
+        );
+
+        // Cancelled and exited sessions already returned or paid out their remaining amount
+        if legacy.cancelled_at == 0 {
+            let locked_amount = legacy
+                .amount
+                .checked_sub(legacy.amount_withdrawn)
+                .ok_or(VestingErrorCode::ArithmeticOverflow)?;
+            let vault_account = &mut ctx.accounts.vault_account;
+            vault_account.expected_valued_balance = vault_account
+                .expected_valued_balance
+                .checked_add(locked_amount)
+                .ok_or(VestingErrorCode::ArithmeticOverflow)?;
+        }
+        account_migrations::write_migrated_account(
+            &ctx.accounts.vesting_session_account,
+            ctx.accounts.backend.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            &VestingSession {
+                id: legacy.id,
+                user: legacy.user,
+                vesting_sessions_account: legacy.vesting_sessions_account,
+                amount: legacy.amount,
+                amount_withdrawn: legacy.amount_withdrawn,
+                start_date: legacy.start_date,
+                last_withdraw_at: legacy.last_withdraw_at,
+                cancelled_at: legacy.cancelled_at,
+                position_mint: None,
+                amount_owed: 0,
+                amount_unlocked: 0,
+            },
+        )?;
+
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            AccountMigratedEvent {
+                version: EVENT_SCHEMA_VERSION,
+                account: ctx.accounts.vesting_session_account.key(),
+                layout_version_before: 0,
+                layout_version: ACCOUNT_LAYOUT_VERSION,
+                actor: ctx.accounts.backend.key(),
+                time: clock.unix_timestamp as u64,
+                slot: clock.slot,
+            }
+        );
         Ok(())
     }
 
@@ -64,8 +495,97 @@ pub mod vesting_contract {
         ctx: Context<InitEscrowToken>,
         metadata: InitEscrowTokenParams,
     ) -> Result<()> {
+        metadata_validations::validate_metadata(
+            metadata_validations::metadata_rules(&ctx.accounts.backend_data),
+            &metadata.name,
+            &metadata.symbol,
+            &metadata.uri,
+        )?;
+        token_2022_validations::validate_token_extensions(
+            &ctx.accounts.valued_token_mint.to_account_info(),
+            token_2022_validations::allowed_token_extensions(
+                &ctx.accounts.backend_data,
+                &ctx.accounts.vault_account,
+            ),
+        )?;
+        let vault_seed = &[
+            b"token_vault".as_ref(),
+            &ctx.accounts.valued_token_mint.key().to_bytes(),
+            &ctx.accounts.escrow_token_mint.key().to_bytes(),
+            &[ctx.bumps.vault_account],
+        ];
+        let vault_signer = &[&vault_seed[..]];
+
+        // Initialize token metadata
+        token_metadata_initialize(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TokenMetadataInitialize {
+                    token_program_id: ctx.accounts.token_program.to_account_info(),
+                    mint: ctx.accounts.escrow_token_mint.to_account_info(),
+                    metadata: ctx.accounts.escrow_token_mint.to_account_info(),
+                    mint_authority: ctx.accounts.vault_account.to_account_info(),
+                    update_authority: ctx.accounts.vault_account.to_account_info(),
+                },
+                vault_signer,
+            ),
+            metadata.name.clone(),
+            metadata.symbol,
+            metadata.uri,
+        )?;
+
+        // Update the mint account to the minimum balance
+        update_account_lamports_to_minimum_balance(
+            ctx.accounts.escrow_token_mint.to_account_info(),
+            ctx.accounts.payer.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+        )?;
+
+        ctx.accounts.vault_account.creator = ctx.accounts.payer.key();
+        ctx.accounts.vault_account.valued_token_mint = ctx.accounts.valued_token_mint.key();
+        ctx.accounts.vault_account.escrow_token_mint = ctx.accounts.escrow_token_mint.key();
+        ctx.accounts.vault_account.app_id = metadata.app_id;
+        ctx.accounts.vault_account.valued_mint_config =
+            token_2022_validations::snapshot_mint_config(
+                &ctx.accounts.valued_token_mint.to_account_info(),
+            )?;
+
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            EscrowCreatedEvent {
+                version: EVENT_SCHEMA_VERSION,
+                creator: ctx.accounts.payer.key(),
+                valued_token_mint: ctx.accounts.valued_token_mint.key(),
+                escrow_token_mint: ctx.accounts.escrow_token_mint.key(),
+                vault_account: ctx.accounts.vault_account.key(),
+                app_id: ctx.accounts.vault_account.app_id.clone(),
+                actor: ctx.accounts.payer.key(),
+                time: clock.unix_timestamp as u64,
+                slot: clock.slot,
+            }
+        );
+
+        Ok(())
+    }
+
+    /// Initializes a new token with metadata which can not be transferred between users
+    pub fn init_non_transferable_escrow_token(
+        ctx: Context<InitNonTransferableEscrowToken>,
+        metadata: InitEscrowTokenParams,
+    ) -> Result<()> {
+        metadata_validations::validate_metadata(
+            metadata_validations::metadata_rules(&ctx.accounts.backend_data),
+            &metadata.name,
+            &metadata.symbol,
+            &metadata.uri,
+        )?;
         token_2022_validations::validate_token_extensions(
             &ctx.accounts.valued_token_mint.to_account_info(),
+            token_2022_validations::allowed_token_extensions(
+                &ctx.accounts.backend_data,
+                &ctx.accounts.vault_account,
+            ),
         )?;
         let vault_seed = &[
             b"token_vault".as_ref(),
@@ -74,6 +594,23 @@ pub mod vesting_contract {
             &[ctx.bumps.vault_account],
         ];
         let vault_signer = &[&vault_seed[..]];
+        let escrow_mint_seed = &[
+            b"escrow_mint".as_ref(),
+            &ctx.accounts.valued_token_mint.key().to_bytes(),
+            metadata.app_id.as_bytes(),
+            &[ctx.bumps.escrow_token_mint],
+        ];
+
+        // Create the escrow mint with the NonTransferable extension
+        create_non_transferable_escrow_mint(
+            &ctx.accounts.token_program,
+            &ctx.accounts.system_program,
+            ctx.accounts.payer.to_account_info(),
+            ctx.accounts.escrow_token_mint.to_account_info(),
+            &ctx.accounts.vault_account,
+            &[&escrow_mint_seed[..]],
+            ctx.accounts.valued_token_mint.decimals,
+        )?;
 
         // Initialize token metadata
         token_metadata_initialize(
@@ -93,191 +630,1666 @@ pub mod vesting_contract {
             metadata.uri,
         )?;
 
-        // Update the mint account to the minimum balance
-        update_account_lamports_to_minimum_balance(
-            ctx.accounts.escrow_token_mint.to_account_info(),
-            ctx.accounts.payer.to_account_info(),
-            ctx.accounts.system_program.to_account_info(),
-        )?;
+        // Update the mint account to the minimum balance
+        update_account_lamports_to_minimum_balance(
+            ctx.accounts.escrow_token_mint.to_account_info(),
+            ctx.accounts.payer.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+        )?;
+
+        ctx.accounts.vault_account.creator = ctx.accounts.payer.key();
+        ctx.accounts.vault_account.valued_token_mint = ctx.accounts.valued_token_mint.key();
+        ctx.accounts.vault_account.escrow_token_mint = ctx.accounts.escrow_token_mint.key();
+        ctx.accounts.vault_account.app_id = metadata.app_id;
+        ctx.accounts.vault_account.escrow_non_transferable = true;
+        ctx.accounts.vault_account.valued_mint_config =
+            token_2022_validations::snapshot_mint_config(
+                &ctx.accounts.valued_token_mint.to_account_info(),
+            )?;
+
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            EscrowCreatedEvent {
+                version: EVENT_SCHEMA_VERSION,
+                creator: ctx.accounts.payer.key(),
+                valued_token_mint: ctx.accounts.valued_token_mint.key(),
+                escrow_token_mint: ctx.accounts.escrow_token_mint.key(),
+                vault_account: ctx.accounts.vault_account.key(),
+                app_id: ctx.accounts.vault_account.app_id.clone(),
+                actor: ctx.accounts.payer.key(),
+                time: clock.unix_timestamp as u64,
+                slot: clock.slot,
+            }
+        );
+
+        Ok(())
+    }
+
+    pub fn init_vault_token_accounts(ctx: Context<InitVaultTokenAccounts>) -> Result<()> {
+        token_2022_validations::validate_token_extensions(
+            &ctx.accounts.valued_token_mint.to_account_info(),
+            token_2022_validations::allowed_token_extensions(
+                &ctx.accounts.backend_data,
+                &ctx.accounts.vault_account,
+            ),
+        )?;
+        token_2022_validations::validate_mint_config(
+            &ctx.accounts.valued_token_mint.to_account_info(),
+            &ctx.accounts.vault_account.valued_mint_config,
+        )?;
+
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            VaultAccountInitializedEvent {
+                version: EVENT_SCHEMA_VERSION,
+                vault_account: ctx.accounts.vault_account.key(),
+                valued_vault_token_account: ctx.accounts.valued_vault_token_account.key(),
+                escrow_vault_token_account: ctx.accounts.escrow_vault_token_account.key(),
+                actor: ctx.accounts.payer.key(),
+                valued_token_mint: ctx.accounts.vault_account.valued_token_mint,
+                escrow_token_mint: ctx.accounts.vault_account.escrow_token_mint,
+                time: clock.unix_timestamp as u64,
+                slot: clock.slot,
+            }
+        );
+        Ok(())
+    }
+
+    pub fn update_vault_config(
+        ctx: Context<UpdateVaultConfig>,
+        params: UpdateVaultConfigParams,
+    ) -> Result<()> {
+        let vault_account = &mut ctx.accounts.vault_account;
+
+        if let Some(sessions_transferable) = params.sessions_transferable {
+            vault_account.sessions_transferable = sessions_transferable;
+        }
+        if let Some(trusted_hook_programs) = params.trusted_hook_programs {
+            require!(
+                trusted_hook_programs.len() <= MAX_TRUSTED_HOOK_PROGRAMS,
+                VestingErrorCode::TooManyTrustedHookPrograms
+            );
+            vault_account.trusted_hook_programs = trusted_hook_programs;
+        }
+        if let Some(max_exchanged_amount) = params.max_exchanged_amount {
+            vault_account.max_exchanged_amount = if max_exchanged_amount > 0 {
+                Some(max_exchanged_amount)
+            } else {
+                None
+            };
+        }
+
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            VaultConfigUpdatedEvent {
+                version: EVENT_SCHEMA_VERSION,
+                vault_account: vault_account.key(),
+                sessions_transferable: vault_account.sessions_transferable,
+                trusted_hook_programs: vault_account.trusted_hook_programs.clone(),
+                max_exchanged_amount: vault_account.max_exchanged_amount,
+                actor: ctx.accounts.backend.key(),
+                valued_token_mint: vault_account.valued_token_mint,
+                escrow_token_mint: vault_account.escrow_token_mint,
+                time: clock.unix_timestamp as u64,
+                slot: clock.slot,
+            }
+        );
+        Ok(())
+    }
+
+    /// Freezes an escrow token account for compliance reasons
+    pub fn freeze_escrow_account(
+        ctx: Context<EscrowAccountFreeze>,
+        params: EscrowFreezeParams,
+    ) -> Result<()> {
+        require!(
+            is_program_admin(
+                &ctx.accounts.program_data,
+                &ctx.accounts.backend_data,
+                &ctx.accounts.admin.key()
+            ),
+            VestingErrorCode::UnathorizedToExecute
+        );
+        let vault_seed = &[
+            b"token_vault".as_ref(),
+            &ctx.accounts.valued_token_mint.key().to_bytes(),
+            &ctx.accounts.escrow_token_mint.key().to_bytes(),
+            &[ctx.bumps.vault_account],
+        ];
+        let vault_signer = &[&vault_seed[..]];
+
+        freeze_account(CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            FreezeAccount {
+                account: ctx.accounts.escrow_token_account.to_account_info(),
+                mint: ctx.accounts.escrow_token_mint.to_account_info(),
+                authority: ctx.accounts.vault_account.to_account_info(),
+            },
+            vault_signer,
+        ))?;
+
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            EscrowAccountFrozenEvent {
+                version: EVENT_SCHEMA_VERSION,
+                vault_account: ctx.accounts.vault_account.key(),
+                escrow_token_account: ctx.accounts.escrow_token_account.key(),
+                owner: ctx.accounts.escrow_token_account.owner,
+                actor: ctx.accounts.admin.key(),
+                reason: params.reason,
+                time: clock.unix_timestamp as u64,
+                valued_token_mint: ctx.accounts.valued_token_mint.key(),
+                escrow_token_mint: ctx.accounts.escrow_token_mint.key(),
+                amount: ctx.accounts.escrow_token_account.amount,
+                slot: clock.slot,
+            }
+        );
+        Ok(())
+    }
+
+    /// Thaws a previously frozen escrow token account
+    pub fn thaw_escrow_account(
+        ctx: Context<EscrowAccountFreeze>,
+        params: EscrowFreezeParams,
+    ) -> Result<()> {
+        require!(
+            is_program_admin(
+                &ctx.accounts.program_data,
+                &ctx.accounts.backend_data,
+                &ctx.accounts.admin.key()
+            ),
+            VestingErrorCode::UnathorizedToExecute
+        );
+        let vault_seed = &[
+            b"token_vault".as_ref(),
+            &ctx.accounts.valued_token_mint.key().to_bytes(),
+            &ctx.accounts.escrow_token_mint.key().to_bytes(),
+            &[ctx.bumps.vault_account],
+        ];
+        let vault_signer = &[&vault_seed[..]];
+
+        thaw_account(CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            ThawAccount {
+                account: ctx.accounts.escrow_token_account.to_account_info(),
+                mint: ctx.accounts.escrow_token_mint.to_account_info(),
+                authority: ctx.accounts.vault_account.to_account_info(),
+            },
+            vault_signer,
+        ))?;
+
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            EscrowAccountThawedEvent {
+                version: EVENT_SCHEMA_VERSION,
+                vault_account: ctx.accounts.vault_account.key(),
+                escrow_token_account: ctx.accounts.escrow_token_account.key(),
+                owner: ctx.accounts.escrow_token_account.owner,
+                actor: ctx.accounts.admin.key(),
+                reason: params.reason,
+                time: clock.unix_timestamp as u64,
+                valued_token_mint: ctx.accounts.valued_token_mint.key(),
+                escrow_token_mint: ctx.accounts.escrow_token_mint.key(),
+                amount: ctx.accounts.escrow_token_account.amount,
+                slot: clock.slot,
+            }
+        );
+        Ok(())
+    }
+
+    /// Decommissions a vault without active sessions, closing its token accounts and escrow mint.
+    /// Settled sessions still count as active until they are closed with `close_session`
+    pub fn close_vault<'info>(ctx: Context<'_, '_, 'info, 'info, CloseVault<'info>>) -> Result<()> {
+        token_2022_validations::validate_transfer_hook(
+            &ctx.accounts.valued_token_mint.to_account_info(),
+            &ctx.accounts.vault_account.trusted_hook_programs,
+        )?;
+        require!(
+            ctx.accounts.vault_account.active_sessions == 0,
+            VestingErrorCode::VaultHasActiveSessions
+        );
+        // Every escrow token must be back in the vault, otherwise users still have claims on it
+        let escrow_vault_balance = ctx.accounts.escrow_vault_token_account.amount;
+        require!(
+            ctx.accounts.escrow_token_mint.supply == escrow_vault_balance,
+            VestingErrorCode::EscrowSupplyOutstanding
+        );
+
+        let vault_seed = &[
+            b"token_vault".as_ref(),
+            &ctx.accounts.valued_token_mint.key().to_bytes(),
+            &ctx.accounts.escrow_token_mint.key().to_bytes(),
+            &[ctx.bumps.vault_account],
+        ];
+        let vault_signer = &[&vault_seed[..]];
+
+        // Sweep leftover valued dust to the creator
+        let valued_amount_swept = ctx.accounts.valued_vault_token_account.amount;
+        if valued_amount_swept > 0 {
+            transfer_tokens(
+                TokenTransferAccounts {
+                    from: &ctx.accounts.valued_vault_token_account,
+                    mint: &ctx.accounts.valued_token_mint,
+                    to: &ctx.accounts.creator_valued_token_account,
+                    token_program: &ctx.accounts.valued_token_program,
+                    authority: ctx.accounts.vault_account.to_account_info(),
+                    pda_signer: Some(vault_signer),
+                    remaining_accounts: ctx.remaining_accounts,
+                },
+                valued_amount_swept,
+            )?;
+        }
+
+        // Burn the escrow held by the vault so the escrow supply goes back to zero
+        if escrow_vault_balance > 0 {
+            burn(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Burn {
+                        mint: ctx.accounts.escrow_token_mint.to_account_info(),
+                        from: ctx.accounts.escrow_vault_token_account.to_account_info(),
+                        authority: ctx.accounts.vault_account.to_account_info(),
+                    },
+                    vault_signer,
+                ),
+                escrow_vault_balance,
+            )?;
+        }
+
+        // Fees withheld by fee bearing valued mints would block closing the vault token account
+        harvest_withheld_fees(
+            ctx.accounts.valued_token_program.to_account_info(),
+            ctx.accounts.valued_token_mint.to_account_info(),
+            ctx.accounts.valued_vault_token_account.to_account_info(),
+        )?;
+
+        // Close both vault token accounts
+        close_account(CpiContext::new_with_signer(
+            ctx.accounts.valued_token_program.to_account_info(),
+            CloseAccount {
+                account: ctx.accounts.valued_vault_token_account.to_account_info(),
+                destination: ctx.accounts.creator.to_account_info(),
+                authority: ctx.accounts.vault_account.to_account_info(),
+            },
+            vault_signer,
+        ))?;
+        close_account(CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            CloseAccount {
+                account: ctx.accounts.escrow_vault_token_account.to_account_info(),
+                destination: ctx.accounts.creator.to_account_info(),
+                authority: ctx.accounts.vault_account.to_account_info(),
+            },
+            vault_signer,
+        ))?;
+
+        // Close the escrow mint when it was created with the vault as close authority
+        let escrow_mint_closed =
+            mint_close_authority(&ctx.accounts.escrow_token_mint.to_account_info())?
+                == Some(ctx.accounts.vault_account.key());
+        if escrow_mint_closed {
+            close_account(CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                CloseAccount {
+                    account: ctx.accounts.escrow_token_mint.to_account_info(),
+                    destination: ctx.accounts.creator.to_account_info(),
+                    authority: ctx.accounts.vault_account.to_account_info(),
+                },
+                vault_signer,
+            ))?;
+        }
+
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            VaultClosedEvent {
+                version: EVENT_SCHEMA_VERSION,
+                vault_account: ctx.accounts.vault_account.key(),
+                creator: ctx.accounts.creator.key(),
+                valued_amount_swept,
+                escrow_amount_burned: escrow_vault_balance,
+                escrow_mint_closed,
+                actor: ctx.accounts.creator.key(),
+                valued_token_mint: ctx.accounts.valued_token_mint.key(),
+                escrow_token_mint: ctx.accounts.escrow_token_mint.key(),
+                time: clock.unix_timestamp as u64,
+                slot: clock.slot,
+            }
+        );
+
+        // Vault account is closed to the creator by the accounts constraints
+        Ok(())
+    }
+
+    pub fn change_escrow_metadata(
+        ctx: Context<ChangeEscrowMetadataAccounts>,
+        metadata: ChangeEscrowMetadataParams,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts
+                .vault_account
+                .metadata_update_authority
+                .is_none(),
+            VestingErrorCode::EscrowMetadataAuthorityDelegated
+        );
+        let field_to_update = escrow_metadata_field(
+            metadata_validations::metadata_rules(&ctx.accounts.backend_data),
+            &metadata,
+        )?;
+        let vault_seed = &[
+            b"token_vault".as_ref(),
+            &ctx.accounts.valued_token_mint.key().to_bytes(),
+            &ctx.accounts.escrow_token_mint.key().to_bytes(),
+            &[ctx.bumps.vault_account],
+        ];
+        let vault_signer = &[&vault_seed[..]];
+
+        token_metadata_update_field(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TokenMetadataUpdateField {
+                    token_program_id: ctx.accounts.token_program.to_account_info(),
+                    metadata: ctx.accounts.escrow_token_mint.to_account_info(),
+                    update_authority: ctx.accounts.vault_account.to_account_info(),
+                },
+                vault_signer,
+            ),
+            field_to_update,
+            metadata.value.clone(),
+        )?;
+
+        // Rebalance the mint account to the minimum balance, the metadata may have grown or shrunk
+        let refund_destination = rent_refund_destination(
+            &ctx.accounts.backend_data,
+            ctx.accounts.rent_refund_destination.as_ref(),
+            ctx.accounts.payer.to_account_info(),
+        )?;
+        let lamports_refunded = rebalance_account_lamports(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            ctx.accounts.escrow_token_mint.to_account_info(),
+            ctx.accounts.payer.to_account_info(),
+            refund_destination,
+            ctx.accounts.vault_account.to_account_info(),
+            vault_signer,
+        )?;
+
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            EscrowMetadataChangedEvent {
+                version: EVENT_SCHEMA_VERSION,
+                escrow_token_mint: ctx.accounts.escrow_token_mint.key(),
+                field_updated: metadata.param_key.clone(),
+                value: metadata.value.clone(),
+                lamports_refunded,
+                actor: ctx.accounts.payer.key(),
+                vault_account: ctx.accounts.vault_account.key(),
+                valued_token_mint: ctx.accounts.valued_token_mint.key(),
+                time: clock.unix_timestamp as u64,
+                slot: clock.slot,
+            }
+        );
+        Ok(())
+    }
+
+    /// Applies several escrow metadata field updates atomically, rebalancing the escrow mint
+    /// rent once after all of them
+    pub fn change_escrow_metadata_batch(
+        ctx: Context<ChangeEscrowMetadataAccounts>,
+        params: ChangeEscrowMetadataBatchParams,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts
+                .vault_account
+                .metadata_update_authority
+                .is_none(),
+            VestingErrorCode::EscrowMetadataAuthorityDelegated
+        );
+        require!(
+            !params.updates.is_empty() && params.updates.len() <= MAX_METADATA_UPDATES_PER_BATCH,
+            VestingErrorCode::InvalidMetadataBatchSize
+        );
+        // Validate every update before touching the mint
+        let metadata_rules = metadata_validations::metadata_rules(&ctx.accounts.backend_data);
+        let fields_to_update = params
+            .updates
+            .iter()
+            .map(|update| escrow_metadata_field(metadata_rules, update))
+            .collect::<Result<Vec<Field>>>()?;
+
+        let vault_seed = &[
+            b"token_vault".as_ref(),
+            &ctx.accounts.valued_token_mint.key().to_bytes(),
+            &ctx.accounts.escrow_token_mint.key().to_bytes(),
+            &[ctx.bumps.vault_account],
+        ];
+        let vault_signer = &[&vault_seed[..]];
+
+        // Fields updates are applied in order, a repeated key keeps its last value
+        for (field_to_update, update) in fields_to_update.into_iter().zip(params.updates.iter()) {
+            token_metadata_update_field(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    TokenMetadataUpdateField {
+                        token_program_id: ctx.accounts.token_program.to_account_info(),
+                        metadata: ctx.accounts.escrow_token_mint.to_account_info(),
+                        update_authority: ctx.accounts.vault_account.to_account_info(),
+                    },
+                    vault_signer,
+                ),
+                field_to_update,
+                update.value.clone(),
+            )?;
+        }
+
+        let refund_destination = rent_refund_destination(
+            &ctx.accounts.backend_data,
+            ctx.accounts.rent_refund_destination.as_ref(),
+            ctx.accounts.payer.to_account_info(),
+        )?;
+        let lamports_refunded = rebalance_account_lamports(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            ctx.accounts.escrow_token_mint.to_account_info(),
+            ctx.accounts.payer.to_account_info(),
+            refund_destination,
+            ctx.accounts.vault_account.to_account_info(),
+            vault_signer,
+        )?;
+
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            EscrowMetadataBatchChangedEvent {
+                version: EVENT_SCHEMA_VERSION,
+                escrow_token_mint: ctx.accounts.escrow_token_mint.key(),
+                updates: params.updates,
+                lamports_refunded,
+                actor: ctx.accounts.payer.key(),
+                vault_account: ctx.accounts.vault_account.key(),
+                valued_token_mint: ctx.accounts.valued_token_mint.key(),
+                time: clock.unix_timestamp as u64,
+                slot: clock.slot,
+            }
+        );
+        Ok(())
+    }
+
+    /// Removes an additional metadata field of an escrow token, refunding the freed rent
+    pub fn remove_escrow_metadata_key(
+        ctx: Context<ChangeEscrowMetadataAccounts>,
+        params: RemoveEscrowMetadataKeyParams,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts
+                .vault_account
+                .metadata_update_authority
+                .is_none(),
+            VestingErrorCode::EscrowMetadataAuthorityDelegated
+        );
+        // Standard fields can be changed but not removed
+        require!(
+            !matches!(params.key.as_str(), "name" | "symbol" | "uri"),
+            VestingErrorCode::InvalidMeta
+        );
+        require!(
+            !params.key.is_empty() && params.key.len() <= MAX_METADATA_KEY_LENGTH,
+            VestingErrorCode::InvalidMetadataKeyLength
+        );
+        let vault_seed = &[
+            b"token_vault".as_ref(),
+            &ctx.accounts.valued_token_mint.key().to_bytes(),
+            &ctx.accounts.escrow_token_mint.key().to_bytes(),
+            &[ctx.bumps.vault_account],
+        ];
+        let vault_signer = &[&vault_seed[..]];
+
+        invoke_signed(
+            &remove_key(
+                ctx.accounts.token_program.key,
+                &ctx.accounts.escrow_token_mint.key(),
+                &ctx.accounts.vault_account.key(),
+                params.key.clone(),
+                false,
+            ),
+            &[
+                ctx.accounts.escrow_token_mint.to_account_info(),
+                ctx.accounts.vault_account.to_account_info(),
+                ctx.accounts.token_program.to_account_info(),
+            ],
+            vault_signer,
+        )?;
+
+        // The mint account shrank, return the rent it does not need anymore
+        let refund_destination = rent_refund_destination(
+            &ctx.accounts.backend_data,
+            ctx.accounts.rent_refund_destination.as_ref(),
+            ctx.accounts.payer.to_account_info(),
+        )?;
+        let lamports_refunded = rebalance_account_lamports(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            ctx.accounts.escrow_token_mint.to_account_info(),
+            ctx.accounts.payer.to_account_info(),
+            refund_destination,
+            ctx.accounts.vault_account.to_account_info(),
+            vault_signer,
+        )?;
+
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            EscrowMetadataKeyRemovedEvent {
+                version: EVENT_SCHEMA_VERSION,
+                escrow_token_mint: ctx.accounts.escrow_token_mint.key(),
+                key: params.key,
+                lamports_refunded,
+                actor: ctx.accounts.payer.key(),
+                vault_account: ctx.accounts.vault_account.key(),
+                valued_token_mint: ctx.accounts.valued_token_mint.key(),
+                time: clock.unix_timestamp as u64,
+                slot: clock.slot,
+            }
+        );
+        Ok(())
+    }
+
+    /// Rotates the escrow token metadata update authority and metadata pointer authority to
+    /// another key, or back to the vault. Requires both the vault creator and an admin to sign,
+    /// plus the current authority when it was already handed off
+    pub fn set_escrow_metadata_authority(
+        ctx: Context<SetEscrowMetadataAuthority>,
+        params: SetEscrowMetadataAuthorityParams,
+    ) -> Result<()> {
+        require!(
+            is_program_admin(
+                &ctx.accounts.program_data,
+                &ctx.accounts.backend_data,
+                &ctx.accounts.admin.key()
+            ),
+            VestingErrorCode::UnathorizedToExecute
+        );
+        let vault_key = ctx.accounts.vault_account.key();
+        let vault_seed = &[
+            b"token_vault".as_ref(),
+            &ctx.accounts.valued_token_mint.key().to_bytes(),
+            &ctx.accounts.escrow_token_mint.key().to_bytes(),
+            &[ctx.bumps.vault_account],
+        ];
+        let vault_signer = &[&vault_seed[..]];
+        // Handing an authority to the vault itself is tracked as None
+        let new_update_authority = params.update_authority.filter(|key| *key != vault_key);
+        let new_metadata_pointer_authority = params
+            .metadata_pointer_authority
+            .filter(|key| *key != vault_key);
+
+        if new_update_authority != ctx.accounts.vault_account.metadata_update_authority {
+            let current_authority = match ctx.accounts.vault_account.metadata_update_authority {
+                Some(_) => ctx
+                    .accounts
+                    .current_update_authority
+                    .as_ref()
+                    .ok_or(VestingErrorCode::InvalidMetadataAuthority)?
+                    .to_account_info(),
+                None => ctx.accounts.vault_account.to_account_info(),
+            };
+            let new_authority =
+                OptionalNonZeroPubkey::try_from(Some(new_update_authority.unwrap_or(vault_key)))?;
+            invoke_signed(
+                &update_authority(
+                    ctx.accounts.token_program.key,
+                    &ctx.accounts.escrow_token_mint.key(),
+                    current_authority.key,
+                    new_authority,
+                ),
+                &[
+                    ctx.accounts.escrow_token_mint.to_account_info(),
+                    current_authority,
+                    ctx.accounts.token_program.to_account_info(),
+                ],
+                vault_signer,
+            )?;
+            ctx.accounts.vault_account.metadata_update_authority = new_update_authority;
+        }
+
+        if new_metadata_pointer_authority != ctx.accounts.vault_account.metadata_pointer_authority {
+            let current_authority = match ctx.accounts.vault_account.metadata_pointer_authority {
+                Some(_) => ctx
+                    .accounts
+                    .current_metadata_pointer_authority
+                    .as_ref()
+                    .ok_or(VestingErrorCode::InvalidMetadataAuthority)?
+                    .to_account_info(),
+                None => ctx.accounts.vault_account.to_account_info(),
+            };
+            set_authority(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    SetAuthority {
+                        current_authority,
+                        account_or_mint: ctx.accounts.escrow_token_mint.to_account_info(),
+                    },
+                    vault_signer,
+                ),
+                AuthorityType::MetadataPointer,
+                Some(new_metadata_pointer_authority.unwrap_or(vault_key)),
+            )?;
+            ctx.accounts.vault_account.metadata_pointer_authority = new_metadata_pointer_authority;
+        }
+
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            EscrowMetadataAuthorityChangedEvent {
+                version: EVENT_SCHEMA_VERSION,
+                vault_account: vault_key,
+                escrow_token_mint: ctx.accounts.escrow_token_mint.key(),
+                update_authority: new_update_authority.unwrap_or(vault_key),
+                metadata_pointer_authority: new_metadata_pointer_authority.unwrap_or(vault_key),
+                actor: ctx.accounts.admin.key(),
+                valued_token_mint: ctx.accounts.valued_token_mint.key(),
+                time: clock.unix_timestamp as u64,
+                slot: clock.slot,
+            }
+        );
+        Ok(())
+    }
+
+    /// Exchanges tokens between user and dual auth accounts
+    pub fn exchange<'info>(
+        ctx: Context<'_, '_, 'info, 'info, Exchange<'info>>,
+        amount: u64,
+    ) -> Result<()> {
+        require!(amount > 0, VestingErrorCode::MinimumAmountHigherZero);
+        require!(
+            ctx.accounts.user_valued_token_account.amount >= amount,
+            VestingErrorCode::InsufficientFunds
+        );
+        token_2022_validations::validate_token_extensions(
+            &ctx.accounts.valued_token_mint.to_account_info(),
+            token_2022_validations::allowed_token_extensions(
+                &ctx.accounts.backend_data,
+                &ctx.accounts.vault_account,
+            ),
+        )?;
+        token_2022_validations::validate_transfer_hook(
+            &ctx.accounts.valued_token_mint.to_account_info(),
+            &ctx.accounts.vault_account.trusted_hook_programs,
+        )?;
+        token_2022_validations::validate_mint_config(
+            &ctx.accounts.valued_token_mint.to_account_info(),
+            &ctx.accounts.vault_account.valued_mint_config,
+        )?;
+
+        // New deposits would be diluted by the shortfall of a degraded vault
+        let vault_valued_balance = ctx.accounts.valued_vault_token_account.amount;
+        require!(
+            vault_valued_balance >= ctx.accounts.vault_account.expected_valued_balance,
+            VestingErrorCode::VaultDegraded
+        );
+
+        // Transfer tokens from user to vault valued token account
+        transfer_tokens(
+            TokenTransferAccounts {
+                from: &ctx.accounts.user_valued_token_account,
+                mint: &ctx.accounts.valued_token_mint,
+                to: &ctx.accounts.valued_vault_token_account,
+                token_program: &ctx.accounts.valued_token_program,
+                authority: ctx.accounts.user.to_account_info(),
+                pda_signer: None,
+                remaining_accounts: ctx.remaining_accounts,
+            },
+            amount,
+        )?;
+
+        // Escrow is backed by what the vault actually received, which is lower than the sent
+        // amount for mints charging transfer fees
+        ctx.accounts.valued_vault_token_account.reload()?;
+        let amount_received = ctx
+            .accounts
+            .valued_vault_token_account
+            .amount
+            .checked_sub(vault_valued_balance)
+            .ok_or(VestingErrorCode::ArithmeticOverflow)?;
+        require!(
+            amount_received > 0,
+            VestingErrorCode::MinimumAmountHigherZero
+        );
+        ctx.accounts.vault_account.expected_valued_balance = ctx
+            .accounts
+            .vault_account
+            .expected_valued_balance
+            .checked_add(amount_received)
+            .ok_or(VestingErrorCode::ArithmeticOverflow)?;
+
+        // Enforce the cap of valued tokens allowed into the vault
+        let vault_account = &mut ctx.accounts.vault_account;
+        vault_account.total_exchanged_amount = vault_account
+            .total_exchanged_amount
+            .checked_add(amount_received)
+            .ok_or(VestingErrorCode::ArithmeticOverflow)?;
+        if let Some(max_exchanged_amount) = vault_account.max_exchanged_amount {
+            require!(
+                vault_account.total_exchanged_amount <= max_exchanged_amount,
+                VestingErrorCode::SupplyCapReached
+            );
+        }
+
+        // Transfer and/or mint equivalent tokens from escrow vault account to the user token account
+        transfer_escrow_from_vault(
+            EscrowTransferAccounts {
+                token_program: &ctx.accounts.token_program,
+                vault_account: &ctx.accounts.vault_account,
+                escrow_vault_token_account: &ctx.accounts.escrow_vault_token_account,
+                user_escrow_token_account: &ctx.accounts.user_escrow_token_account,
+                escrow_token_mint: &ctx.accounts.escrow_token_mint,
+            },
+            ctx.bumps.vault_account,
+            amount_received,
+        )?;
+
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            ExchangedEvent {
+                version: EVENT_SCHEMA_VERSION,
+                vault_account: ctx.accounts.vault_account.key(),
+                amount: amount_received,
+                user: ctx.accounts.user.key(),
+                actor: ctx.accounts.user.key(),
+                valued_token_mint: ctx.accounts.valued_token_mint.key(),
+                escrow_token_mint: ctx.accounts.escrow_token_mint.key(),
+                valued_vault_balance_before: vault_valued_balance,
+                valued_vault_balance_after: ctx.accounts.valued_vault_token_account.amount,
+                time: clock.unix_timestamp as u64,
+                slot: clock.slot,
+            }
+        );
+
+        Ok(())
+    }
+
+    /// Creates a new vesting session
+    pub fn create_vesting_session(ctx: Context<CreateVestingSession>, amount: u64) -> Result<()> {
+        let vesting_account = &mut ctx.accounts.vesting_sessions_account;
+        let vesting_session = &mut ctx.accounts.vesting_session_account;
+
+        // Check if the amount is sufficient (at the minimum value)
+        require!(
+            amount >= MIN_DIVISIBLE_BY_VESTING_PERIOD,
+            VestingErrorCode::MinimumAmountNotMet
+        );
+
+        // Initialize vesting session with details
+        vesting_session.id = next_session_id(vesting_account, &ctx.accounts.session_ids_account);
+        vesting_session.user = ctx.accounts.user.key();
+        vesting_session.vesting_sessions_account = vesting_account.key();
+        vesting_session.amount = amount;
+        vesting_session.amount_withdrawn = 0;
+        vesting_session.start_date = Clock::get()?.unix_timestamp as u64;
+        vesting_session.last_withdraw_at = 0;
+        vesting_session.cancelled_at = 0;
+        vesting_session.position_mint = None;
+        vesting_session.amount_owed = 0;
+        vesting_session.amount_unlocked = 0;
+
+        // Increment the session ID for the next vesting session
+        vesting_account.last_session_id = vesting_session.id + 1;
+        vesting_account.active_sessions = vesting_account
+            .active_sessions
+            .checked_add(1)
+            .ok_or(VestingErrorCode::ArithmeticOverflow)?;
+        vesting_account.user = ctx.accounts.user.key();
+
+        let vault_account = &mut ctx.accounts.vault_account;
+        vault_account.active_sessions = vault_account
+            .active_sessions
+            .checked_add(1)
+            .ok_or(VestingErrorCode::ArithmeticOverflow)?;
+
+        // Transfer tokens from the user escrow account back to vault escrow account
+        let user_escrow_balance_before = ctx.accounts.user_escrow_token_account.amount;
+        transfer_escrow_to_vault(
+            EscrowTransferAccounts {
+                token_program: &ctx.accounts.token_program,
+                vault_account: &ctx.accounts.vault_account,
+                escrow_vault_token_account: &ctx.accounts.escrow_vault_token_account,
+                user_escrow_token_account: &ctx.accounts.user_escrow_token_account,
+                escrow_token_mint: &ctx.accounts.escrow_token_mint,
+            },
+            ctx.accounts.user.to_account_info(),
+            amount,
+        )?;
+        ctx.accounts.user_escrow_token_account.reload()?;
+
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            CreatedVestingSessionEvent {
+                version: EVENT_SCHEMA_VERSION,
+                vault_account: ctx.accounts.vault_account.key(),
+                vesting_session: vesting_session.key(),
+                user: ctx.accounts.user.key(),
+                amount,
+                actor: ctx.accounts.user.key(),
+                valued_token_mint: ctx.accounts.valued_token_mint.key(),
+                escrow_token_mint: ctx.accounts.escrow_token_mint.key(),
+                session_id: vesting_session.id,
+                start_date: vesting_session.start_date,
+                user_escrow_balance_before,
+                user_escrow_balance_after: ctx.accounts.user_escrow_token_account.amount,
+                time: clock.unix_timestamp as u64,
+                slot: clock.slot,
+            }
+        );
+
+        Ok(())
+    }
+
+    /// Creates a new vesting session represented by a non-fungible position token,
+    /// the holder of the position token is the one allowed to withdraw from the session
+    pub fn create_tokenized_vesting_session(
+        ctx: Context<CreateTokenizedVestingSession>,
+        params: CreateTokenizedVestingSessionParams,
+    ) -> Result<()> {
+        metadata_validations::validate_position_metadata(
+            metadata_validations::metadata_rules(&ctx.accounts.backend_data),
+            &params.name,
+            &params.symbol,
+            &params.uri,
+        )?;
+        let vesting_account = &mut ctx.accounts.vesting_sessions_account;
+        let vesting_session = &mut ctx.accounts.vesting_session_account;
+        let amount = params.amount;
+
+        // Check if the amount is sufficient (at the minimum value)
+        require!(
+            amount >= MIN_DIVISIBLE_BY_VESTING_PERIOD,
+            VestingErrorCode::MinimumAmountNotMet
+        );
+
+        // Initialize vesting session with details
+        vesting_session.id = next_session_id(vesting_account, &ctx.accounts.session_ids_account);
+        vesting_session.user = ctx.accounts.user.key();
+        vesting_session.vesting_sessions_account = vesting_account.key();
+        vesting_session.amount = amount;
+        vesting_session.amount_withdrawn = 0;
+        vesting_session.start_date = Clock::get()?.unix_timestamp as u64;
+        vesting_session.last_withdraw_at = 0;
+        vesting_session.cancelled_at = 0;
+        vesting_session.position_mint = Some(ctx.accounts.position_mint.key());
+        vesting_session.amount_owed = 0;
+        vesting_session.amount_unlocked = 0;
+
+        // Increment the session ID for the next vesting session
+        vesting_account.last_session_id = vesting_session.id + 1;
+        vesting_account.active_sessions = vesting_account
+            .active_sessions
+            .checked_add(1)
+            .ok_or(VestingErrorCode::ArithmeticOverflow)?;
+        vesting_account.user = ctx.accounts.user.key();
+
+        let vault_account = &mut ctx.accounts.vault_account;
+        vault_account.active_sessions = vault_account
+            .active_sessions
+            .checked_add(1)
+            .ok_or(VestingErrorCode::ArithmeticOverflow)?;
+
+        // Transfer tokens from the user escrow account back to vault escrow account
+        let user_escrow_balance_before = ctx.accounts.user_escrow_token_account.amount;
+        transfer_escrow_to_vault(
+            EscrowTransferAccounts {
+                token_program: &ctx.accounts.token_program,
+                vault_account: &ctx.accounts.vault_account,
+                escrow_vault_token_account: &ctx.accounts.escrow_vault_token_account,
+                user_escrow_token_account: &ctx.accounts.user_escrow_token_account,
+                escrow_token_mint: &ctx.accounts.escrow_token_mint,
+            },
+            ctx.accounts.user.to_account_info(),
+            amount,
+        )?;
+        ctx.accounts.user_escrow_token_account.reload()?;
+
+        let vault_seed = &[
+            b"token_vault".as_ref(),
+            &ctx.accounts.valued_token_mint.key().to_bytes(),
+            &ctx.accounts.escrow_token_mint.key().to_bytes(),
+            &[ctx.bumps.vault_account],
+        ];
+        let vault_signer = &[&vault_seed[..]];
+
+        // Initialize position token metadata
+        token_metadata_initialize(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TokenMetadataInitialize {
+                    token_program_id: ctx.accounts.token_program.to_account_info(),
+                    mint: ctx.accounts.position_mint.to_account_info(),
+                    metadata: ctx.accounts.position_mint.to_account_info(),
+                    mint_authority: ctx.accounts.vault_account.to_account_info(),
+                    update_authority: ctx.accounts.vault_account.to_account_info(),
+                },
+                vault_signer,
+            ),
+            params.name,
+            params.symbol,
+            params.uri,
+        )?;
+
+        // Update the mint account to the minimum balance
+        update_account_lamports_to_minimum_balance(
+            ctx.accounts.position_mint.to_account_info(),
+            ctx.accounts.user.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+        )?;
+
+        // Mint the single position token to the user and remove the mint authority
+        mint_to(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                MintTo {
+                    mint: ctx.accounts.position_mint.to_account_info(),
+                    to: ctx.accounts.user_position_token_account.to_account_info(),
+                    authority: ctx.accounts.vault_account.to_account_info(),
+                },
+                vault_signer,
+            ),
+            1,
+        )?;
+        set_authority(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                SetAuthority {
+                    current_authority: ctx.accounts.vault_account.to_account_info(),
+                    account_or_mint: ctx.accounts.position_mint.to_account_info(),
+                },
+                vault_signer,
+            ),
+            AuthorityType::MintTokens,
+            None,
+        )?;
+
+        let vesting_session = &ctx.accounts.vesting_session_account;
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            CreatedVestingSessionEvent {
+                version: EVENT_SCHEMA_VERSION,
+                vault_account: ctx.accounts.vault_account.key(),
+                vesting_session: vesting_session.key(),
+                user: ctx.accounts.user.key(),
+                amount,
+                actor: ctx.accounts.user.key(),
+                valued_token_mint: ctx.accounts.valued_token_mint.key(),
+                escrow_token_mint: ctx.accounts.escrow_token_mint.key(),
+                session_id: vesting_session.id,
+                start_date: vesting_session.start_date,
+                user_escrow_balance_before,
+                user_escrow_balance_after: ctx.accounts.user_escrow_token_account.amount,
+                time: clock.unix_timestamp as u64,
+                slot: clock.slot,
+            }
+        );
+        emit_event!(
+            ctx,
+            VestingPositionMintedEvent {
+                version: EVENT_SCHEMA_VERSION,
+                vault_account: ctx.accounts.vault_account.key(),
+                vesting_session: vesting_session.key(),
+                position_mint: ctx.accounts.position_mint.key(),
+                holder: ctx.accounts.user.key(),
+                actor: ctx.accounts.user.key(),
+                valued_token_mint: ctx.accounts.valued_token_mint.key(),
+                escrow_token_mint: ctx.accounts.escrow_token_mint.key(),
+                session_id: vesting_session.id,
+                time: clock.unix_timestamp as u64,
+                slot: clock.slot,
+            }
+        );
+
+        Ok(())
+    }
+
+    /// Adds escrow tokens to an ongoing vesting session
+    pub fn session_top_up(ctx: Context<SessionTopUp>, amount: u64) -> Result<()> {
+        require!(amount > 0, VestingErrorCode::MinimumAmountHigherZero);
+        let vesting_session = &mut ctx.accounts.vesting_session_account;
+
+        require!(
+            vesting_session.cancelled_at == 0,
+            VestingErrorCode::InteractingWithCanceledSession
+        );
+
+        // Re-base the locked tokens with the new tranche starting now, released tokens stay unlocked
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp as u64;
+        let total_amount_before = vesting_session.amount;
+        fold_tranche_into_session(
+            vesting_session,
+            &Schedule {
+                amount,
+                start_date: current_time,
+                ..Default::default()
+            },
+        )?;
+
+        // Transfer tokens from the user escrow account back to vault escrow account
+        transfer_escrow_to_vault(
+            EscrowTransferAccounts {
+                token_program: &ctx.accounts.token_program,
+                vault_account: &ctx.accounts.vault_account,
+                escrow_vault_token_account: &ctx.accounts.escrow_vault_token_account,
+                user_escrow_token_account: &ctx.accounts.user_escrow_token_account,
+                escrow_token_mint: &ctx.accounts.escrow_token_mint,
+            },
+            ctx.accounts.user.to_account_info(),
+            amount,
+        )?;
+
+        emit_event!(
+            ctx,
+            SessionToppedUpEvent {
+                version: EVENT_SCHEMA_VERSION,
+                vault_account: ctx.accounts.vault_account.key(),
+                vesting_session: vesting_session.key(),
+                user: ctx.accounts.user.key(),
+                amount,
+                total_amount: vesting_session.amount,
+                start_date: vesting_session.start_date,
+                time: current_time,
+                actor: ctx.accounts.user.key(),
+                valued_token_mint: ctx.accounts.vault_account.valued_token_mint,
+                escrow_token_mint: ctx.accounts.vault_account.escrow_token_mint,
+                session_id: vesting_session.id,
+                total_amount_before,
+                slot: clock.slot,
+            }
+        );
+
+        Ok(())
+    }
+
+    /// Merges other vesting sessions of the same collection into a session, closing them
+    pub fn merge_sessions<'info>(
+        ctx: Context<'_, '_, 'info, 'info, MergeSessions<'info>>,
+    ) -> Result<()> {
+        require!(
+            !ctx.remaining_accounts.is_empty()
+                && ctx.remaining_accounts.len() <= MAX_SESSIONS_PER_MERGE,
+            VestingErrorCode::InvalidSessionsToMerge
+        );
+        let vesting_session = &mut ctx.accounts.vesting_session_account;
+
+        require!(
+            vesting_session.cancelled_at == 0,
+            VestingErrorCode::InteractingWithCanceledSession
+        );
+
+        let total_amount_before = vesting_session.amount;
+        let mut merged_sessions: Vec<Pubkey> = Vec::with_capacity(ctx.remaining_accounts.len());
+        for session_info in ctx.remaining_accounts.iter() {
+            require!(
+                session_info.key() != vesting_session.key()
+                    && !merged_sessions.contains(&session_info.key()),
+                VestingErrorCode::InvalidSessionsToMerge
+            );
+
+            let merged_session = Account::<VestingSession>::try_from(session_info)?;
+            require!(
+                merged_session.user == ctx.accounts.user.key()
+                    && merged_session.vesting_sessions_account
+                        == ctx.accounts.vesting_sessions_account.key(),
+                VestingErrorCode::UnathorizedToExecute
+            );
+            require!(
+                merged_session.cancelled_at == 0,
+                VestingErrorCode::InteractingWithCanceledSession
+            );
+            require!(
+                merged_session.position_mint.is_none(),
+                VestingErrorCode::SessionIsTokenized
+            );
+
+            // Re-base the locked tokens of the merged session, its released tokens stay claimable
+            fold_tranche_into_session(vesting_session, &Schedule::from(&*merged_session))?;
+            // Valued tokens owed to the merged session are owed to the combined one
+            vesting_session.amount_owed = vesting_session
+                .amount_owed
+                .checked_add(merged_session.amount_owed)
+                .ok_or(VestingErrorCode::ArithmeticOverflow)?;
+
+            // Close the merged session and refund its rent to the user
+            merged_session.close(ctx.accounts.user.to_account_info())?;
+            merged_sessions.push(session_info.key());
+        }
+
+        let vesting_account = &mut ctx.accounts.vesting_sessions_account;
+        vesting_account.active_sessions = vesting_account
+            .active_sessions
+            .checked_sub(merged_sessions.len() as u64)
+            .ok_or(VestingErrorCode::ArithmeticOverflow)?;
+        let vault_account = &mut ctx.accounts.vault_account;
+        vault_account.active_sessions = vault_account
+            .active_sessions
+            .checked_sub(merged_sessions.len() as u64)
+            .ok_or(VestingErrorCode::ArithmeticOverflow)?;
+
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            SessionsMergedEvent {
+                version: EVENT_SCHEMA_VERSION,
+                vault_account: ctx.accounts.vault_account.key(),
+                vesting_session: vesting_session.key(),
+                user: ctx.accounts.user.key(),
+                merged_sessions,
+                total_amount: vesting_session.amount,
+                amount_withdrawn: vesting_session.amount_withdrawn,
+                start_date: vesting_session.start_date,
+                time: clock.unix_timestamp as u64,
+                actor: ctx.accounts.user.key(),
+                valued_token_mint: ctx.accounts.vault_account.valued_token_mint,
+                escrow_token_mint: ctx.accounts.vault_account.escrow_token_mint,
+                session_id: vesting_session.id,
+                total_amount_before,
+                slot: clock.slot,
+                amount_unlocked: vesting_session.amount_unlocked,
+            }
+        );
+
+        Ok(())
+    }
+
+    /// Carves part of the remaining amount of a vesting session off into a new session
+    pub fn split_session(ctx: Context<SplitSession>, amount: u64) -> Result<()> {
+        let recipient = ctx.accounts.recipient.key();
+        require!(
+            recipient == ctx.accounts.user.key()
+                || ctx.accounts.vault_account.sessions_transferable,
+            VestingErrorCode::SessionsNotTransferable
+        );
+        let vesting_session = &mut ctx.accounts.vesting_session_account;
+        let recipient_vesting_account = &mut ctx.accounts.recipient_vesting_sessions_account;
+        let new_vesting_session = &mut ctx.accounts.new_vesting_session_account;
+
+        require!(
+            vesting_session.cancelled_at == 0,
+            VestingErrorCode::InteractingWithCanceledSession
+        );
 
-        ctx.accounts.vault_account.creator = ctx.accounts.payer.key();
-        ctx.accounts.vault_account.valued_token_mint = ctx.accounts.valued_token_mint.key();
-        ctx.accounts.vault_account.escrow_token_mint = ctx.accounts.escrow_token_mint.key();
-        ctx.accounts.vault_account.app_id = metadata.app_id;
+        // Split the amounts keeping the same schedule on both sessions
+        let amount_before = vesting_session.amount;
+        let (split_amount, split_amount_withdrawn) =
+            split_session_amounts(vesting_session, amount)?;
+        require!(
+            split_amount >= MIN_DIVISIBLE_BY_VESTING_PERIOD
+                && vesting_session.amount >= MIN_DIVISIBLE_BY_VESTING_PERIOD,
+            VestingErrorCode::MinimumAmountNotMet
+        );
+
+        // Initialize the new vesting session under the recipient collection
+        new_vesting_session.id = next_session_id(
+            recipient_vesting_account,
+            &ctx.accounts.recipient_session_ids_account,
+        );
+        new_vesting_session.user = recipient;
+        new_vesting_session.vesting_sessions_account = recipient_vesting_account.key();
+        new_vesting_session.amount = split_amount;
+        new_vesting_session.amount_withdrawn = split_amount_withdrawn;
+        new_vesting_session.start_date = vesting_session.start_date;
+        new_vesting_session.last_withdraw_at = vesting_session.last_withdraw_at;
+        new_vesting_session.cancelled_at = 0;
+        new_vesting_session.position_mint = None;
+        new_vesting_session.amount_owed = 0;
+        new_vesting_session.amount_unlocked = 0;
+
+        // Increment the session ID for the next vesting session of the recipient
+        recipient_vesting_account.last_session_id = new_vesting_session.id + 1;
+        recipient_vesting_account.active_sessions = recipient_vesting_account
+            .active_sessions
+            .checked_add(1)
+            .ok_or(VestingErrorCode::ArithmeticOverflow)?;
+        recipient_vesting_account.user = recipient;
+        let vault_account = &mut ctx.accounts.vault_account;
+        vault_account.active_sessions = vault_account
+            .active_sessions
+            .checked_add(1)
+            .ok_or(VestingErrorCode::ArithmeticOverflow)?;
 
-        emit!(EscrowCreatedEvent {
-            creator: ctx.accounts.payer.key(),
-            valued_token_mint: ctx.accounts.valued_token_mint.key(),
-            escrow_token_mint: ctx.accounts.escrow_token_mint.key(),
-            vault_account: ctx.accounts.vault_account.key(),
-            app_id: ctx.accounts.vault_account.app_id.clone(),
-        });
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            SessionSplitEvent {
+                version: EVENT_SCHEMA_VERSION,
+                vault_account: ctx.accounts.vault_account.key(),
+                vesting_session: vesting_session.key(),
+                new_vesting_session: new_vesting_session.key(),
+                user: ctx.accounts.user.key(),
+                recipient,
+                amount,
+                split_amount,
+                split_amount_withdrawn,
+                time: clock.unix_timestamp as u64,
+                actor: ctx.accounts.user.key(),
+                valued_token_mint: ctx.accounts.vault_account.valued_token_mint,
+                escrow_token_mint: ctx.accounts.vault_account.escrow_token_mint,
+                session_id: vesting_session.id,
+                new_session_id: new_vesting_session.id,
+                amount_before,
+                amount_after: vesting_session.amount,
+                slot: clock.slot,
+            }
+        );
 
         Ok(())
     }
 
-    pub fn init_vault_token_accounts(ctx: Context<InitVaultTokenAccounts>) -> Result<()> {
-        emit!(VaultAccountInitializedEvent {
-            vault_account: ctx.accounts.vault_account.key(),
-            valued_vault_token_account: ctx.accounts.valued_vault_token_account.key(),
-            escrow_vault_token_account: ctx.accounts.escrow_vault_token_account.key(),
-        });
+    /// Moves a vesting session into the sessions collection of another wallet
+    pub fn transfer_session(ctx: Context<TransferSession>) -> Result<()> {
+        let new_user = ctx.accounts.new_user.key();
+        require!(
+            new_user != ctx.accounts.user.key(),
+            VestingErrorCode::InvalidTransferRecipient
+        );
+        let vesting_session = &ctx.accounts.vesting_session_account;
+        let vesting_account = &mut ctx.accounts.vesting_sessions_account;
+        let new_vesting_account = &mut ctx.accounts.new_vesting_sessions_account;
+        let new_vesting_session = &mut ctx.accounts.new_vesting_session_account;
+
+        require!(
+            vesting_session.cancelled_at == 0,
+            VestingErrorCode::InteractingWithCanceledSession
+        );
+
+        // Copy the session state, preserving its schedule and withdrawal history
+        new_vesting_session.id =
+            next_session_id(new_vesting_account, &ctx.accounts.new_session_ids_account);
+        new_vesting_session.user = new_user;
+        new_vesting_session.vesting_sessions_account = new_vesting_account.key();
+        new_vesting_session.amount = vesting_session.amount;
+        new_vesting_session.amount_withdrawn = vesting_session.amount_withdrawn;
+        new_vesting_session.start_date = vesting_session.start_date;
+        new_vesting_session.last_withdraw_at = vesting_session.last_withdraw_at;
+        new_vesting_session.cancelled_at = 0;
+        new_vesting_session.position_mint = None;
+        new_vesting_session.amount_owed = vesting_session.amount_owed;
+        new_vesting_session.amount_unlocked = vesting_session.amount_unlocked;
+
+        // Increment the session ID for the next vesting session of the new owner
+        new_vesting_account.last_session_id = new_vesting_session.id + 1;
+        new_vesting_account.active_sessions = new_vesting_account
+            .active_sessions
+            .checked_add(1)
+            .ok_or(VestingErrorCode::ArithmeticOverflow)?;
+        new_vesting_account.user = new_user;
+        vesting_account.active_sessions = vesting_account
+            .active_sessions
+            .checked_sub(1)
+            .ok_or(VestingErrorCode::ArithmeticOverflow)?;
+
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            SessionTransferredEvent {
+                version: EVENT_SCHEMA_VERSION,
+                vault_account: ctx.accounts.vault_account.key(),
+                vesting_session: vesting_session.key(),
+                new_vesting_session: new_vesting_session.key(),
+                user: ctx.accounts.user.key(),
+                new_user,
+                amount: new_vesting_session.amount,
+                amount_withdrawn: new_vesting_session.amount_withdrawn,
+                time: clock.unix_timestamp as u64,
+                actor: ctx.accounts.user.key(),
+                valued_token_mint: ctx.accounts.vault_account.valued_token_mint,
+                escrow_token_mint: ctx.accounts.vault_account.escrow_token_mint,
+                session_id: vesting_session.id,
+                new_session_id: new_vesting_session.id,
+                slot: clock.slot,
+            }
+        );
+
+        // Previous session account is closed to the user by the accounts constraints
         Ok(())
     }
 
-    pub fn change_escrow_metadata(
-        ctx: Context<ChangeEscrowMetadataAccounts>,
-        metadata: ChangeEscrowMetadataParams,
-    ) -> Result<()> {
-        require!(!metadata.value.is_empty(), VestingErrorCode::InvalidMeta);
-        let vault_seed = &[
-            b"token_vault".as_ref(),
-            &ctx.accounts.valued_token_mint.key().to_bytes(),
-            &ctx.accounts.escrow_token_mint.key().to_bytes(),
-            &[ctx.bumps.vault_account],
-        ];
-        let vault_signer = &[&vault_seed[..]];
+    /// Closes a settled vesting session, refunding its rent to the user
+    pub fn close_session(ctx: Context<CloseSession>) -> Result<()> {
+        let vesting_session = &ctx.accounts.vesting_session_account;
+        let vesting_account = &mut ctx.accounts.vesting_sessions_account;
 
-        let field_to_update = match metadata.param_key.as_str() {
-            "name" => Field::Name,
-            "symbol" => Field::Symbol,
-            "uri" => Field::Uri,
-            _ => return err!(VestingErrorCode::InvalidMeta),
-        };
+        // Only fully withdrawn, cancelled or exited sessions can be closed, once nothing is owed
+        require!(
+            (vesting_session.cancelled_at > 0
+                || Schedule::from(&**vesting_session).remaining_amount() == 0)
+                && vesting_session.amount_owed == 0,
+            VestingErrorCode::SessionNotSettled
+        );
 
-        token_metadata_update_field(
-            CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                TokenMetadataUpdateField {
-                    token_program_id: ctx.accounts.token_program.to_account_info(),
-                    metadata: ctx.accounts.escrow_token_mint.to_account_info(),
-                    update_authority: ctx.accounts.vault_account.to_account_info(),
+        // The position token must be burned by its holder, then its mint is closed to the user
+        if let Some(position_mint_key) = vesting_session.position_mint {
+            let position_mint = ctx
+                .accounts
+                .position_mint
+                .as_ref()
+                .filter(|position_mint| position_mint.key() == position_mint_key)
+                .ok_or(VestingErrorCode::InvalidPositionToken)?;
+            require!(
+                position_mint.supply == 0,
+                VestingErrorCode::PositionTokenNotBurned
+            );
+            let token_program = ctx
+                .accounts
+                .token_program
+                .as_ref()
+                .ok_or(VestingErrorCode::InvalidPositionToken)?;
+
+            let vault_seed = &[
+                b"token_vault".as_ref(),
+                &ctx.accounts.valued_token_mint.key().to_bytes(),
+                &ctx.accounts.escrow_token_mint.key().to_bytes(),
+                &[ctx.bumps.vault_account],
+            ];
+            close_account(CpiContext::new_with_signer(
+                token_program.to_account_info(),
+                CloseAccount {
+                    account: position_mint.to_account_info(),
+                    destination: ctx.accounts.user.to_account_info(),
+                    authority: ctx.accounts.vault_account.to_account_info(),
                 },
-                vault_signer,
-            ),
-            field_to_update,
-            metadata.value.clone(),
-        )?;
+                &[&vault_seed[..]],
+            ))?;
+        }
 
-        // Update the mint account to the minimum balance
-        update_account_lamports_to_minimum_balance(
-            ctx.accounts.escrow_token_mint.to_account_info(),
-            ctx.accounts.payer.to_account_info(),
-            ctx.accounts.system_program.to_account_info(),
-        )?;
+        vesting_account.active_sessions = vesting_account
+            .active_sessions
+            .checked_sub(1)
+            .ok_or(VestingErrorCode::ArithmeticOverflow)?;
+        let vault_account = &mut ctx.accounts.vault_account;
+        vault_account.active_sessions = vault_account
+            .active_sessions
+            .checked_sub(1)
+            .ok_or(VestingErrorCode::ArithmeticOverflow)?;
 
-        emit!(EscrowMetadataChangedEvent {
-            escrow_token_mint: ctx.accounts.escrow_token_mint.key(),
-            field_updated: metadata.param_key.clone(),
-            value: metadata.value.clone(),
-        });
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            SessionClosedEvent {
+                version: EVENT_SCHEMA_VERSION,
+                vault_account: ctx.accounts.vault_account.key(),
+                vesting_session: vesting_session.key(),
+                user: ctx.accounts.user.key(),
+                time: clock.unix_timestamp as u64,
+                actor: ctx.accounts.user.key(),
+                valued_token_mint: ctx.accounts.vault_account.valued_token_mint,
+                escrow_token_mint: ctx.accounts.vault_account.escrow_token_mint,
+                session_id: vesting_session.id,
+                slot: clock.slot,
+            }
+        );
+
+        // Session account is closed to the user by the accounts constraints
         Ok(())
     }
 
-    /// Exchanges tokens between user and dual auth accounts
-    pub fn exchange(ctx: Context<Exchange>, amount: u64) -> Result<()> {
-        require!(amount > 0, VestingErrorCode::MinimumAmountHigherZero);
+    /// Closes an empty vesting sessions collection, refunding its rent to the user
+    pub fn close_sessions_collection(ctx: Context<CloseSessionsCollection>) -> Result<()> {
         require!(
-            ctx.accounts.user_valued_token_account.amount >= amount,
-            VestingErrorCode::InsufficientFunds
+            ctx.accounts.vesting_sessions_account.active_sessions == 0,
+            VestingErrorCode::SessionsCollectionNotEmpty
         );
-        token_2022_validations::validate_token_extensions(
-            &ctx.accounts.valued_token_mint.to_account_info(),
-        )?;
 
-        // Transfer tokens from user to vault valued token account
-        transfer_tokens(
-            &ctx.accounts.user_valued_token_account,
-            &ctx.accounts.valued_token_mint,
-            &ctx.accounts.valued_vault_token_account,
-            &ctx.accounts.valued_token_program,
-            amount,
-            ctx.accounts.user.to_account_info(),
-            None,
-        )?;
+        // Save the session ids so a new collection of the user does not hand them out again
+        let session_ids = &mut ctx.accounts.session_ids_account;
+        session_ids.user = ctx.accounts.user.key();
+        session_ids.last_session_id = session_ids
+            .last_session_id
+            .max(ctx.accounts.vesting_sessions_account.last_session_id);
+
+        let clock = Clock::get()?;
+        emit_event!(
+            ctx,
+            SessionsCollectionClosedEvent {
+                version: EVENT_SCHEMA_VERSION,
+                vault_account: ctx.accounts.vault_account.key(),
+                vesting_sessions_account: ctx.accounts.vesting_sessions_account.key(),
+                user: ctx.accounts.user.key(),
+                time: clock.unix_timestamp as u64,
+                actor: ctx.accounts.user.key(),
+                valued_token_mint: ctx.accounts.vault_account.valued_token_mint,
+                escrow_token_mint: ctx.accounts.vault_account.escrow_token_mint,
+                slot: clock.slot,
+            }
+        );
 
-        // Transfer and/or mint equivalent tokens from escrow vault account to the user token account
-        transfer_escrow_from_vault(
-            &ctx.accounts.token_program,
-            &ctx.accounts.vault_account,
-            &ctx.accounts.escrow_vault_token_account,
-            &ctx.accounts.user_escrow_token_account,
-            &ctx.accounts.valued_token_mint,
-            &ctx.accounts.escrow_token_mint,
-            ctx.bumps.vault_account,
-            amount,
-        )?;
+        // Collection account is closed to the user by the accounts constraints
+        Ok(())
+    }
 
-        emit!(ExchangedEvent {
-            vault_account: ctx.accounts.vault_account.key(),
-            amount: amount,
-        });
+    /// Closes the sessions collection and the saved session ids of a user once their vault was
+    /// closed, refunding the rent to the user. Either account may be omitted if it does not exist
+    pub fn close_orphaned_sessions_collection(
+        ctx: Context<CloseOrphanedSessionsCollection>,
+    ) -> Result<()> {
+        if let Some(vesting_sessions_account) = &ctx.accounts.vesting_sessions_account {
+            let clock = Clock::get()?;
+            emit_event!(
+                ctx,
+                SessionsCollectionClosedEvent {
+                    version: EVENT_SCHEMA_VERSION,
+                    vault_account: ctx.accounts.vault_account.key(),
+                    vesting_sessions_account: vesting_sessions_account.key(),
+                    user: ctx.accounts.user.key(),
+                    time: clock.unix_timestamp as u64,
+                    actor: ctx.accounts.user.key(),
+                    valued_token_mint: ctx.accounts.valued_token_mint.key(),
+                    escrow_token_mint: ctx.accounts.escrow_token_mint.key(),
+                    slot: clock.slot,
+                }
+            );
+        }
 
+        // Both accounts are closed to the user by the accounts constraints
         Ok(())
     }
 
-    /// Creates a new vesting session
-    pub fn create_vesting_session(ctx: Context<CreateVestingSession>, amount: u64) -> Result<()> {
-        let vesting_account = &mut ctx.accounts.vesting_sessions_account;
+    /// Withdraws vested tokens from a session
+    pub fn session_withdraw<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SessionWithdraw<'info>>,
+    ) -> Result<()> {
+        token_2022_validations::validate_token_extensions(
+            &ctx.accounts.valued_token_mint.to_account_info(),
+            token_2022_validations::allowed_token_extensions(
+                &ctx.accounts.backend_data,
+                &ctx.accounts.vault_account,
+            ),
+        )?;
+        token_2022_validations::validate_transfer_hook(
+            &ctx.accounts.valued_token_mint.to_account_info(),
+            &ctx.accounts.vault_account.trusted_hook_programs,
+        )?;
+        token_2022_validations::validate_mint_config(
+            &ctx.accounts.valued_token_mint.to_account_info(),
+            &ctx.accounts.vault_account.valued_mint_config,
+        )?;
+        // Detect valued tokens moved out of the vault outside of the program
+        let valued_vault_balance = ctx.accounts.valued_vault_token_account.amount;
+        if let Some(shortfall) =
+            update_vault_shortfall(&mut ctx.accounts.vault_account, valued_vault_balance)
+        {
+            let clock = Clock::get()?;
+            emit_event!(
+                ctx,
+                VaultShortfallEvent {
+                    version: EVENT_SCHEMA_VERSION,
+                    vault_account: ctx.accounts.vault_account.key(),
+                    expected_balance: ctx.accounts.vault_account.expected_valued_balance,
+                    actual_balance: valued_vault_balance,
+                    shortfall,
+                    time: clock.unix_timestamp as u64,
+                    actor: ctx.accounts.user.key(),
+                    valued_token_mint: ctx.accounts.valued_token_mint.key(),
+                    escrow_token_mint: ctx.accounts.escrow_token_mint.key(),
+                    slot: clock.slot,
+                }
+            );
+        }
+
         let vesting_session = &mut ctx.accounts.vesting_session_account;
+        let amount_withdrawn_before = vesting_session.amount_withdrawn;
 
-        // Check if the amount is sufficient (at the minimum value)
+        // Cancelled sessions can only claim the valued tokens still owed to them
         require!(
-            amount >= MIN_DIVISIBLE_BY_VESTING_PERIOD,
-            VestingErrorCode::MinimumAmountNotMet
+            vesting_session.cancelled_at == 0 || vesting_session.amount_owed > 0,
+            VestingErrorCode::InteractingWithCanceledSession
         );
 
-        // Initialize vesting session with details
-        vesting_session.id = vesting_account.last_session_id;
-        vesting_session.user = ctx.accounts.user.key();
-        vesting_session.vesting_sessions_account = vesting_account.key();
-        vesting_session.amount = amount;
-        vesting_session.amount_withdrawn = 0;
-        vesting_session.start_date = Clock::get()?.unix_timestamp as u64;
-        vesting_session.last_withdraw_at = 0;
-        vesting_session.cancelled_at = 0;
-
-        // Increment the session ID for the next vesting session
-        vesting_account.last_session_id += 1;
-        vesting_account.user = ctx.accounts.user.key();
+        // Calculate amount to release using the helper function
+        let amount_to_release = if vesting_session.cancelled_at == 0 {
+            calculate_amount_to_release(vesting_session)?
+        } else {
+            0
+        };
 
-        // Transfer tokens from the user escrow account back to vault escrow account
-        transfer_tokens(
-            &ctx.accounts.user_escrow_token_account,
-            &ctx.accounts.escrow_token_mint,
-            &ctx.accounts.escrow_vault_token_account,
-            &ctx.accounts.token_program,
-            amount,
-            ctx.accounts.user.to_account_info(),
-            None,
+        // Degraded vaults pay the same share of every claim and owe the rest to the session
+        let amount_paid = book_valued_payout(
+            &mut ctx.accounts.vault_account,
+            vesting_session,
+            valued_vault_balance,
+            amount_to_release,
         )?;
 
-        emit!(CreatedVestingSessionEvent {
-            vault_account: ctx.accounts.vault_account.key(),
-            vesting_session: vesting_session.key(),
-            user: ctx.accounts.user.key(),
-            amount: amount,
-        });
+        if amount_paid > 0 {
+            let vault_seed = &[
+                "token_vault".as_bytes(),
+                &ctx.accounts.valued_token_mint.key().to_bytes(),
+                &ctx.accounts.escrow_token_mint.key().to_bytes(),
+                &[ctx.bumps.vault_account],
+            ];
+            let vault_signer = &[&vault_seed[..]];
+
+            // Transfer releasable tokens
+            transfer_tokens(
+                TokenTransferAccounts {
+                    from: &ctx.accounts.valued_vault_token_account,
+                    mint: &ctx.accounts.valued_token_mint,
+                    to: &ctx.accounts.user_valued_token_account,
+                    token_program: &ctx.accounts.valued_token_program,
+                    authority: ctx.accounts.vault_account.to_account_info(),
+                    pda_signer: Some(vault_signer),
+                    remaining_accounts: ctx.remaining_accounts,
+                },
+                amount_paid,
+            )?;
 
-        Ok(())
+            // Update vesting session state, owed tokens alone do not move the release reference
+            let clock = Clock::get()?;
+            if amount_to_release > 0 {
+                record_session_withdrawal(
+                    vesting_session,
+                    amount_to_release,
+                    clock.unix_timestamp as u64,
+                )?;
+            }
+
+            emit_event!(
+                ctx,
+                SessionWithdrawnEvent {
+                    version: EVENT_SCHEMA_VERSION,
+                    vault_account: ctx.accounts.vault_account.key(),
+                    vesting_session: vesting_session.key(),
+                    user: ctx.accounts.user.key(),
+                    amount: amount_paid,
+                    time: clock.unix_timestamp as u64,
+                    actor: ctx.accounts.user.key(),
+                    valued_token_mint: ctx.accounts.valued_token_mint.key(),
+                    escrow_token_mint: ctx.accounts.escrow_token_mint.key(),
+                    session_id: vesting_session.id,
+                    amount_withdrawn_before,
+                    amount_withdrawn_after: vesting_session.amount_withdrawn,
+                    slot: clock.slot,
+                }
+            );
+
+            return Ok(());
+        }
+
+        Err(VestingErrorCode::InsufficientWithdrawalAmount.into())
     }
 
-    /// Withdraws vested tokens from a session
-    pub fn session_withdraw(ctx: Context<SessionWithdraw>) -> Result<()> {
+    /// Withdraws vested tokens from a tokenized session to the holder of its position token
+    pub fn position_withdraw<'info>(
+        ctx: Context<'_, '_, 'info, 'info, PositionWithdraw<'info>>,
+    ) -> Result<()> {
         token_2022_validations::validate_token_extensions(
             &ctx.accounts.valued_token_mint.to_account_info(),
+            token_2022_validations::allowed_token_extensions(
+                &ctx.accounts.backend_data,
+                &ctx.accounts.vault_account,
+            ),
+        )?;
+        token_2022_validations::validate_transfer_hook(
+            &ctx.accounts.valued_token_mint.to_account_info(),
+            &ctx.accounts.vault_account.trusted_hook_programs,
         )?;
+        token_2022_validations::validate_mint_config(
+            &ctx.accounts.valued_token_mint.to_account_info(),
+            &ctx.accounts.vault_account.valued_mint_config,
+        )?;
+        // Detect valued tokens moved out of the vault outside of the program
+        let valued_vault_balance = ctx.accounts.valued_vault_token_account.amount;
+        if let Some(shortfall) =
+            update_vault_shortfall(&mut ctx.accounts.vault_account, valued_vault_balance)
+        {
+            let clock = Clock::get()?;
+            emit_event!(
+                ctx,
+                VaultShortfallEvent {
+                    version: EVENT_SCHEMA_VERSION,
+                    vault_account: ctx.accounts.vault_account.key(),
+                    expected_balance: ctx.accounts.vault_account.expected_valued_balance,
+                    actual_balance: valued_vault_balance,
+                    shortfall,
+                    time: clock.unix_timestamp as u64,
+                    actor: ctx.accounts.holder.key(),
+                    valued_token_mint: ctx.accounts.valued_token_mint.key(),
+                    escrow_token_mint: ctx.accounts.escrow_token_mint.key(),
+                    slot: clock.slot,
+                }
+            );
+        }
+
         let vesting_session = &mut ctx.accounts.vesting_session_account;
+        let amount_withdrawn_before = vesting_session.amount_withdrawn;
 
+        // Cancelled sessions can only claim the valued tokens still owed to them
         require!(
-            vesting_session.cancelled_at == 0,
+            vesting_session.cancelled_at == 0 || vesting_session.amount_owed > 0,
             VestingErrorCode::InteractingWithCanceledSession
         );
 
         // Calculate amount to release using the helper function
-        let amount_to_release = calculate_amount_to_release(vesting_session)?;
+        let amount_to_release = if vesting_session.cancelled_at == 0 {
+            calculate_amount_to_release(vesting_session)?
+        } else {
+            0
+        };
+
+        // Degraded vaults pay the same share of every claim and owe the rest to the session
+        let amount_paid = book_valued_payout(
+            &mut ctx.accounts.vault_account,
+            vesting_session,
+            valued_vault_balance,
+            amount_to_release,
+        )?;
 
-        if amount_to_release > 0 {
+        if amount_paid > 0 {
             let vault_seed = &[
                 "token_vault".as_bytes(),
                 &ctx.accounts.valued_token_mint.key().to_bytes(),
@@ -286,31 +2298,48 @@ pub mod vesting_contract {
             ];
             let vault_signer = &[&vault_seed[..]];
 
-            // Transfer releasable tokens
+            // Transfer releasable tokens to the position holder
             transfer_tokens(
-                &ctx.accounts.valued_vault_token_account,
-                &ctx.accounts.valued_token_mint,
-                &ctx.accounts.user_valued_token_account,
-                &ctx.accounts.valued_token_program,
-                amount_to_release,
-                ctx.accounts.vault_account.to_account_info(),
-                Some(vault_signer),
+                TokenTransferAccounts {
+                    from: &ctx.accounts.valued_vault_token_account,
+                    mint: &ctx.accounts.valued_token_mint,
+                    to: &ctx.accounts.holder_valued_token_account,
+                    token_program: &ctx.accounts.valued_token_program,
+                    authority: ctx.accounts.vault_account.to_account_info(),
+                    pda_signer: Some(vault_signer),
+                    remaining_accounts: ctx.remaining_accounts,
+                },
+                amount_paid,
             )?;
 
-            // Update vesting session state
-            vesting_session.amount_withdrawn = vesting_session
-                .amount_withdrawn
-                .checked_add(amount_to_release)
-                .ok_or(VestingErrorCode::ArithmeticOverflow)?;
-            vesting_session.last_withdraw_at = Clock::get()?.unix_timestamp as u64;
+            // Update vesting session state, owed tokens alone do not move the release reference
+            let clock = Clock::get()?;
+            if amount_to_release > 0 {
+                record_session_withdrawal(
+                    vesting_session,
+                    amount_to_release,
+                    clock.unix_timestamp as u64,
+                )?;
+            }
 
-            emit!(SessionWithdrawnEvent {
-                vault_account: ctx.accounts.vault_account.key(),
-                vesting_session: vesting_session.key(),
-                user: ctx.accounts.user.key(),
-                amount: amount_to_release,
-                time: vesting_session.last_withdraw_at,
-            });
+            emit_event!(
+                ctx,
+                SessionWithdrawnEvent {
+                    version: EVENT_SCHEMA_VERSION,
+                    vault_account: ctx.accounts.vault_account.key(),
+                    vesting_session: vesting_session.key(),
+                    user: ctx.accounts.holder.key(),
+                    amount: amount_paid,
+                    time: clock.unix_timestamp as u64,
+                    actor: ctx.accounts.holder.key(),
+                    valued_token_mint: ctx.accounts.valued_token_mint.key(),
+                    escrow_token_mint: ctx.accounts.escrow_token_mint.key(),
+                    session_id: vesting_session.id,
+                    amount_withdrawn_before,
+                    amount_withdrawn_after: vesting_session.amount_withdrawn,
+                    slot: clock.slot,
+                }
+            );
 
             return Ok(());
         }
@@ -319,11 +2348,49 @@ pub mod vesting_contract {
     }
 
     /// Cancels an ongoing vesting session
-    pub fn session_cancel(ctx: Context<SessionCancelation>) -> Result<()> {
+    pub fn session_cancel<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SessionCancelation<'info>>,
+    ) -> Result<()> {
         token_2022_validations::validate_token_extensions(
             &ctx.accounts.valued_token_mint.to_account_info(),
+            token_2022_validations::allowed_token_extensions(
+                &ctx.accounts.backend_data,
+                &ctx.accounts.vault_account,
+            ),
+        )?;
+        token_2022_validations::validate_transfer_hook(
+            &ctx.accounts.valued_token_mint.to_account_info(),
+            &ctx.accounts.vault_account.trusted_hook_programs,
+        )?;
+        token_2022_validations::validate_mint_config(
+            &ctx.accounts.valued_token_mint.to_account_info(),
+            &ctx.accounts.vault_account.valued_mint_config,
         )?;
+        // Detect valued tokens moved out of the vault outside of the program
+        let valued_vault_balance = ctx.accounts.valued_vault_token_account.amount;
+        if let Some(shortfall) =
+            update_vault_shortfall(&mut ctx.accounts.vault_account, valued_vault_balance)
+        {
+            let clock = Clock::get()?;
+            emit_event!(
+                ctx,
+                VaultShortfallEvent {
+                    version: EVENT_SCHEMA_VERSION,
+                    vault_account: ctx.accounts.vault_account.key(),
+                    expected_balance: ctx.accounts.vault_account.expected_valued_balance,
+                    actual_balance: valued_vault_balance,
+                    shortfall,
+                    time: clock.unix_timestamp as u64,
+                    actor: ctx.accounts.user.key(),
+                    valued_token_mint: ctx.accounts.valued_token_mint.key(),
+                    escrow_token_mint: ctx.accounts.escrow_token_mint.key(),
+                    slot: clock.slot,
+                }
+            );
+        }
+
         let vesting_session = &mut ctx.accounts.vesting_session_account;
+        let amount_withdrawn_before = vesting_session.amount_withdrawn;
 
         require!(
             vesting_session.cancelled_at == 0,
@@ -333,15 +2400,21 @@ pub mod vesting_contract {
         // Calculate amount to release using the helper function
         let valued_amount_to_release = calculate_amount_to_release(vesting_session)?;
 
+        // Degraded vaults pay the same share of every claim and owe the rest to the session
+        let valued_amount_paid = book_valued_payout(
+            &mut ctx.accounts.vault_account,
+            vesting_session,
+            valued_vault_balance,
+            valued_amount_to_release,
+        )?;
+
         // Calculate the amount to return to escrow
-        let escrow_amount_to_get_back = vesting_session
-            .amount
-            .checked_sub(vesting_session.amount_withdrawn)
-            .ok_or(VestingErrorCode::ArithmeticOverflow)?
+        let escrow_amount_to_get_back = Schedule::from(&**vesting_session)
+            .remaining_amount()
             .checked_sub(valued_amount_to_release)
             .ok_or(VestingErrorCode::ArithmeticOverflow)?;
 
-        if valued_amount_to_release > 0 {
+        if valued_amount_paid > 0 {
             // Transfer releasable tokens to user
             let vault_seed = &[
                 "token_vault".as_bytes(),
@@ -353,56 +2426,103 @@ pub mod vesting_contract {
 
             // Transfer releasable tokens
             transfer_tokens(
-                &ctx.accounts.valued_vault_token_account,
-                &ctx.accounts.valued_token_mint,
-                &ctx.accounts.user_valued_token_account,
-                &ctx.accounts.valued_token_program,
-                valued_amount_to_release,
-                ctx.accounts.vault_account.to_account_info(),
-                Some(vault_signer),
+                TokenTransferAccounts {
+                    from: &ctx.accounts.valued_vault_token_account,
+                    mint: &ctx.accounts.valued_token_mint,
+                    to: &ctx.accounts.user_valued_token_account,
+                    token_program: &ctx.accounts.valued_token_program,
+                    authority: ctx.accounts.vault_account.to_account_info(),
+                    pda_signer: Some(vault_signer),
+                    remaining_accounts: ctx.remaining_accounts,
+                },
+                valued_amount_paid,
             )?;
+        }
 
-            // Update vesting session state
-            vesting_session.amount_withdrawn = vesting_session
-                .amount_withdrawn
-                .checked_add(valued_amount_to_release)
-                .ok_or(VestingErrorCode::ArithmeticOverflow)?;
-
-            vesting_session.last_withdraw_at = Clock::get()?.unix_timestamp as u64;
+        // Update vesting session state
+        if valued_amount_to_release > 0 {
+            record_session_withdrawal(
+                vesting_session,
+                valued_amount_to_release,
+                Clock::get()?.unix_timestamp as u64,
+            )?;
         }
 
         if escrow_amount_to_get_back > 0 {
             // Return remaining tokens to user escrow account
             transfer_escrow_from_vault(
-                &ctx.accounts.token_program,
-                &ctx.accounts.vault_account,
-                &ctx.accounts.escrow_vault_token_account,
-                &ctx.accounts.user_escrow_token_account,
-                &ctx.accounts.valued_token_mint,
-                &ctx.accounts.escrow_token_mint,
+                EscrowTransferAccounts {
+                    token_program: &ctx.accounts.token_program,
+                    vault_account: &ctx.accounts.vault_account,
+                    escrow_vault_token_account: &ctx.accounts.escrow_vault_token_account,
+                    user_escrow_token_account: &ctx.accounts.user_escrow_token_account,
+                    escrow_token_mint: &ctx.accounts.escrow_token_mint,
+                },
                 ctx.bumps.vault_account,
                 escrow_amount_to_get_back,
             )?;
         }
 
         // Mark the session as cancelled
-        vesting_session.cancelled_at = Clock::get()?.unix_timestamp as u64;
+        let clock = Clock::get()?;
+        vesting_session.cancelled_at = clock.unix_timestamp as u64;
 
-        emit!(SessionCancelEvent {
-            vault_account: ctx.accounts.vault_account.key(),
-            vesting_session: vesting_session.key(),
-            user: ctx.accounts.user.key(),
-            valued_amount: valued_amount_to_release,
-            escrow_amount: escrow_amount_to_get_back,
-            time: vesting_session.cancelled_at,
-        });
+        emit_event!(
+            ctx,
+            SessionCancelEvent {
+                version: EVENT_SCHEMA_VERSION,
+                vault_account: ctx.accounts.vault_account.key(),
+                vesting_session: vesting_session.key(),
+                user: ctx.accounts.user.key(),
+                valued_amount: valued_amount_paid,
+                escrow_amount: escrow_amount_to_get_back,
+                time: vesting_session.cancelled_at,
+                actor: ctx.accounts.user.key(),
+                valued_token_mint: ctx.accounts.valued_token_mint.key(),
+                escrow_token_mint: ctx.accounts.escrow_token_mint.key(),
+                session_id: vesting_session.id,
+                amount_withdrawn_before,
+                amount_withdrawn_after: vesting_session.amount_withdrawn,
+                slot: clock.slot,
+            }
+        );
 
         Ok(())
     }
 
     /// Exits an ongoing vesting session
-    pub fn session_exit(ctx: Context<SessionCancelation>) -> Result<()> {
+    pub fn session_exit<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SessionCancelation<'info>>,
+    ) -> Result<()> {
+        token_2022_validations::validate_transfer_hook(
+            &ctx.accounts.valued_token_mint.to_account_info(),
+            &ctx.accounts.vault_account.trusted_hook_programs,
+        )?;
+        // Detect valued tokens moved out of the vault outside of the program
+        let valued_vault_balance = ctx.accounts.valued_vault_token_account.amount;
+        if let Some(shortfall) =
+            update_vault_shortfall(&mut ctx.accounts.vault_account, valued_vault_balance)
+        {
+            let clock = Clock::get()?;
+            emit_event!(
+                ctx,
+                VaultShortfallEvent {
+                    version: EVENT_SCHEMA_VERSION,
+                    vault_account: ctx.accounts.vault_account.key(),
+                    expected_balance: ctx.accounts.vault_account.expected_valued_balance,
+                    actual_balance: valued_vault_balance,
+                    shortfall,
+                    time: clock.unix_timestamp as u64,
+                    actor: ctx.accounts.user.key(),
+                    valued_token_mint: ctx.accounts.valued_token_mint.key(),
+                    escrow_token_mint: ctx.accounts.escrow_token_mint.key(),
+                    slot: clock.slot,
+                }
+            );
+        }
+
         let vesting_session = &mut ctx.accounts.vesting_session_account;
+        let amount_withdrawn_before = vesting_session.amount_withdrawn;
 
         require!(
             vesting_session.cancelled_at == 0,
@@ -410,12 +2530,17 @@ pub mod vesting_contract {
         );
 
         // Calculate the amount to return back to the user
-        let amount = vesting_session
-            .amount
-            .checked_sub(vesting_session.amount_withdrawn)
-            .ok_or(VestingErrorCode::ArithmeticOverflow)?;
+        let amount = Schedule::from(&**vesting_session).remaining_amount();
+
+        // Degraded vaults pay the same share of every claim and owe the rest to the session
+        let amount_paid = book_valued_payout(
+            &mut ctx.accounts.vault_account,
+            vesting_session,
+            valued_vault_balance,
+            amount,
+        )?;
 
-        if amount > 0 {
+        if amount_paid > 0 {
             // Transfer releasable tokens to user
             let vault_seed = &[
                 "token_vault".as_bytes(),
@@ -427,34 +2552,44 @@ pub mod vesting_contract {
 
             // Transfer releasable tokens
             transfer_tokens(
-                &ctx.accounts.valued_vault_token_account,
-                &ctx.accounts.valued_token_mint,
-                &ctx.accounts.user_valued_token_account,
-                &ctx.accounts.valued_token_program,
-                amount,
-                ctx.accounts.vault_account.to_account_info(),
-                Some(vault_signer),
+                TokenTransferAccounts {
+                    from: &ctx.accounts.valued_vault_token_account,
+                    mint: &ctx.accounts.valued_token_mint,
+                    to: &ctx.accounts.user_valued_token_account,
+                    token_program: &ctx.accounts.valued_token_program,
+                    authority: ctx.accounts.vault_account.to_account_info(),
+                    pda_signer: Some(vault_signer),
+                    remaining_accounts: ctx.remaining_accounts,
+                },
+                amount_paid,
             )?;
         }
 
         // Update vesting session state
-        vesting_session.amount_withdrawn = vesting_session
-            .amount_withdrawn
-            .checked_add(amount)
-            .ok_or(VestingErrorCode::ArithmeticOverflow)?;
-
-        vesting_session.last_withdraw_at = Clock::get()?.unix_timestamp as u64;
+        let clock = Clock::get()?;
+        record_session_withdrawal(vesting_session, amount, clock.unix_timestamp as u64)?;
 
         // Mark the session as cancelled
-        vesting_session.cancelled_at = Clock::get()?.unix_timestamp as u64;
-
-        emit!(SessionExitEvent {
-            vault_account: ctx.accounts.vault_account.key(),
-            vesting_session: vesting_session.key(),
-            user: ctx.accounts.user.key(),
-            amount: amount,
-            time: vesting_session.cancelled_at,
-        });
+        vesting_session.cancelled_at = clock.unix_timestamp as u64;
+
+        emit_event!(
+            ctx,
+            SessionExitEvent {
+                version: EVENT_SCHEMA_VERSION,
+                vault_account: ctx.accounts.vault_account.key(),
+                vesting_session: vesting_session.key(),
+                user: ctx.accounts.user.key(),
+                amount: amount_paid,
+                time: vesting_session.cancelled_at,
+                actor: ctx.accounts.user.key(),
+                valued_token_mint: ctx.accounts.valued_token_mint.key(),
+                escrow_token_mint: ctx.accounts.escrow_token_mint.key(),
+                session_id: vesting_session.id,
+                amount_withdrawn_before,
+                amount_withdrawn_after: vesting_session.amount_withdrawn,
+                slot: clock.slot,
+            }
+        );
 
         Ok(())
     }
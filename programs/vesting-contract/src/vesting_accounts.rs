@@ -4,6 +4,10 @@ use anchor_spl::{
     token_interface::{Mint, Token2022, TokenAccount, TokenInterface},
 };
 use anchor_lang::solana_program::bpf_loader_upgradeable as bpf;
+use vesting_math::Schedule;
+
+use crate::errors::VestingErrorCode;
+use crate::helpers::next_session_id;
 
 // ##### set_backend_account #####
 
@@ -13,13 +17,42 @@ pub struct SetBackendAccountParams {
     pub new_authority: Option<Pubkey>,
 }
 
+/// Maximum amount of token extensions which can be allowed for valued tokens
+pub const MAX_ALLOWED_TOKEN_EXTENSIONS: usize = 32;
+
+/// Maximum amount of URI schemes which can be allowed for escrow metadata
+pub const MAX_ALLOWED_URI_SCHEMES: usize = 5;
+
+/// Maximum length of the URI schemes and symbol markers of the metadata rules
+pub const MAX_METADATA_RULE_LENGTH: usize = 10;
+
+/// Validation rules for the name, symbol and URI of escrow tokens
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, InitSpace)]
+pub struct MetadataRules {
+    pub max_name_length: u16,
+    pub max_symbol_length: u16,
+    pub max_uri_length: u16,
+    #[max_len(MAX_ALLOWED_URI_SCHEMES, MAX_METADATA_RULE_LENGTH)]
+    pub allowed_uri_schemes: Vec<String>, // Schemes an URI may use, such as https or ipfs. Any if empty
+    pub ascii_symbol: bool, // Whether symbols may only contain printable ASCII characters
+    #[max_len(MAX_METADATA_RULE_LENGTH)]
+    pub symbol_prefix: Option<String>, // Prefix marking a symbol as an escrow token
+    #[max_len(MAX_METADATA_RULE_LENGTH)]
+    pub symbol_suffix: Option<String>, // Suffix marking a symbol as an escrow token
+}
+
 #[account]
 #[derive(InitSpace)]
 pub struct BackendAccountData {
     pub backend_account: Pubkey,
     pub change_authority: Option<Pubkey>,
+    #[max_len(MAX_ALLOWED_TOKEN_EXTENSIONS)]
+    pub allowed_token_extensions: Option<Vec<u16>>, // Extensions allowed for valued tokens, built-in defaults if None
+    pub rent_refund_destination: Option<Pubkey>, // Receives the escrow mint rent surplus, the payer if None
+    pub metadata_rules: Option<MetadataRules>, // Escrow metadata validation rules, not enforced if None
 }
 
+#[cfg_attr(feature = "cpi-events", event_cpi)]
 #[derive(Accounts)]
 #[instruction(params: SetBackendAccountParams)]
 pub struct SetBackendAccountCtx<'info> {
@@ -45,6 +78,174 @@ pub struct SetBackendAccountCtx<'info> {
     pub payer: Signer<'info>,
 }
 
+// ##### allowed token extensions #####
+
+/// Parameters for updating the token extensions allowed for valued tokens
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct AllowedTokenExtensionsParams {
+    pub allowed_token_extensions: Option<Vec<u16>>,
+}
+
+/// Accounts required for updating the backend wide configuration
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct UpdateBackendConfig<'info> {
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf::id(),
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    #[account(
+        mut,
+        seeds = [
+            b"davincij15_seed"
+        ],
+        bump
+    )]
+    pub backend_data: Box<Account<'info, BackendAccountData>>,
+    pub admin: Signer<'info>,
+}
+
+// ##### metadata rules #####
+
+/// Parameters for updating the escrow metadata validation rules
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct MetadataRulesParams {
+    pub metadata_rules: Option<MetadataRules>,
+}
+
+// ##### rent refund destination #####
+
+/// Parameters for updating the account receiving the rent surplus of escrow mints
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct RentRefundDestinationParams {
+    pub rent_refund_destination: Option<Pubkey>,
+}
+
+/// Accounts required for overriding the token extensions allowed for the valued token of a vault
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct SetVaultAllowedTokenExtensions<'info> {
+    pub valued_token_mint: Box<InterfaceAccount<'info, Mint>>,
+    pub escrow_token_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf::id(),
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    #[account(
+        seeds = [
+            b"davincij15_seed"
+        ],
+        bump
+    )]
+    pub backend_data: Box<Account<'info, BackendAccountData>>,
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"token_vault",
+            valued_token_mint.key().as_ref(),
+            escrow_token_mint.key().as_ref(),
+        ],
+        bump,
+        has_one = escrow_token_mint,
+        has_one = valued_token_mint
+    )]
+    pub vault_account: Box<Account<'info, VaultAccount>>,
+}
+
+/// Accounts required for refreshing the valued token mint configuration recorded by a vault
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct RefreshValuedMintConfig<'info> {
+    pub valued_token_mint: Box<InterfaceAccount<'info, Mint>>,
+    pub escrow_token_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf::id(),
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    #[account(
+        seeds = [
+            b"davincij15_seed"
+        ],
+        bump
+    )]
+    pub backend_data: Box<Account<'info, BackendAccountData>>,
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"token_vault",
+            valued_token_mint.key().as_ref(),
+            escrow_token_mint.key().as_ref(),
+        ],
+        bump,
+        has_one = escrow_token_mint,
+        has_one = valued_token_mint
+    )]
+    pub vault_account: Box<Account<'info, VaultAccount>>,
+}
+
+// ##### escrow account freeze #####
+
+/// Parameters for freezing or thawing an escrow token account
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct EscrowFreezeParams {
+    pub reason: u16, // App defined code explaining the action for audit trails
+}
+
+/// Accounts required for freezing or thawing an escrow token account
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct EscrowAccountFreeze<'info> {
+    pub token_program: Program<'info, Token2022>,
+    pub valued_token_mint: Box<InterfaceAccount<'info, Mint>>,
+    pub escrow_token_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf::id(),
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    #[account(
+        seeds = [
+            b"davincij15_seed"
+        ],
+        bump
+    )]
+    pub backend_data: Box<Account<'info, BackendAccountData>>,
+    pub admin: Signer<'info>,
+
+    #[account(
+        seeds = [
+            b"token_vault",
+            valued_token_mint.key().as_ref(),
+            escrow_token_mint.key().as_ref(),
+        ],
+        bump,
+        has_one = escrow_token_mint,
+        has_one = valued_token_mint
+    )]
+    pub vault_account: Box<Account<'info, VaultAccount>>,
+
+    #[account(
+        mut,
+        token::mint = escrow_token_mint,
+        token::token_program = token_program,
+    )]
+    pub escrow_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+}
+
 // ##### init_escrow_token #####
 
 /// Parameters for initializing a new token
@@ -56,6 +257,20 @@ pub struct InitEscrowTokenParams {
     pub app_id: String,
 }
 
+/// Configuration of the valued token mint recorded when the vault is created, or refreshed by an
+/// admin
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, InitSpace)]
+pub struct MintConfigSnapshot {
+    #[max_len(MAX_ALLOWED_TOKEN_EXTENSIONS)]
+    pub extensions: Vec<u16>, // Extension types implemented by the mint
+    pub freeze_authority: Option<Pubkey>,
+    pub permanent_delegate: Option<Pubkey>,
+    pub transfer_hook_program: Option<Pubkey>,
+}
+
+/// Maximum amount of transfer hook programs a vault can trust
+pub const MAX_TRUSTED_HOOK_PROGRAMS: usize = 5;
+
 /// Account structure for vault account
 #[account]
 #[derive(InitSpace)]
@@ -65,9 +280,25 @@ pub struct VaultAccount {
     pub escrow_token_mint: Pubkey, // public key of the escrow token mint
     #[max_len(100)]
     pub app_id: String, //Unique id of the app used to create the escrow
+    pub sessions_transferable: bool, // Whether vesting sessions can be split to other users
+    pub active_sessions: u64, // Amount of session accounts of the vault not closed yet, even settled
+    #[max_len(MAX_TRUSTED_HOOK_PROGRAMS)]
+    pub trusted_hook_programs: Vec<Pubkey>, // Transfer hook programs allowed to run on valued token transfers
+    #[max_len(MAX_ALLOWED_TOKEN_EXTENSIONS)]
+    pub allowed_token_extensions: Option<Vec<u16>>, // Overrides the backend allowed extensions if set
+    pub valued_mint_config: MintConfigSnapshot, // Valued token mint configuration at vault creation or last refresh
+    pub expected_valued_balance: u64, // Valued tokens the vault should hold for its escrow holders
+    pub degraded: bool, // Whether valued tokens were moved out of the vault outside of the program
+    pub owed_valued_balance: u64, // Part of the expected balance owed to sessions paid short while degraded
+    pub escrow_non_transferable: bool, // Whether the escrow mint implements the NonTransferable extension
+    pub max_exchanged_amount: Option<u64>, // Cap of valued tokens which can be exchanged into the vault
+    pub total_exchanged_amount: u64, // Valued tokens exchanged into the vault since its creation
+    pub metadata_update_authority: Option<Pubkey>, // Escrow metadata update authority when handed off, the vault if None
+    pub metadata_pointer_authority: Option<Pubkey>, // Escrow metadata pointer authority when handed off, the vault if None
 }
 
 /// Accounts required for initializing a new token
+#[cfg_attr(feature = "cpi-events", event_cpi)]
 #[derive(Accounts)]
 #[instruction(
     params: InitEscrowTokenParams
@@ -119,7 +350,8 @@ pub struct InitEscrowToken<'info> {
         mint::authority = vault_account,
         mint::freeze_authority = vault_account,
         extensions::metadata_pointer::authority = vault_account,
-        extensions::metadata_pointer::metadata_address = escrow_token_mint
+        extensions::metadata_pointer::metadata_address = escrow_token_mint,
+        extensions::close_authority::authority = vault_account
     )]
     pub escrow_token_mint: Box<InterfaceAccount<'info, Mint>>,
 
@@ -127,6 +359,62 @@ pub struct InitEscrowToken<'info> {
     pub payer: Signer<'info>,
 }
 
+/// Accounts required for initializing a new non transferable token
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+#[instruction(
+    params: InitEscrowTokenParams
+)]
+pub struct InitNonTransferableEscrowToken<'info> {
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token2022>,
+    pub valued_token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+
+    // Backend authorization
+    #[account(
+        seeds = [
+            b"davincij15_seed"
+        ],
+        bump
+    )]
+    pub backend_data: Box<Account<'info, BackendAccountData>>,
+    #[account(address = backend_data.backend_account)]
+    pub backend: Signer<'info>,
+
+    //New vault for new token creation
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + VaultAccount::INIT_SPACE,
+        seeds = [
+            b"token_vault",
+            valued_token_mint.key().as_ref(),
+            escrow_token_mint.key().as_ref(),
+        ],
+        bump
+    )]
+    pub vault_account: Box<Account<'info, VaultAccount>>,
+
+    pub valued_token_mint: Box<InterfaceAccount<'info, Mint>>,
+    // New mint address creation, the mint init constraints do not support the NonTransferable extension
+    /// CHECK: Created and initialized as a Token-2022 mint by the instruction
+    #[account(
+        mut,
+        seeds = [
+            b"escrow_mint",
+            valued_token_mint.key().as_ref(),
+            params.app_id.as_bytes(),
+        ],
+        bump
+    )]
+    pub escrow_token_mint: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+}
+
+#[cfg_attr(feature = "cpi-events", event_cpi)]
 #[derive(Accounts)]
 pub struct InitVaultTokenAccounts<'info> {
     pub system_program: Program<'info, System>,
@@ -182,23 +470,19 @@ pub struct InitVaultTokenAccounts<'info> {
     pub payer: Signer<'info>,
 }
 
-/// Parameters for initializing a new token
+/// Parameters for updating the vault configuration, unset values are left unchanged
 #[derive(AnchorSerialize, AnchorDeserialize, Debug, Clone)]
-pub struct ChangeEscrowMetadataParams {
-    pub value: String,
-    pub param_key: String,
+pub struct UpdateVaultConfigParams {
+    pub sessions_transferable: Option<bool>,
+    pub trusted_hook_programs: Option<Vec<Pubkey>>,
+    pub max_exchanged_amount: Option<u64>, // Zero removes the cap
 }
 
-/// Update token metadata
+/// Update vault configuration
+#[cfg_attr(feature = "cpi-events", event_cpi)]
 #[derive(Accounts)]
-#[instruction(
-    params: ChangeEscrowMetadataParams
-)]
-pub struct ChangeEscrowMetadataAccounts<'info> {
-    pub token_program: Program<'info, Token2022>,
-    pub system_program: Program<'info, System>,
+pub struct UpdateVaultConfig<'info> {
     pub valued_token_mint: Box<InterfaceAccount<'info, Mint>>,
-    #[account(mut)]
     pub escrow_token_mint: Box<InterfaceAccount<'info, Mint>>,
 
     //Backend authorization
@@ -224,22 +508,20 @@ pub struct ChangeEscrowMetadataAccounts<'info> {
         has_one = valued_token_mint
     )]
     pub vault_account: Box<Account<'info, VaultAccount>>,
-    #[account(mut)]
-    pub payer: Signer<'info>,
 }
 
-
-/// Accounts required for token exchange
+/// Accounts required for decommissioning a vault
+#[cfg_attr(feature = "cpi-events", event_cpi)]
 #[derive(Accounts)]
-#[instruction(amount: u64)]
-pub struct Exchange<'info> {
+pub struct CloseVault<'info> {
+    pub system_program: Program<'info, System>,
     pub token_program: Program<'info, Token2022>,
+    pub valued_token_program: Interface<'info, TokenInterface>,
     pub associated_token_program: Program<'info, AssociatedToken>,
-    pub system_program: Program<'info, System>,
+    #[account(mut)]
     pub valued_token_mint: Box<InterfaceAccount<'info, Mint>>,
     #[account(mut)]
     pub escrow_token_mint: Box<InterfaceAccount<'info, Mint>>,
-    pub valued_token_program: Interface<'info, TokenInterface>,
 
     //Backend authorization
     #[account(
@@ -254,6 +536,7 @@ pub struct Exchange<'info> {
 
     #[account(
         mut,
+        close = creator,
         seeds = [
             b"token_vault",
             valued_token_mint.key().as_ref(),
@@ -261,7 +544,8 @@ pub struct Exchange<'info> {
         ],
         bump,
         has_one = escrow_token_mint,
-        has_one = valued_token_mint
+        has_one = valued_token_mint,
+        has_one = creator
     )]
     pub vault_account: Box<Account<'info, VaultAccount>>,
 
@@ -282,57 +566,983 @@ pub struct Exchange<'info> {
     )]
     pub escrow_vault_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
 
-    // User token accounts
     #[account(mut)]
-    pub user: Signer<'info>,
+    pub creator: Signer<'info>,
     #[account(
-        mut,
+        init_if_needed,
+        payer = creator,
         associated_token::mint = valued_token_mint,
-        associated_token::authority = user,
+        associated_token::authority = creator,
         associated_token::token_program = valued_token_program,
         mint::token_program = valued_token_program
     )]
-    pub user_valued_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
-    #[account(
-        init_if_needed,
-        payer = user,
-        associated_token::mint = escrow_token_mint,
-        associated_token::authority = user,
-        associated_token::token_program = token_program,
-        mint::token_program = token_program
-    )]
-    pub user_escrow_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+    pub creator_valued_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
 }
 
-/// Account structure for tracking all vesting sessions
-#[account]
-#[derive(InitSpace)]
-pub struct VestingSessionsAccount {
-    pub last_session_id: u64, // ID to be used for the next vesting session
-    pub user: Pubkey,         // Public key of the user
+/// Parameters for initializing a new token
+#[derive(AnchorSerialize, AnchorDeserialize, Debug, Clone)]
+pub struct ChangeEscrowMetadataParams {
+    pub value: String,
+    pub param_key: String,
 }
 
-/// Account structure for an individual vesting session
-#[account]
-#[derive(InitSpace)]
-pub struct VestingSession {
-    pub id: u64,                          // Unique identifier for this session
-    pub user: Pubkey,                     // Public key of the user
-    pub vesting_sessions_account: Pubkey, // Parent account tracking all sessions
-    pub amount: u64,                      // Total amount of tokens in this vesting session
-    pub amount_withdrawn: u64,            // Amount of tokens already withdrawn
-    pub start_date: u64,                  // Start date of the vesting session
-    pub last_withdraw_at: u64,            // Timestamp of the last withdrawal
-    pub cancelled_at: u64, // Timestamp when the session was cancelled (0 if not cancelled)
+/// Parameters for updating several escrow metadata fields at once
+#[derive(AnchorSerialize, AnchorDeserialize, Debug, Clone)]
+pub struct ChangeEscrowMetadataBatchParams {
+    pub updates: Vec<ChangeEscrowMetadataParams>,
 }
 
-/// Accounts required for creating a vesting session
-#[derive(Accounts)]
-pub struct CreateVestingSession<'info> {
+/// Parameters for removing an additional metadata field of an escrow token
+#[derive(AnchorSerialize, AnchorDeserialize, Debug, Clone)]
+pub struct RemoveEscrowMetadataKeyParams {
+    pub key: String,
+}
+
+/// Parameters for rotating the escrow metadata authorities. None hands the authority back to the vault
+#[derive(AnchorSerialize, AnchorDeserialize, Debug, Clone)]
+pub struct SetEscrowMetadataAuthorityParams {
+    pub update_authority: Option<Pubkey>,
+    pub metadata_pointer_authority: Option<Pubkey>,
+}
+
+/// Accounts required for rotating the escrow metadata authorities
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct SetEscrowMetadataAuthority<'info> {
+    pub token_program: Program<'info, Token2022>,
+    pub valued_token_mint: Box<InterfaceAccount<'info, Mint>>,
+    #[account(mut)]
+    pub escrow_token_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf::id(),
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    #[account(
+        seeds = [
+            b"davincij15_seed"
+        ],
+        bump
+    )]
+    pub backend_data: Box<Account<'info, BackendAccountData>>,
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"token_vault",
+            valued_token_mint.key().as_ref(),
+            escrow_token_mint.key().as_ref(),
+        ],
+        bump,
+        has_one = escrow_token_mint,
+        has_one = valued_token_mint,
+        has_one = creator
+    )]
+    pub vault_account: Box<Account<'info, VaultAccount>>,
+    pub creator: Signer<'info>,
+
+    // Current authorities, only required when they were handed off from the vault
+    #[account(
+        constraint = Some(current_update_authority.key()) == vault_account.metadata_update_authority @ VestingErrorCode::InvalidMetadataAuthority
+    )]
+    pub current_update_authority: Option<Signer<'info>>,
+    #[account(
+        constraint = Some(current_metadata_pointer_authority.key()) == vault_account.metadata_pointer_authority @ VestingErrorCode::InvalidMetadataAuthority
+    )]
+    pub current_metadata_pointer_authority: Option<Signer<'info>>,
+}
+
+/// Update token metadata
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct ChangeEscrowMetadataAccounts<'info> {
+    pub token_program: Program<'info, Token2022>,
+    pub system_program: Program<'info, System>,
+    pub valued_token_mint: Box<InterfaceAccount<'info, Mint>>,
+    #[account(mut)]
+    pub escrow_token_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    //Backend authorization
+    #[account(
+        seeds = [
+            b"davincij15_seed"
+        ],
+        bump
+    )]
+    pub backend_data: Box<Account<'info, BackendAccountData>>,
+    #[account(address = backend_data.backend_account)]
+    pub backend: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"token_vault",
+            valued_token_mint.key().as_ref(),
+            escrow_token_mint.key().as_ref(),
+        ],
+        bump,
+        has_one = escrow_token_mint,
+        has_one = valued_token_mint
+    )]
+    pub vault_account: Box<Account<'info, VaultAccount>>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    /// CHECK: Only receives lamports, must match the backend configured rent refund destination
+    #[account(
+        mut,
+        constraint = Some(rent_refund_destination.key()) == backend_data.rent_refund_destination @ VestingErrorCode::InvalidRentRefundDestination
+    )]
+    pub rent_refund_destination: Option<UncheckedAccount<'info>>,
+}
+
+
+/// Accounts required for token exchange
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+#[instruction(amount: u64)]
+pub struct Exchange<'info> {
+    pub token_program: Program<'info, Token2022>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+    pub valued_token_mint: Box<InterfaceAccount<'info, Mint>>,
+    #[account(mut)]
+    pub escrow_token_mint: Box<InterfaceAccount<'info, Mint>>,
+    pub valued_token_program: Interface<'info, TokenInterface>,
+
+    //Backend authorization
+    #[account(
+        seeds = [
+            b"davincij15_seed"
+        ],
+        bump
+    )]
+    pub backend_data: Box<Account<'info, BackendAccountData>>,
+    #[account(address = backend_data.backend_account)]
+    pub backend: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"token_vault",
+            valued_token_mint.key().as_ref(),
+            escrow_token_mint.key().as_ref(),
+        ],
+        bump,
+        has_one = escrow_token_mint,
+        has_one = valued_token_mint
+    )]
+    pub vault_account: Box<Account<'info, VaultAccount>>,
+
+    #[account(
+        mut,
+        associated_token::mint = valued_token_mint,
+        associated_token::authority = vault_account,
+        associated_token::token_program = valued_token_program,
+        mint::token_program = valued_token_program
+    )]
+    pub valued_vault_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        mut,
+        associated_token::mint = escrow_token_mint,
+        associated_token::authority = vault_account,
+        associated_token::token_program = token_program,
+        mint::token_program = token_program
+    )]
+    pub escrow_vault_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    // User token accounts
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        associated_token::mint = valued_token_mint,
+        associated_token::authority = user,
+        associated_token::token_program = valued_token_program,
+        mint::token_program = valued_token_program
+    )]
+    pub user_valued_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = escrow_token_mint,
+        associated_token::authority = user,
+        associated_token::token_program = token_program,
+        mint::token_program = token_program
+    )]
+    pub user_escrow_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+}
+
+/// Account structure for tracking all vesting sessions
+#[account]
+#[derive(InitSpace)]
+pub struct VestingSessionsAccount {
+    pub last_session_id: u64, // ID to be used for the next vesting session
+    pub user: Pubkey,         // Public key of the user
+    pub active_sessions: u64, // Amount of session accounts of the collection not closed yet
+}
+
+/// Session ids of a user in a vault saved when their sessions collection is closed, so a new
+/// collection never hands out the ids and addresses of the closed sessions again
+#[account]
+#[derive(InitSpace)]
+pub struct SessionIdsAccount {
+    pub user: Pubkey,
+    pub last_session_id: u64, // ID to be used for the next vesting session
+}
+
+/// Account structure for an individual vesting session
+#[account]
+#[derive(InitSpace)]
+pub struct VestingSession {
+    pub id: u64,                          // Unique identifier for this session
+    pub user: Pubkey,                     // Public key of the user
+    pub vesting_sessions_account: Pubkey, // Parent account tracking all sessions
+    pub amount: u64,                      // Total amount of tokens in this vesting session
+    pub amount_withdrawn: u64,            // Amount of tokens already withdrawn
+    pub start_date: u64,                  // Start date of the vesting session
+    pub last_withdraw_at: u64,            // Timestamp of the last withdrawal
+    pub cancelled_at: u64, // Timestamp when the session was cancelled (0 if not cancelled)
+    pub position_mint: Option<Pubkey>, // Position token mint of tokenized sessions, its holder withdraws
+    pub amount_owed: u64, // Released valued tokens a degraded vault could not pay yet
+    pub amount_unlocked: u64, // Released tokens of folded tranches, claimable at once and not part of `amount`
+}
+
+/// Release schedule of a vesting session, shared by the program and the off-chain simulator
+impl From<&VestingSession> for Schedule {
+    fn from(vesting_session: &VestingSession) -> Self {
+        Schedule {
+            amount: vesting_session.amount,
+            amount_withdrawn: vesting_session.amount_withdrawn,
+            start_date: vesting_session.start_date,
+            last_withdraw_at: vesting_session.last_withdraw_at,
+            amount_unlocked: vesting_session.amount_unlocked,
+        }
+    }
+}
+
+/// Accounts required for creating a vesting session
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct CreateVestingSession<'info> {
+    pub valued_token_mint: InterfaceAccount<'info, Mint>,
+    pub escrow_token_mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Program<'info, Token2022>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+
+    //Backend authorization
+    #[account(
+        seeds = [
+            b"davincij15_seed"
+        ],
+        bump
+    )]
+    pub backend_data: Box<Account<'info, BackendAccountData>>,
+    #[account(address = backend_data.backend_account)]
+    pub backend: Signer<'info>,
+
+    /// CHECK: Session ids saved by a closed sessions collection of the user, read if it exists
+    #[account(
+        seeds = [
+            b"user_session_ids",
+            vault_account.key().as_ref(),
+            user.key().as_ref(),
+        ],
+        bump
+    )]
+    pub session_ids_account: UncheckedAccount<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        seeds = [
+            b"user_vesting_session_collection",
+            vault_account.key().as_ref(),
+            user.key().as_ref(),
+        ],
+        bump,
+        space = 8 + VestingSessionsAccount::INIT_SPACE
+    )]
+    pub vesting_sessions_account: Box<Account<'info, VestingSessionsAccount>>,
+
+    #[account(
+        init,
+        payer = user,
+        seeds = [
+            b"user_vesting_session",
+            vesting_sessions_account.key().as_ref(),
+            next_session_id(&vesting_sessions_account, &session_ids_account).to_le_bytes().as_ref(),
+        ],
+        bump,
+        space = 8 + VestingSession::INIT_SPACE
+    )]
+    pub vesting_session_account: Box<Account<'info, VestingSession>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"token_vault",
+            valued_token_mint.key().as_ref(),
+            escrow_token_mint.key().as_ref(),
+        ],
+        bump,
+        has_one = escrow_token_mint,
+        has_one = valued_token_mint
+    )]
+    pub vault_account: Box<Account<'info, VaultAccount>>,
+
+    #[account(
+        mut,
+        associated_token::mint = escrow_token_mint,
+        associated_token::authority = vault_account,
+        associated_token::token_program = token_program,
+        mint::token_program = token_program
+    )]
+    pub escrow_vault_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(mut,
+        associated_token::mint = escrow_token_mint,
+        associated_token::authority = user,
+        associated_token::token_program = token_program,
+        mint::token_program = token_program
+    )]
+    pub user_escrow_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+}
+
+/// Parameters for creating a tokenized vesting session
+#[derive(AnchorSerialize, AnchorDeserialize, Debug, Clone)]
+pub struct CreateTokenizedVestingSessionParams {
+    pub amount: u64,
+    pub name: String,
+    pub symbol: String,
+    pub uri: String,
+}
+
+/// Accounts required for creating a vesting session represented by a position token
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct CreateTokenizedVestingSession<'info> {
+    pub valued_token_mint: InterfaceAccount<'info, Mint>,
+    pub escrow_token_mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Program<'info, Token2022>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+
+    //Backend authorization
+    #[account(
+        seeds = [
+            b"davincij15_seed"
+        ],
+        bump
+    )]
+    pub backend_data: Box<Account<'info, BackendAccountData>>,
+    #[account(address = backend_data.backend_account)]
+    pub backend: Signer<'info>,
+
+    /// CHECK: Session ids saved by a closed sessions collection of the user, read if it exists
+    #[account(
+        seeds = [
+            b"user_session_ids",
+            vault_account.key().as_ref(),
+            user.key().as_ref(),
+        ],
+        bump
+    )]
+    pub session_ids_account: UncheckedAccount<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        seeds = [
+            b"user_vesting_session_collection",
+            vault_account.key().as_ref(),
+            user.key().as_ref(),
+        ],
+        bump,
+        space = 8 + VestingSessionsAccount::INIT_SPACE
+    )]
+    pub vesting_sessions_account: Box<Account<'info, VestingSessionsAccount>>,
+
+    #[account(
+        init,
+        payer = user,
+        seeds = [
+            b"user_vesting_session",
+            vesting_sessions_account.key().as_ref(),
+            next_session_id(&vesting_sessions_account, &session_ids_account).to_le_bytes().as_ref(),
+        ],
+        bump,
+        space = 8 + VestingSession::INIT_SPACE
+    )]
+    pub vesting_session_account: Box<Account<'info, VestingSession>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"token_vault",
+            valued_token_mint.key().as_ref(),
+            escrow_token_mint.key().as_ref(),
+        ],
+        bump,
+        has_one = escrow_token_mint,
+        has_one = valued_token_mint
+    )]
+    pub vault_account: Box<Account<'info, VaultAccount>>,
+
+    // New non-fungible position token of the session
+    #[account(
+        init,
+        seeds = [
+            b"position_mint",
+            vesting_session_account.key().as_ref(),
+        ],
+        bump,
+        payer = user,
+        mint::decimals = 0,
+        mint::token_program = token_program,
+        mint::authority = vault_account,
+        extensions::metadata_pointer::authority = vault_account,
+        extensions::metadata_pointer::metadata_address = position_mint,
+        extensions::close_authority::authority = vault_account
+    )]
+    pub position_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        associated_token::mint = escrow_token_mint,
+        associated_token::authority = vault_account,
+        associated_token::token_program = token_program,
+        mint::token_program = token_program
+    )]
+    pub escrow_vault_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(mut,
+        associated_token::mint = escrow_token_mint,
+        associated_token::authority = user,
+        associated_token::token_program = token_program,
+        mint::token_program = token_program
+    )]
+    pub user_escrow_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init,
+        payer = user,
+        associated_token::mint = position_mint,
+        associated_token::authority = user,
+        associated_token::token_program = token_program
+    )]
+    pub user_position_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+}
+
+/// Accounts required for topping up a vesting session
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct SessionTopUp<'info> {
+    pub valued_token_mint: InterfaceAccount<'info, Mint>,
+    pub escrow_token_mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Program<'info, Token2022>,
+
+    //Backend authorization
+    #[account(
+        seeds = [
+            b"davincij15_seed"
+        ],
+        bump
+    )]
+    pub backend_data: Box<Account<'info, BackendAccountData>>,
+    #[account(address = backend_data.backend_account)]
+    pub backend: Signer<'info>,
+
+    #[account(
+        seeds = [
+            b"user_vesting_session_collection",
+            vault_account.key().as_ref(),
+            user.key().as_ref(),
+        ],
+        bump,
+        has_one = user
+    )]
+    pub vesting_sessions_account: Box<Account<'info, VestingSessionsAccount>>,
+
+    #[account(mut,
+        has_one = user,
+        has_one = vesting_sessions_account,
+        constraint = vesting_session_account.position_mint.is_none() @ VestingErrorCode::SessionIsTokenized
+    )]
+    pub vesting_session_account: Account<'info, VestingSession>,
+
+    #[account(
+        seeds = [
+            b"token_vault",
+            valued_token_mint.key().as_ref(),
+            escrow_token_mint.key().as_ref(),
+        ],
+        bump,
+        has_one = escrow_token_mint,
+        has_one = valued_token_mint
+    )]
+    pub vault_account: Box<Account<'info, VaultAccount>>,
+
+    #[account(
+        mut,
+        associated_token::mint = escrow_token_mint,
+        associated_token::authority = vault_account,
+        associated_token::token_program = token_program,
+        mint::token_program = token_program
+    )]
+    pub escrow_vault_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub user: Signer<'info>,
+
+    #[account(mut,
+        associated_token::mint = escrow_token_mint,
+        associated_token::authority = user,
+        associated_token::token_program = token_program,
+        mint::token_program = token_program
+    )]
+    pub user_escrow_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+}
+
+/// Accounts required for merging vesting sessions, the sessions to merge are passed as remaining accounts
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct MergeSessions<'info> {
+    pub valued_token_mint: InterfaceAccount<'info, Mint>,
+    pub escrow_token_mint: InterfaceAccount<'info, Mint>,
+
+    //Backend authorization
+    #[account(
+        seeds = [
+            b"davincij15_seed"
+        ],
+        bump
+    )]
+    pub backend_data: Box<Account<'info, BackendAccountData>>,
+    #[account(address = backend_data.backend_account)]
+    pub backend: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"user_vesting_session_collection",
+            vault_account.key().as_ref(),
+            user.key().as_ref(),
+        ],
+        bump,
+        has_one = user
+    )]
+    pub vesting_sessions_account: Box<Account<'info, VestingSessionsAccount>>,
+
+    #[account(mut,
+        has_one = user,
+        has_one = vesting_sessions_account,
+        constraint = vesting_session_account.position_mint.is_none() @ VestingErrorCode::SessionIsTokenized
+    )]
+    pub vesting_session_account: Account<'info, VestingSession>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"token_vault",
+            valued_token_mint.key().as_ref(),
+            escrow_token_mint.key().as_ref(),
+        ],
+        bump,
+        has_one = escrow_token_mint,
+        has_one = valued_token_mint
+    )]
+    pub vault_account: Box<Account<'info, VaultAccount>>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+}
+
+/// Accounts required for splitting a vesting session into a new session of the recipient
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct SplitSession<'info> {
+    pub valued_token_mint: InterfaceAccount<'info, Mint>,
+    pub escrow_token_mint: InterfaceAccount<'info, Mint>,
+    pub system_program: Program<'info, System>,
+
+    //Backend authorization
+    #[account(
+        seeds = [
+            b"davincij15_seed"
+        ],
+        bump
+    )]
+    pub backend_data: Box<Account<'info, BackendAccountData>>,
+    #[account(address = backend_data.backend_account)]
+    pub backend: Signer<'info>,
+
+    #[account(
+        seeds = [
+            b"user_vesting_session_collection",
+            vault_account.key().as_ref(),
+            user.key().as_ref(),
+        ],
+        bump,
+        has_one = user
+    )]
+    pub vesting_sessions_account: Box<Account<'info, VestingSessionsAccount>>,
+
+    #[account(mut,
+        has_one = user,
+        has_one = vesting_sessions_account,
+        constraint = vesting_session_account.position_mint.is_none() @ VestingErrorCode::SessionIsTokenized
+    )]
+    pub vesting_session_account: Account<'info, VestingSession>,
+
+    pub recipient: SystemAccount<'info>,
+
+    /// CHECK: Session ids saved by a closed sessions collection of the recipient, read if it exists
+    #[account(
+        seeds = [
+            b"user_session_ids",
+            vault_account.key().as_ref(),
+            recipient.key().as_ref(),
+        ],
+        bump
+    )]
+    pub recipient_session_ids_account: UncheckedAccount<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        seeds = [
+            b"user_vesting_session_collection",
+            vault_account.key().as_ref(),
+            recipient.key().as_ref(),
+        ],
+        bump,
+        space = 8 + VestingSessionsAccount::INIT_SPACE
+    )]
+    pub recipient_vesting_sessions_account: Box<Account<'info, VestingSessionsAccount>>,
+
+    #[account(
+        init,
+        payer = user,
+        seeds = [
+            b"user_vesting_session",
+            recipient_vesting_sessions_account.key().as_ref(),
+            next_session_id(&recipient_vesting_sessions_account, &recipient_session_ids_account).to_le_bytes().as_ref(),
+        ],
+        bump,
+        space = 8 + VestingSession::INIT_SPACE
+    )]
+    pub new_vesting_session_account: Box<Account<'info, VestingSession>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"token_vault",
+            valued_token_mint.key().as_ref(),
+            escrow_token_mint.key().as_ref(),
+        ],
+        bump,
+        has_one = escrow_token_mint,
+        has_one = valued_token_mint
+    )]
+    pub vault_account: Box<Account<'info, VaultAccount>>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+}
+
+/// Accounts required for moving a vesting session to the collection of a new owner
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct TransferSession<'info> {
+    pub valued_token_mint: InterfaceAccount<'info, Mint>,
+    pub escrow_token_mint: InterfaceAccount<'info, Mint>,
+    pub system_program: Program<'info, System>,
+
+    //Backend authorization
+    #[account(
+        seeds = [
+            b"davincij15_seed"
+        ],
+        bump
+    )]
+    pub backend_data: Box<Account<'info, BackendAccountData>>,
+    #[account(address = backend_data.backend_account)]
+    pub backend: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"user_vesting_session_collection",
+            vault_account.key().as_ref(),
+            user.key().as_ref(),
+        ],
+        bump,
+        has_one = user
+    )]
+    pub vesting_sessions_account: Box<Account<'info, VestingSessionsAccount>>,
+
+    #[account(mut,
+        close = user,
+        has_one = user,
+        has_one = vesting_sessions_account,
+        constraint = vesting_session_account.position_mint.is_none() @ VestingErrorCode::SessionIsTokenized
+    )]
+    pub vesting_session_account: Account<'info, VestingSession>,
+
+    pub new_user: SystemAccount<'info>,
+
+    /// CHECK: Session ids saved by a closed sessions collection of the new user, read if it exists
+    #[account(
+        seeds = [
+            b"user_session_ids",
+            vault_account.key().as_ref(),
+            new_user.key().as_ref(),
+        ],
+        bump
+    )]
+    pub new_session_ids_account: UncheckedAccount<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        seeds = [
+            b"user_vesting_session_collection",
+            vault_account.key().as_ref(),
+            new_user.key().as_ref(),
+        ],
+        bump,
+        space = 8 + VestingSessionsAccount::INIT_SPACE
+    )]
+    pub new_vesting_sessions_account: Box<Account<'info, VestingSessionsAccount>>,
+
+    #[account(
+        init,
+        payer = user,
+        seeds = [
+            b"user_vesting_session",
+            new_vesting_sessions_account.key().as_ref(),
+            next_session_id(&new_vesting_sessions_account, &new_session_ids_account).to_le_bytes().as_ref(),
+        ],
+        bump,
+        space = 8 + VestingSession::INIT_SPACE
+    )]
+    pub new_vesting_session_account: Box<Account<'info, VestingSession>>,
+
+    #[account(
+        seeds = [
+            b"token_vault",
+            valued_token_mint.key().as_ref(),
+            escrow_token_mint.key().as_ref(),
+        ],
+        bump,
+        has_one = escrow_token_mint,
+        has_one = valued_token_mint
+    )]
+    pub vault_account: Box<Account<'info, VaultAccount>>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+}
+
+/// Accounts required for closing a settled vesting session
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct CloseSession<'info> {
+    pub valued_token_mint: InterfaceAccount<'info, Mint>,
+    pub escrow_token_mint: InterfaceAccount<'info, Mint>,
+
+    //Backend authorization
+    #[account(
+        seeds = [
+            b"davincij15_seed"
+        ],
+        bump
+    )]
+    pub backend_data: Box<Account<'info, BackendAccountData>>,
+    #[account(address = backend_data.backend_account)]
+    pub backend: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"user_vesting_session_collection",
+            vault_account.key().as_ref(),
+            user.key().as_ref(),
+        ],
+        bump,
+        has_one = user
+    )]
+    pub vesting_sessions_account: Box<Account<'info, VestingSessionsAccount>>,
+
+    #[account(mut,
+        close = user,
+        has_one = user,
+        has_one = vesting_sessions_account
+    )]
+    pub vesting_session_account: Account<'info, VestingSession>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"token_vault",
+            valued_token_mint.key().as_ref(),
+            escrow_token_mint.key().as_ref(),
+        ],
+        bump,
+        has_one = escrow_token_mint,
+        has_one = valued_token_mint
+    )]
+    pub vault_account: Box<Account<'info, VaultAccount>>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    // Position token mint of tokenized sessions, closed with the session once its token is burned
+    #[account(mut)]
+    pub position_mint: Option<Box<InterfaceAccount<'info, Mint>>>,
+    pub token_program: Option<Program<'info, Token2022>>,
+}
+
+/// Accounts required for closing an empty vesting sessions collection
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct CloseSessionsCollection<'info> {
+    pub valued_token_mint: InterfaceAccount<'info, Mint>,
+    pub escrow_token_mint: InterfaceAccount<'info, Mint>,
+    pub system_program: Program<'info, System>,
+
+    //Backend authorization
+    #[account(
+        seeds = [
+            b"davincij15_seed"
+        ],
+        bump
+    )]
+    pub backend_data: Box<Account<'info, BackendAccountData>>,
+    #[account(address = backend_data.backend_account)]
+    pub backend: Signer<'info>,
+
+    #[account(
+        mut,
+        close = user,
+        seeds = [
+            b"user_vesting_session_collection",
+            vault_account.key().as_ref(),
+            user.key().as_ref(),
+        ],
+        bump,
+        has_one = user
+    )]
+    pub vesting_sessions_account: Box<Account<'info, VestingSessionsAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        seeds = [
+            b"user_session_ids",
+            vault_account.key().as_ref(),
+            user.key().as_ref(),
+        ],
+        bump,
+        space = 8 + SessionIdsAccount::INIT_SPACE
+    )]
+    pub session_ids_account: Box<Account<'info, SessionIdsAccount>>,
+
+    #[account(
+        seeds = [
+            b"token_vault",
+            valued_token_mint.key().as_ref(),
+            escrow_token_mint.key().as_ref(),
+        ],
+        bump,
+        has_one = escrow_token_mint,
+        has_one = valued_token_mint
+    )]
+    pub vault_account: Box<Account<'info, VaultAccount>>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+}
+
+/// Accounts required for closing the sessions collection and session ids of a closed vault
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct CloseOrphanedSessionsCollection<'info> {
+    /// CHECK: Only seeds the address of the closed vault
+    pub valued_token_mint: UncheckedAccount<'info>,
+    /// CHECK: Only seeds the address of the closed vault, the escrow mint is closed with it
+    pub escrow_token_mint: UncheckedAccount<'info>,
+
+    //Backend authorization
+    #[account(
+        seeds = [
+            b"davincij15_seed"
+        ],
+        bump
+    )]
+    pub backend_data: Box<Account<'info, BackendAccountData>>,
+    #[account(address = backend_data.backend_account)]
+    pub backend: Signer<'info>,
+
+    #[account(
+        mut,
+        close = user,
+        seeds = [
+            b"user_vesting_session_collection",
+            vault_account.key().as_ref(),
+            user.key().as_ref(),
+        ],
+        bump,
+        has_one = user
+    )]
+    pub vesting_sessions_account: Option<Box<Account<'info, VestingSessionsAccount>>>,
+
+    #[account(
+        mut,
+        close = user,
+        seeds = [
+            b"user_session_ids",
+            vault_account.key().as_ref(),
+            user.key().as_ref(),
+        ],
+        bump,
+        has_one = user
+    )]
+    pub session_ids_account: Option<Box<Account<'info, SessionIdsAccount>>>,
+
+    /// CHECK: Address of the closed vault, which must not hold any data anymore
+    #[account(
+        seeds = [
+            b"token_vault",
+            valued_token_mint.key().as_ref(),
+            escrow_token_mint.key().as_ref(),
+        ],
+        bump,
+        constraint = vault_account.data_is_empty() @ VestingErrorCode::VaultNotClosed
+    )]
+    pub vault_account: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+}
+
+/// Accounts required for withdrawing from a vesting session
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct SessionWithdraw<'info> {
     pub valued_token_mint: InterfaceAccount<'info, Mint>,
     pub escrow_token_mint: InterfaceAccount<'info, Mint>,
     pub token_program: Program<'info, Token2022>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub valued_token_program: Interface<'info, TokenInterface>,
     pub system_program: Program<'info, System>,
 
     //Backend authorization
@@ -347,32 +1557,25 @@ pub struct CreateVestingSession<'info> {
     pub backend: Signer<'info>,
 
     #[account(
-        init_if_needed,
-        payer = user,
         seeds = [
             b"user_vesting_session_collection",
             vault_account.key().as_ref(),
             user.key().as_ref(),
         ],
         bump,
-        space = 8 + VestingSessionsAccount::INIT_SPACE
+        has_one = user
     )]
     pub vesting_sessions_account: Box<Account<'info, VestingSessionsAccount>>,
 
-    #[account(
-        init,
-        payer = user,
-        seeds = [
-            b"user_vesting_session",
-            vesting_sessions_account.key().as_ref(),
-            vesting_sessions_account.last_session_id.to_le_bytes().as_ref(),
-        ],
-        bump,
-        space = 8 + VestingSession::INIT_SPACE
+    #[account(mut,
+        has_one = user,
+        has_one = vesting_sessions_account,
+        constraint = vesting_session_account.position_mint.is_none() @ VestingErrorCode::SessionIsTokenized
     )]
-    pub vesting_session_account: Box<Account<'info, VestingSession>>,
+    pub vesting_session_account: Account<'info, VestingSession>,
 
     #[account(
+        mut,
         seeds = [
             b"token_vault",
             valued_token_mint.key().as_ref(),
@@ -386,33 +1589,32 @@ pub struct CreateVestingSession<'info> {
 
     #[account(
         mut,
-        associated_token::mint = escrow_token_mint,
+        associated_token::mint = valued_token_mint,
         associated_token::authority = vault_account,
-        associated_token::token_program = token_program,
-        mint::token_program = token_program
+        associated_token::token_program = valued_token_program,
+        mint::token_program = valued_token_program
     )]
-    pub escrow_vault_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+    pub valued_vault_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
 
-    #[account(mut)]
     pub user: Signer<'info>,
-
     #[account(mut,
-        associated_token::mint = escrow_token_mint,
+        associated_token::mint = valued_token_mint,
         associated_token::authority = user,
-        associated_token::token_program = token_program,
-        mint::token_program = token_program
+        associated_token::token_program = valued_token_program,
+        mint::token_program = valued_token_program
     )]
-    pub user_escrow_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+    pub user_valued_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
 }
 
-/// Accounts required for withdrawing from a vesting session
+/// Accounts required for withdrawing from a tokenized vesting session by its position holder
+#[cfg_attr(feature = "cpi-events", event_cpi)]
 #[derive(Accounts)]
-pub struct SessionWithdraw<'info> {
-    #[account(mut)]
+pub struct PositionWithdraw<'info> {
     pub valued_token_mint: InterfaceAccount<'info, Mint>,
     pub escrow_token_mint: InterfaceAccount<'info, Mint>,
     pub token_program: Program<'info, Token2022>,
     pub valued_token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
 
     //Backend authorization
@@ -430,20 +1632,30 @@ pub struct SessionWithdraw<'info> {
         seeds = [
             b"user_vesting_session_collection",
             vault_account.key().as_ref(),
-            user.key().as_ref(),
+            vesting_session_account.user.as_ref(),
         ],
-        bump,
-        has_one = user
+        bump
     )]
     pub vesting_sessions_account: Box<Account<'info, VestingSessionsAccount>>,
 
     #[account(mut,
-        has_one = user,
-        has_one = vesting_sessions_account
+        has_one = vesting_sessions_account,
+        constraint = vesting_session_account.position_mint == Some(position_mint.key()) @ VestingErrorCode::InvalidPositionToken
     )]
     pub vesting_session_account: Account<'info, VestingSession>,
 
     #[account(
+        seeds = [
+            b"position_mint",
+            vesting_session_account.key().as_ref(),
+        ],
+        bump,
+        mint::token_program = token_program
+    )]
+    pub position_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
         seeds = [
             b"token_vault",
             valued_token_mint.key().as_ref(),
@@ -464,17 +1676,28 @@ pub struct SessionWithdraw<'info> {
     )]
     pub valued_vault_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
 
-    pub user: Signer<'info>,
-    #[account(mut,
+    #[account(mut)]
+    pub holder: Signer<'info>,
+    #[account(
+        token::mint = position_mint,
+        token::authority = holder,
+        token::token_program = token_program,
+        constraint = holder_position_token_account.amount == 1 @ VestingErrorCode::InvalidPositionToken
+    )]
+    pub holder_position_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        init_if_needed,
+        payer = holder,
         associated_token::mint = valued_token_mint,
-        associated_token::authority = user,
+        associated_token::authority = holder,
         associated_token::token_program = valued_token_program,
         mint::token_program = valued_token_program
     )]
-    pub user_valued_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+    pub holder_valued_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
 }
 
 /// Accounts required for cancelling a vesting session
+#[cfg_attr(feature = "cpi-events", event_cpi)]
 #[derive(Accounts)]
 pub struct SessionCancelation<'info> {
     pub valued_token_mint: InterfaceAccount<'info, Mint>,
@@ -507,7 +1730,8 @@ pub struct SessionCancelation<'info> {
     pub vesting_sessions_account: Box<Account<'info, VestingSessionsAccount>>,
     #[account(mut,
         has_one = user,
-        has_one = vesting_sessions_account
+        has_one = vesting_sessions_account,
+        constraint = vesting_session_account.position_mint.is_none() @ VestingErrorCode::SessionIsTokenized
     )]
     pub vesting_session_account: Account<'info, VestingSession>,
 
@@ -559,3 +1783,216 @@ pub struct SessionCancelation<'info> {
     )]
     pub user_escrow_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
 }
+
+// ##### account migrations #####
+
+/// Layout version of the program accounts. Accounts written with an older layout can not be
+/// deserialized anymore until a `migrate_*` instruction reallocates and backfills them
+#[constant]
+pub const ACCOUNT_LAYOUT_VERSION: u8 = 1;
+
+/// Version 0 layout of [`BackendAccountData`]
+#[derive(AnchorDeserialize, InitSpace)]
+pub struct BackendAccountDataV0 {
+    pub backend_account: Pubkey,
+    pub change_authority: Option<Pubkey>,
+}
+
+/// Version 0 layout of [`VaultAccount`]
+#[derive(AnchorDeserialize, InitSpace)]
+pub struct VaultAccountV0 {
+    pub creator: Pubkey,
+    pub valued_token_mint: Pubkey,
+    pub escrow_token_mint: Pubkey,
+    #[max_len(100)]
+    pub app_id: String,
+}
+
+/// Version 0 layout of [`VestingSessionsAccount`]
+#[derive(AnchorDeserialize, InitSpace)]
+pub struct VestingSessionsAccountV0 {
+    pub last_session_id: u64,
+    pub user: Pubkey,
+}
+
+/// Version 0 layout of [`VestingSession`]
+#[derive(AnchorDeserialize, InitSpace)]
+pub struct VestingSessionV0 {
+    pub id: u64,
+    pub user: Pubkey,
+    pub vesting_sessions_account: Pubkey,
+    pub amount: u64,
+    pub amount_withdrawn: u64,
+    pub start_date: u64,
+    pub last_withdraw_at: u64,
+    pub cancelled_at: u64,
+}
+
+/// Accounts required for migrating the backend configuration to the current layout
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct MigrateBackendData<'info> {
+    pub system_program: Program<'info, System>,
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf::id(),
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    /// CHECK: Backend configuration written with a legacy layout, validated by the instruction
+    #[account(
+        mut,
+        seeds = [
+            b"davincij15_seed"
+        ],
+        bump
+    )]
+    pub backend_data: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+}
+
+/// Accounts required for migrating a vault to the current layout
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct MigrateVault<'info> {
+    pub valued_token_mint: Box<InterfaceAccount<'info, Mint>>,
+    pub escrow_token_mint: Box<InterfaceAccount<'info, Mint>>,
+    pub token_program: Program<'info, Token2022>,
+    pub system_program: Program<'info, System>,
+
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf::id(),
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    #[account(
+        seeds = [
+            b"davincij15_seed"
+        ],
+        bump
+    )]
+    pub backend_data: Box<Account<'info, BackendAccountData>>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    /// CHECK: Vault written with a legacy layout, validated by the instruction
+    #[account(
+        mut,
+        seeds = [
+            b"token_vault",
+            valued_token_mint.key().as_ref(),
+            escrow_token_mint.key().as_ref(),
+        ],
+        bump
+    )]
+    pub vault_account: UncheckedAccount<'info>,
+
+    #[account(
+        associated_token::mint = escrow_token_mint,
+        associated_token::authority = vault_account,
+        associated_token::token_program = token_program,
+        mint::token_program = token_program
+    )]
+    pub escrow_vault_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+}
+
+/// Accounts required for migrating a sessions collection to the current layout
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct MigrateSessionsCollection<'info> {
+    pub valued_token_mint: InterfaceAccount<'info, Mint>,
+    pub escrow_token_mint: InterfaceAccount<'info, Mint>,
+    pub system_program: Program<'info, System>,
+
+    //Backend authorization
+    #[account(
+        seeds = [
+            b"davincij15_seed"
+        ],
+        bump
+    )]
+    pub backend_data: Box<Account<'info, BackendAccountData>>,
+    #[account(mut, address = backend_data.backend_account)]
+    pub backend: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"token_vault",
+            valued_token_mint.key().as_ref(),
+            escrow_token_mint.key().as_ref(),
+        ],
+        bump,
+        has_one = escrow_token_mint,
+        has_one = valued_token_mint
+    )]
+    pub vault_account: Box<Account<'info, VaultAccount>>,
+
+    /// CHECK: Owner of the collection, only seeds its address
+    pub user: UncheckedAccount<'info>,
+
+    /// CHECK: Sessions collection written with a legacy layout, validated by the instruction
+    #[account(
+        mut,
+        seeds = [
+            b"user_vesting_session_collection",
+            vault_account.key().as_ref(),
+            user.key().as_ref(),
+        ],
+        bump
+    )]
+    pub vesting_sessions_account: UncheckedAccount<'info>,
+}
+
+/// Accounts required for migrating a vesting session to the current layout
+#[cfg_attr(feature = "cpi-events", event_cpi)]
+#[derive(Accounts)]
+pub struct MigrateVestingSession<'info> {
+    pub valued_token_mint: InterfaceAccount<'info, Mint>,
+    pub escrow_token_mint: InterfaceAccount<'info, Mint>,
+    pub system_program: Program<'info, System>,
+
+    //Backend authorization
+    #[account(
+        seeds = [
+            b"davincij15_seed"
+        ],
+        bump
+    )]
+    pub backend_data: Box<Account<'info, BackendAccountData>>,
+    #[account(mut, address = backend_data.backend_account)]
+    pub backend: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"token_vault",
+            valued_token_mint.key().as_ref(),
+            escrow_token_mint.key().as_ref(),
+        ],
+        bump,
+        has_one = escrow_token_mint,
+        has_one = valued_token_mint
+    )]
+    pub vault_account: Box<Account<'info, VaultAccount>>,
+
+    /// CHECK: Owner of the collection, only seeds its address
+    pub user: UncheckedAccount<'info>,
+
+    #[account(
+        seeds = [
+            b"user_vesting_session_collection",
+            vault_account.key().as_ref(),
+            user.key().as_ref(),
+        ],
+        bump,
+        has_one = user
+    )]
+    pub vesting_sessions_account: Box<Account<'info, VestingSessionsAccount>>,
+
+    /// CHECK: Vesting session written with a legacy layout, validated by the instruction
+    #[account(mut)]
+    pub vesting_session_account: UncheckedAccount<'info>,
+}
@@ -3,11 +3,12 @@ import ValuedToken from "./valuedToken";
 import BN from "bn.js";
 
 interface IVestingContextParams {
-  valuedToken: ValuedToken;
+  valuedToken: Pick<ValuedToken, "mintAddress">;
   user: PublicKey;
   backend: PublicKey;
   owner: PublicKey;
   programId: PublicKey;
+  appId?: string;
 }
 
 class VestingContext {
@@ -23,6 +24,7 @@ class VestingContext {
   constructor(contextParams: IVestingContextParams) {
     const valuedToken = contextParams.valuedToken;
     this.programId = contextParams.programId;
+    this.appId = contextParams.appId ?? this.appId;
 
     [this.backendDataAccount] = PublicKey.findProgramAddressSync(
       [Buffer.from("davincij15_seed")],
@@ -67,6 +69,14 @@ class VestingContext {
     );
     return publicKey;
   }
+
+  public getPositionMintAccount(vestingSession: PublicKey) {
+    const [publicKey] = PublicKey.findProgramAddressSync(
+      [Buffer.from("position_mint"), vestingSession.toBuffer()],
+      this.programId
+    );
+    return publicKey;
+  }
 }
 
 export default VestingContext;
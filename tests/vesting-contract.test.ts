@@ -1,6 +1,9 @@
 import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
 import { VestingContract } from "../target/types/vesting_contract";
+import { TransferHookCounter } from "../target/types/transfer_hook_counter";
+import vestingContractIdl from "../target/idl/vesting_contract.json";
+import eventSchema from "./fixtures/event-schema.json";
 import {
   AccountInfo,
   Connection,
@@ -11,9 +14,24 @@ import {
 import {
   createMint,
   createAssociatedTokenAccount,
+  createInitializeMintInstruction,
+  createInitializeTransferFeeConfigInstruction,
+  createInitializePermanentDelegateInstruction,
+  createInitializeTransferHookInstruction,
+  transferChecked,
+  ExtensionType,
+  getMintLen,
+  AuthorityType,
+  setAuthority,
   mintTo,
   getAssociatedTokenAddressSync,
+  getExtraAccountMetaAddress,
   getAccount,
+  getMint,
+  getMetadataPointerState,
+  getTokenMetadata,
+  getTransferFeeAmount,
+  getTransferFeeConfig,
   TOKEN_2022_PROGRAM_ID,
 } from "@solana/spl-token";
 import { assert } from "chai";
@@ -29,12 +47,14 @@ const logDebug = (message: string, addNewLine: boolean = false) => {
 };
 
 const TEST_AUTHORITY_ACCOUNT = new Uint8Array(testAccount);
+const VESTING_PERIOD_MINUTES = 180 * 24 * 60;
 
 describe("vesting-contract", () => {
   const provider = anchor.AnchorProvider.env();
   anchor.setProvider(provider);
 
   const program = anchor.workspace.VestingContract as Program<VestingContract>;
+  const hookProgram = anchor.workspace.TransferHookCounter as Program<TransferHookCounter>;
   const userWallet = anchor.web3.Keypair.generate();
   const backendWallet = anchor.web3.Keypair.generate();
   const changeAuthorityWallet = anchor.web3.Keypair.generate();
@@ -65,6 +85,29 @@ describe("vesting-contract", () => {
     return Number(account.amount);
   }
 
+  async function chainTime(): Promise<number> {
+    const clock = await provider.connection.getAccountInfo(anchor.web3.SYSVAR_CLOCK_PUBKEY);
+    return Number(clock.data.readBigInt64LE(32)); // unix_timestamp of the Clock sysvar
+  }
+
+  // Amount a withdrawal at `now` would release, mirrors the release math of the program
+  function claimableAmount(
+    session: Awaited<ReturnType<typeof program.account.vestingSession.fetch>>,
+    now: number
+  ): anchor.BN {
+    const lockedAmount = session.amount.sub(session.amountWithdrawn);
+    if (now >= session.startDate.toNumber() + VESTING_PERIOD_MINUTES * 60) {
+      return lockedAmount.add(session.amountUnlocked);
+    }
+    const releaseReference = session.lastWithdrawAt.gtn(0)
+      ? session.lastWithdrawAt
+      : session.startDate;
+    const releasedAmount = session.amount
+      .divn(VESTING_PERIOD_MINUTES)
+      .muln(Math.floor(Math.max(now - releaseReference.toNumber(), 0) / 60));
+    return anchor.BN.min(releasedAmount, lockedAmount).add(session.amountUnlocked);
+  }
+
   before(async () => {
     // Airdrop SOL to user and backend
     const airdropAmount = 100 * anchor.web3.LAMPORTS_PER_SOL;
@@ -253,7 +296,9 @@ describe("vesting-contract", () => {
       name: string;
       symbol: string;
       uri: string;
-    } = parsedData.parsed.info.extensions[1].state;
+    } = parsedData.parsed.info.extensions.find(
+      (extension) => extension.extension === "tokenMetadata"
+    ).state;
     logDebug(`Initial token metadata: ${JSON.stringify(tokenMetadata)}`);
     const tx = new Transaction();
     tx.add(
@@ -314,7 +359,9 @@ describe("vesting-contract", () => {
       name: string;
       symbol: string;
       uri: string;
-    } = newParsedData.parsed.info.extensions[1].state;
+    } = newParsedData.parsed.info.extensions.find(
+      (extension) => extension.extension === "tokenMetadata"
+    ).state;
     assert.equal(newTokenMetadata.name, newName);
     assert.equal(newTokenMetadata.symbol, newSymbol);
     assert.equal(newTokenMetadata.uri, newUri);
@@ -875,6 +922,2718 @@ describe("vesting-contract", () => {
     );
   });
 
+  it("Tops up an ongoing vesting session", async () => {
+    const toVestAmount = new anchor.BN(1000000000); // 1 token
+    const topUpAmount = new anchor.BN(2000000000); // 2 tokens
+    const vaultSessionsAccounts = vestingContext.getVaultSessionsAccount(userWallet.publicKey);
+    const sessionsAccountData = await program.account.vestingSessionsAccount.fetch(
+      vaultSessionsAccounts
+    );
+    const vestingSessionAccount = vestingContext.getVaulSessionAccount(
+      vaultSessionsAccounts,
+      sessionsAccountData.lastSessionId
+    );
+
+    const createVestingTx = await program.methods
+      .createVestingSession(toVestAmount)
+      .accounts({
+        backendData: vestingContext.backendDataAccount,
+        vestingSessionsAccount: vaultSessionsAccounts,
+        vestingSessionAccount: vestingSessionAccount,
+        vaultAccount: vestingContext.vaultAccount,
+        valuedTokenMint: valuedToken.mintAddress,
+        escrowTokenMint: vestingContext.escrowTokenMintAccount,
+        user: userWallet.publicKey,
+        backend: backendWallet.publicKey,
+      })
+      .signers([backendWallet, userWallet])
+      .rpc();
+    await provider.connection.confirmTransaction(createVestingTx);
+
+    // Wait for the session to release tokens
+    await new Promise((resolve) => setTimeout(resolve, 60 * 1000));
+    const claimableBeforeTopUp = claimableAmount(
+      await program.account.vestingSession.fetch(vestingSessionAccount),
+      await chainTime()
+    );
+    assert(claimableBeforeTopUp.gtn(0), "Session should have released tokens before the top up");
+
+    const escrowUserTokenBalance = await getTokenBalance(userEscrowTokenAccount);
+    logDebug(`Current user escrow token balance: ${escrowUserTokenBalance}`);
+    const escrowVaultBalance = await getTokenBalance(programVaultTokenAccounts.escrowTokenVault);
+    logDebug(`Current vault escrow token balance: ${escrowVaultBalance}`);
+
+    const topUpTx = await program.methods
+      .sessionTopUp(topUpAmount)
+      .accounts({
+        backendData: vestingContext.backendDataAccount,
+        vestingSessionsAccount: vaultSessionsAccounts,
+        vestingSessionAccount: vestingSessionAccount,
+        vaultAccount: vestingContext.vaultAccount,
+        valuedTokenMint: valuedToken.mintAddress,
+        escrowTokenMint: vestingContext.escrowTokenMintAccount,
+        user: userWallet.publicKey,
+        backend: backendWallet.publicKey,
+      })
+      .signers([backendWallet, userWallet])
+      .rpc();
+    await provider.connection.confirmTransaction(topUpTx);
+    logDebug("Vesting session topped up");
+
+    const afterTopUpEscrowUserTokenBalance = await getTokenBalance(userEscrowTokenAccount);
+    logDebug(`After top up user escrow token balance: ${afterTopUpEscrowUserTokenBalance}`);
+    assert.equal(
+      afterTopUpEscrowUserTokenBalance.toString(),
+      new anchor.BN(escrowUserTokenBalance).sub(topUpAmount).toString()
+    );
+
+    const afterTopUpEscrowVaultBalance = await getTokenBalance(
+      programVaultTokenAccounts.escrowTokenVault
+    );
+    logDebug(`After top up vault escrow token balance: ${afterTopUpEscrowVaultBalance}`);
+    assert.equal(
+      afterTopUpEscrowVaultBalance.toString(),
+      new anchor.BN(escrowVaultBalance).add(topUpAmount).toString()
+    );
+
+    const vestingSessionData = await program.account.vestingSession.fetch(vestingSessionAccount);
+    assert.equal(
+      vestingSessionData.amount.add(vestingSessionData.amountUnlocked).toString(),
+      toVestAmount.add(topUpAmount).toString(),
+      "Vesting session should hold the top up"
+    );
+    assert.equal(vestingSessionData.amountWithdrawn.toNumber(), 0);
+    assert(
+      vestingSessionData.amountUnlocked.gte(claimableBeforeTopUp),
+      "Released tokens should stay claimable after the top up"
+    );
+    const claimableAfterTopUp = claimableAmount(vestingSessionData, await chainTime());
+    assert(
+      claimableAfterTopUp.gte(claimableBeforeTopUp),
+      "Topping up should not lock released tokens again"
+    );
+    assert(
+      claimableAfterTopUp.lt(claimableBeforeTopUp.add(topUpAmount)),
+      "The top up should vest instead of being claimable at once"
+    );
+  });
+
+  it("Merges vesting sessions into one", async () => {
+    const toVestAmount = new anchor.BN(1000000000); // 1 token
+    const vaultSessionsAccounts = vestingContext.getVaultSessionsAccount(userWallet.publicKey);
+    const sessionsAccountData = await program.account.vestingSessionsAccount.fetch(
+      vaultSessionsAccounts
+    );
+    const sessionAccounts = [0, 1, 2].map((offset) =>
+      vestingContext.getVaulSessionAccount(
+        vaultSessionsAccounts,
+        sessionsAccountData.lastSessionId.add(new anchor.BN(offset))
+      )
+    );
+
+    for (const sessionAccount of sessionAccounts) {
+      const createVestingTx = await program.methods
+        .createVestingSession(toVestAmount)
+        .accounts({
+          backendData: vestingContext.backendDataAccount,
+          vestingSessionsAccount: vaultSessionsAccounts,
+          vestingSessionAccount: sessionAccount,
+          vaultAccount: vestingContext.vaultAccount,
+          valuedTokenMint: valuedToken.mintAddress,
+          escrowTokenMint: vestingContext.escrowTokenMintAccount,
+          user: userWallet.publicKey,
+          backend: backendWallet.publicKey,
+        })
+        .signers([backendWallet, userWallet])
+        .rpc();
+      await provider.connection.confirmTransaction(createVestingTx);
+    }
+    const [targetSessionAccount, ...mergedSessionAccounts] = sessionAccounts;
+    const userLamports = await checkBalance(userWallet.publicKey, "Current User");
+
+    // Wait for the sessions to release tokens
+    await new Promise((resolve) => setTimeout(resolve, 60 * 1000));
+    const timeBeforeMerge = await chainTime();
+    let claimableBeforeMerge = new anchor.BN(0);
+    for (const sessionAccount of sessionAccounts) {
+      const sessionData = await program.account.vestingSession.fetch(sessionAccount);
+      claimableBeforeMerge = claimableBeforeMerge.add(
+        claimableAmount(sessionData, timeBeforeMerge)
+      );
+    }
+    assert(claimableBeforeMerge.gtn(0), "Sessions should have released tokens before the merge");
+
+    const mergeTx = await program.methods
+      .mergeSessions()
+      .accounts({
+        backendData: vestingContext.backendDataAccount,
+        vestingSessionsAccount: vaultSessionsAccounts,
+        vestingSessionAccount: targetSessionAccount,
+        vaultAccount: vestingContext.vaultAccount,
+        valuedTokenMint: valuedToken.mintAddress,
+        escrowTokenMint: vestingContext.escrowTokenMintAccount,
+        user: userWallet.publicKey,
+        backend: backendWallet.publicKey,
+      })
+      .remainingAccounts(
+        mergedSessionAccounts.map((pubkey) => ({ pubkey, isWritable: true, isSigner: false }))
+      )
+      .signers([backendWallet, userWallet])
+      .rpc();
+    await provider.connection.confirmTransaction(mergeTx);
+    logDebug("Vesting sessions merged");
+
+    const vestingSessionData = await program.account.vestingSession.fetch(targetSessionAccount);
+    assert.equal(
+      vestingSessionData.amount.add(vestingSessionData.amountUnlocked).toString(),
+      toVestAmount.muln(sessionAccounts.length).toString(),
+      "Merged session should hold the tokens of every merged session"
+    );
+    assert(
+      vestingSessionData.amountUnlocked.gte(claimableBeforeMerge),
+      "Released tokens of the merged sessions should stay claimable"
+    );
+    assert(
+      claimableAmount(vestingSessionData, await chainTime()).gte(claimableBeforeMerge),
+      "Merging should not lock released tokens again"
+    );
+    for (const mergedSessionAccount of mergedSessionAccounts) {
+      const mergedAccountInfo = await provider.connection.getAccountInfo(mergedSessionAccount);
+      assert.isNull(mergedAccountInfo, "Merged session accounts should be closed");
+    }
+    const afterMergeUserLamports = await checkBalance(userWallet.publicKey, "After merge User");
+    assert(afterMergeUserLamports > userLamports, "Merged sessions rent should be refunded");
+  });
+
+  it("Splits a vesting session into a session of another user", async () => {
+    const toVestAmount = new anchor.BN(2000000000); // 2 tokens
+    const splitAmount = new anchor.BN(500000000); // 0.5 tokens
+    const vaultSessionsAccounts = vestingContext.getVaultSessionsAccount(userWallet.publicKey);
+    const sessionsAccountData = await program.account.vestingSessionsAccount.fetch(
+      vaultSessionsAccounts
+    );
+    const vestingSessionAccount = vestingContext.getVaulSessionAccount(
+      vaultSessionsAccounts,
+      sessionsAccountData.lastSessionId
+    );
+    const recipientSessionsAccount = vestingContext.getVaultSessionsAccount(
+      notBackendWallet.publicKey
+    );
+    const newVestingSessionAccount = vestingContext.getVaulSessionAccount(
+      recipientSessionsAccount,
+      new anchor.BN(0)
+    );
+
+    const createVestingTx = await program.methods
+      .createVestingSession(toVestAmount)
+      .accounts({
+        backendData: vestingContext.backendDataAccount,
+        vestingSessionsAccount: vaultSessionsAccounts,
+        vestingSessionAccount: vestingSessionAccount,
+        vaultAccount: vestingContext.vaultAccount,
+        valuedTokenMint: valuedToken.mintAddress,
+        escrowTokenMint: vestingContext.escrowTokenMintAccount,
+        user: userWallet.publicKey,
+        backend: backendWallet.publicKey,
+      })
+      .signers([backendWallet, userWallet])
+      .rpc();
+    await provider.connection.confirmTransaction(createVestingTx);
+
+    const splitAccounts = {
+      backendData: vestingContext.backendDataAccount,
+      vestingSessionsAccount: vaultSessionsAccounts,
+      vestingSessionAccount: vestingSessionAccount,
+      recipient: notBackendWallet.publicKey,
+      recipientVestingSessionsAccount: recipientSessionsAccount,
+      newVestingSessionAccount: newVestingSessionAccount,
+      vaultAccount: vestingContext.vaultAccount,
+      valuedTokenMint: valuedToken.mintAddress,
+      escrowTokenMint: vestingContext.escrowTokenMintAccount,
+      user: userWallet.publicKey,
+      backend: backendWallet.publicKey,
+    };
+
+    try {
+      await program.methods
+        .splitSession(splitAmount)
+        .accounts(splitAccounts)
+        .signers([backendWallet, userWallet])
+        .rpc();
+      assert.fail("Should not be able to split to another user in a non transferable vault");
+    } catch (error) {
+      assert.include(error.message, "SessionsNotTransferable");
+    }
+
+    const updateConfigTx = await program.methods
+      .updateVaultConfig({ sessionsTransferable: true, trustedHookPrograms: null, maxExchangedAmount: null })
+      .accounts({
+        backendData: vestingContext.backendDataAccount,
+        vaultAccount: vestingContext.vaultAccount,
+        valuedTokenMint: valuedToken.mintAddress,
+        escrowTokenMint: vestingContext.escrowTokenMintAccount,
+        backend: backendWallet.publicKey,
+      })
+      .signers([backendWallet])
+      .rpc();
+    await provider.connection.confirmTransaction(updateConfigTx);
+
+    const splitTx = await program.methods
+      .splitSession(splitAmount)
+      .accounts(splitAccounts)
+      .signers([backendWallet, userWallet])
+      .rpc();
+    await provider.connection.confirmTransaction(splitTx);
+    logDebug("Vesting session split");
+
+    const vestingSessionData = await program.account.vestingSession.fetch(vestingSessionAccount);
+    const newVestingSessionData = await program.account.vestingSession.fetch(
+      newVestingSessionAccount
+    );
+    assert.equal(newVestingSessionData.user.toBase58(), notBackendWallet.publicKey.toBase58());
+    assert.equal(
+      newVestingSessionData.amount.sub(newVestingSessionData.amountWithdrawn).toString(),
+      splitAmount.toString(),
+      "New session should hold the split amount"
+    );
+    assert.equal(
+      vestingSessionData.amount.add(newVestingSessionData.amount).toString(),
+      toVestAmount.toString(),
+      "Split sessions should add up to the original amount"
+    );
+    assert.equal(
+      newVestingSessionData.startDate.toString(),
+      vestingSessionData.startDate.toString(),
+      "Split session should keep the same schedule"
+    );
+  });
+
+  it("Transfers a vesting session to another wallet", async () => {
+    const vaultSessionsAccounts = vestingContext.getVaultSessionsAccount(
+      notBackendWallet.publicKey
+    );
+    const vestingSessionAccount = vestingContext.getVaulSessionAccount(
+      vaultSessionsAccounts,
+      new anchor.BN(0)
+    );
+    const vestingSessionData = await program.account.vestingSession.fetch(vestingSessionAccount);
+
+    const newVaultSessionsAccounts = vestingContext.getVaultSessionsAccount(userWallet.publicKey);
+    const newSessionsAccountData = await program.account.vestingSessionsAccount.fetch(
+      newVaultSessionsAccounts
+    );
+    const newVestingSessionAccount = vestingContext.getVaulSessionAccount(
+      newVaultSessionsAccounts,
+      newSessionsAccountData.lastSessionId
+    );
+
+    const transferTx = await program.methods
+      .transferSession()
+      .accounts({
+        backendData: vestingContext.backendDataAccount,
+        vestingSessionsAccount: vaultSessionsAccounts,
+        vestingSessionAccount: vestingSessionAccount,
+        newUser: userWallet.publicKey,
+        newVestingSessionsAccount: newVaultSessionsAccounts,
+        newVestingSessionAccount: newVestingSessionAccount,
+        vaultAccount: vestingContext.vaultAccount,
+        valuedTokenMint: valuedToken.mintAddress,
+        escrowTokenMint: vestingContext.escrowTokenMintAccount,
+        user: notBackendWallet.publicKey,
+        backend: backendWallet.publicKey,
+      })
+      .signers([backendWallet, notBackendWallet])
+      .rpc();
+    await provider.connection.confirmTransaction(transferTx);
+    logDebug("Vesting session transferred");
+
+    const previousAccountInfo = await provider.connection.getAccountInfo(vestingSessionAccount);
+    assert.isNull(previousAccountInfo, "Previous session account should be closed");
+
+    const newVestingSessionData = await program.account.vestingSession.fetch(
+      newVestingSessionAccount
+    );
+    assert.equal(newVestingSessionData.user.toBase58(), userWallet.publicKey.toBase58());
+    assert.equal(newVestingSessionData.amount.toString(), vestingSessionData.amount.toString());
+    assert.equal(
+      newVestingSessionData.amountWithdrawn.toString(),
+      vestingSessionData.amountWithdrawn.toString()
+    );
+    assert.equal(
+      newVestingSessionData.startDate.toString(),
+      vestingSessionData.startDate.toString()
+    );
+  });
+
+  it("Creates a tokenized vesting session owned by its position token", async () => {
+    const toVestAmount = new anchor.BN(500000000); // 0.5 tokens
+    const vaultSessionsAccounts = vestingContext.getVaultSessionsAccount(userWallet.publicKey);
+    const sessionsAccountData = await program.account.vestingSessionsAccount.fetch(
+      vaultSessionsAccounts
+    );
+    const vestingSessionAccount = vestingContext.getVaulSessionAccount(
+      vaultSessionsAccounts,
+      sessionsAccountData.lastSessionId
+    );
+    const positionMintAccount = vestingContext.getPositionMintAccount(vestingSessionAccount);
+    const userPositionTokenAccount = getAssociatedTokenAddressSync(
+      positionMintAccount,
+      userWallet.publicKey,
+      false,
+      TOKEN_2022_PROGRAM_ID
+    );
+
+    const tokenizedAccounts = {
+      backendData: vestingContext.backendDataAccount,
+      vestingSessionsAccount: vaultSessionsAccounts,
+      vestingSessionAccount: vestingSessionAccount,
+      vaultAccount: vestingContext.vaultAccount,
+      positionMint: positionMintAccount,
+      valuedTokenMint: valuedToken.mintAddress,
+      escrowTokenMint: vestingContext.escrowTokenMintAccount,
+      user: userWallet.publicKey,
+      userPositionTokenAccount: userPositionTokenAccount,
+      backend: backendWallet.publicKey,
+    };
+    const positionMetadata = {
+      name: "Vesting position",
+      symbol: "vPOS",
+      uri: valuedToken.description.uri,
+    };
+
+    for (const [invalidMetadata, errorCode] of [
+      [{ ...positionMetadata, name: "" }, "InvalidMeta"],
+      [{ ...positionMetadata, uri: "u".repeat(201) }, "MetadataValueTooLong"],
+    ] as const) {
+      try {
+        await program.methods
+          .createTokenizedVestingSession({ amount: toVestAmount, ...invalidMetadata })
+          .accounts(tokenizedAccounts)
+          .signers([backendWallet, userWallet])
+          .rpc();
+        assert.fail("Invalid position metadata should be rejected");
+      } catch (error) {
+        assert.include(error.message, errorCode);
+      }
+    }
+
+    const createVestingTx = await program.methods
+      .createTokenizedVestingSession({ amount: toVestAmount, ...positionMetadata })
+      .accounts(tokenizedAccounts)
+      .signers([backendWallet, userWallet])
+      .rpc();
+    await provider.connection.confirmTransaction(createVestingTx);
+    logDebug("Tokenized vesting session created");
+
+    const vestingSessionData = await program.account.vestingSession.fetch(vestingSessionAccount);
+    assert.equal(vestingSessionData.positionMint.toBase58(), positionMintAccount.toBase58());
+    const positionTokenBalance = await getTokenBalance(userPositionTokenAccount);
+    assert.equal(positionTokenBalance, 1, "User should hold the single position token");
+
+    try {
+      await program.methods
+        .sessionWithdraw()
+        .accounts({
+          valuedTokenProgram: valuedToken.mintInfo.owner,
+          backendData: vestingContext.backendDataAccount,
+          vestingSessionsAccount: vaultSessionsAccounts,
+          vestingSessionAccount: vestingSessionAccount,
+          vaultAccount: vestingContext.vaultAccount,
+          valuedTokenMint: valuedToken.mintAddress,
+          escrowTokenMint: vestingContext.escrowTokenMintAccount,
+          user: userWallet.publicKey,
+          backend: backendWallet.publicKey,
+        })
+        .signers([backendWallet, userWallet])
+        .rpc();
+      assert.fail("Tokenized sessions should only be withdrawn by the position holder");
+    } catch (error) {
+      assert.include(error.message, "SessionIsTokenized");
+    }
+  });
+
+  it("Closes settled vesting sessions and keeps non empty collections open", async () => {
+    const vaultSessionsAccounts = vestingContext.getVaultSessionsAccount(userWallet.publicKey);
+    const cancelledSessionAccount = vestingContext.getVaulSessionAccount(
+      vaultSessionsAccounts,
+      new anchor.BN(0)
+    );
+    const sessionsAccountData = await program.account.vestingSessionsAccount.fetch(
+      vaultSessionsAccounts
+    );
+    const activeSessionAccount = vestingContext.getVaulSessionAccount(
+      vaultSessionsAccounts,
+      sessionsAccountData.lastSessionId.sub(new anchor.BN(1))
+    );
+    const closeAccounts = {
+      backendData: vestingContext.backendDataAccount,
+      vestingSessionsAccount: vaultSessionsAccounts,
+      vaultAccount: vestingContext.vaultAccount,
+      valuedTokenMint: valuedToken.mintAddress,
+      escrowTokenMint: vestingContext.escrowTokenMintAccount,
+      user: userWallet.publicKey,
+      backend: backendWallet.publicKey,
+    };
+
+    try {
+      await program.methods
+        .closeSession()
+        .accounts({ ...closeAccounts, vestingSessionAccount: activeSessionAccount })
+        .signers([backendWallet, userWallet])
+        .rpc();
+      assert.fail("Should not be able to close a session which is not settled");
+    } catch (error) {
+      assert.include(error.message, "SessionNotSettled");
+    }
+
+    const closeTx = await program.methods
+      .closeSession()
+      .accounts({ ...closeAccounts, vestingSessionAccount: cancelledSessionAccount })
+      .signers([backendWallet, userWallet])
+      .rpc();
+    await provider.connection.confirmTransaction(closeTx);
+    logDebug("Cancelled vesting session closed");
+
+    const closedAccountInfo = await provider.connection.getAccountInfo(cancelledSessionAccount);
+    assert.isNull(closedAccountInfo, "Settled session account should be closed");
+    const afterCloseSessionsAccountData = await program.account.vestingSessionsAccount.fetch(
+      vaultSessionsAccounts
+    );
+    assert.equal(
+      afterCloseSessionsAccountData.activeSessions.toString(),
+      sessionsAccountData.activeSessions.sub(new anchor.BN(1)).toString()
+    );
+
+    try {
+      await program.methods
+        .closeSessionsCollection()
+        .accounts(closeAccounts)
+        .signers([backendWallet, userWallet])
+        .rpc();
+      assert.fail("Should not be able to close a collection with open sessions");
+    } catch (error) {
+      assert.include(error.message, "SessionsCollectionNotEmpty");
+    }
+  });
+
+  it("Keeps numbering sessions after a closed collection is reopened", async () => {
+    const vestAmount = new anchor.BN(100000000); // 0.1 tokens
+    const reopeningWallet = anchor.web3.Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(
+        reopeningWallet.publicKey,
+        anchor.web3.LAMPORTS_PER_SOL
+      )
+    );
+    await createAssociatedTokenAccount(
+      provider.connection,
+      reopeningWallet,
+      valuedToken.mintAddress,
+      reopeningWallet.publicKey,
+      undefined,
+      valuedToken.mintInfo.owner
+    );
+    const reopeningEscrowAccount = await createAssociatedTokenAccount(
+      provider.connection,
+      reopeningWallet,
+      vestingContext.escrowTokenMintAccount,
+      reopeningWallet.publicKey,
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+    await transferChecked(
+      provider.connection,
+      userWallet,
+      getAssociatedTokenAddressSync(
+        vestingContext.escrowTokenMintAccount,
+        userWallet.publicKey,
+        false,
+        TOKEN_2022_PROGRAM_ID
+      ),
+      vestingContext.escrowTokenMintAccount,
+      reopeningEscrowAccount,
+      userWallet,
+      BigInt(vestAmount.toString()),
+      9,
+      [],
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+
+    const vaultSessionsAccount = vestingContext.getVaultSessionsAccount(
+      reopeningWallet.publicKey
+    );
+    const sessionIdsAccount = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("user_session_ids"),
+        vestingContext.vaultAccount.toBuffer(),
+        reopeningWallet.publicKey.toBuffer(),
+      ],
+      program.programId
+    )[0];
+    const sessionAccounts = (sessionId: number) => ({
+      valuedTokenProgram: valuedToken.mintInfo.owner,
+      backendData: vestingContext.backendDataAccount,
+      sessionIdsAccount,
+      vestingSessionsAccount: vaultSessionsAccount,
+      vestingSessionAccount: vestingContext.getVaulSessionAccount(
+        vaultSessionsAccount,
+        new anchor.BN(sessionId)
+      ),
+      vaultAccount: vestingContext.vaultAccount,
+      valuedTokenMint: valuedToken.mintAddress,
+      escrowTokenMint: vestingContext.escrowTokenMintAccount,
+      user: reopeningWallet.publicKey,
+      backend: backendWallet.publicKey,
+    });
+
+    // Settle and close the only session of the collection, then the collection itself
+    await program.methods
+      .createVestingSession(vestAmount)
+      .accounts(sessionAccounts(0))
+      .signers([backendWallet, reopeningWallet])
+      .rpc();
+    await program.methods
+      .sessionExit()
+      .accounts(sessionAccounts(0))
+      .signers([backendWallet, reopeningWallet])
+      .rpc();
+    await program.methods
+      .closeSession()
+      .accounts(sessionAccounts(0))
+      .signers([backendWallet, reopeningWallet])
+      .rpc();
+    const closeCollectionTx = await program.methods
+      .closeSessionsCollection()
+      .accounts(sessionAccounts(0))
+      .signers([backendWallet, reopeningWallet])
+      .rpc();
+    await provider.connection.confirmTransaction(closeCollectionTx);
+    assert.isNull(await provider.connection.getAccountInfo(vaultSessionsAccount));
+    const sessionIdsData = await program.account.sessionIdsAccount.fetch(sessionIdsAccount);
+    assert.equal(sessionIdsData.lastSessionId.toString(), "1");
+
+    // The reopened collection continues from the kept id instead of reusing the session 0 address
+    const createTx = await program.methods
+      .createVestingSession(vestAmount)
+      .accounts(sessionAccounts(1))
+      .signers([backendWallet, reopeningWallet])
+      .rpc();
+    await provider.connection.confirmTransaction(createTx);
+    logDebug("Vesting session created in a reopened collection");
+
+    const vestingSessionData = await program.account.vestingSession.fetch(
+      sessionAccounts(1).vestingSessionAccount
+    );
+    assert.equal(vestingSessionData.id.toString(), "1");
+    const sessionsAccountData = await program.account.vestingSessionsAccount.fetch(
+      vaultSessionsAccount
+    );
+    assert.equal(sessionsAccountData.lastSessionId.toString(), "2");
+  });
+
+  it("Refuses to close a vault with active sessions", async () => {
+    try {
+      await program.methods
+        .closeVault()
+        .accounts({
+          valuedTokenProgram: valuedToken.mintInfo.owner,
+          backendData: vestingContext.backendDataAccount,
+          vaultAccount: vestingContext.vaultAccount,
+          valuedTokenMint: valuedToken.mintAddress,
+          escrowTokenMint: vestingContext.escrowTokenMintAccount,
+          creator: userWallet.publicKey,
+          backend: backendWallet.publicKey,
+        })
+        .signers([backendWallet, userWallet])
+        .rpc();
+      assert.fail("Should not be able to close a vault with active sessions");
+    } catch (error) {
+      assert.include(error.message, "VaultHasActiveSessions");
+    }
+
+    const vaultData = await program.account.vaultAccount.fetch(vestingContext.vaultAccount);
+    assert(vaultData.activeSessions.toNumber() > 0, "Vault should track its open sessions");
+  });
+
+  it("Exchanges a transfer fee token minting escrow for the amount received", async () => {
+    const feeBasisPoints = 100; // 1%
+    const exchangeAmount = BigInt(1000000000); // 1 token
+    const expectedFee = (exchangeAmount * BigInt(feeBasisPoints)) / BigInt(10000);
+    const feeMintKeypair = anchor.web3.Keypair.generate();
+    const feeMint = feeMintKeypair.publicKey;
+
+    const mintLen = getMintLen([ExtensionType.TransferFeeConfig]);
+    const mintLamports = await provider.connection.getMinimumBalanceForRentExemption(mintLen);
+    const createMintTx = new Transaction().add(
+      anchor.web3.SystemProgram.createAccount({
+        fromPubkey: userWallet.publicKey,
+        newAccountPubkey: feeMint,
+        space: mintLen,
+        lamports: mintLamports,
+        programId: TOKEN_2022_PROGRAM_ID,
+      }),
+      createInitializeTransferFeeConfigInstruction(
+        feeMint,
+        userWallet.publicKey,
+        userWallet.publicKey,
+        feeBasisPoints,
+        exchangeAmount,
+        TOKEN_2022_PROGRAM_ID
+      ),
+      createInitializeMintInstruction(feeMint, 9, userWallet.publicKey, null, TOKEN_2022_PROGRAM_ID)
+    );
+    await provider.sendAndConfirm(createMintTx, [userWallet, feeMintKeypair]);
+
+    const userFeeTokenAccount = await createAssociatedTokenAccount(
+      provider.connection,
+      userWallet,
+      feeMint,
+      userWallet.publicKey,
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+    await mintTo(
+      provider.connection,
+      userWallet,
+      feeMint,
+      userFeeTokenAccount,
+      userWallet,
+      exchangeAmount,
+      [],
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+
+    const feeVestingContext = new VestingContext({
+      valuedToken: { mintAddress: feeMint },
+      user: userWallet.publicKey,
+      backend: backendWallet.publicKey,
+      owner: program_authority.publicKey,
+      programId: program.programId,
+    });
+
+    const tx = new Transaction();
+    tx.add(
+      await program.methods
+        .initEscrowToken({
+          name: "Fee Token",
+          symbol: "esFEE",
+          uri: valuedToken.description.uri,
+          appId: feeVestingContext.appId,
+        })
+        .accounts({
+          valuedTokenProgram: TOKEN_2022_PROGRAM_ID,
+          backend_data: feeVestingContext.backendDataAccount,
+          vaultAccount: feeVestingContext.vaultAccount,
+          valuedTokenMint: feeMint,
+          escrowTokenMint: feeVestingContext.escrowTokenMintAccount,
+          payer: userWallet.publicKey,
+          backend: backendWallet.publicKey,
+        })
+        .instruction()
+    );
+    tx.add(
+      await program.methods
+        .initVaultTokenAccounts()
+        .accounts({
+          valuedTokenProgram: TOKEN_2022_PROGRAM_ID,
+          backend_data: feeVestingContext.backendDataAccount,
+          vaultAccount: feeVestingContext.vaultAccount,
+          valuedTokenMint: feeMint,
+          escrowTokenMint: feeVestingContext.escrowTokenMintAccount,
+          payer: userWallet.publicKey,
+          backend: backendWallet.publicKey,
+        })
+        .instruction()
+    );
+    const txReceipt = await provider.connection.sendTransaction(tx, [backendWallet, userWallet]);
+    await provider.connection.confirmTransaction(txReceipt);
+
+    const exchangeTx = await program.methods
+      .exchange(new anchor.BN(exchangeAmount.toString()))
+      .accounts({
+        valuedTokenProgram: TOKEN_2022_PROGRAM_ID,
+        backendData: feeVestingContext.backendDataAccount,
+        vaultAccount: feeVestingContext.vaultAccount,
+        valuedTokenMint: feeMint,
+        escrowTokenMint: feeVestingContext.escrowTokenMintAccount,
+        user: userWallet.publicKey,
+        backend: backendWallet.publicKey,
+      })
+      .signers([backendWallet, userWallet])
+      .rpc();
+    await provider.connection.confirmTransaction(exchangeTx);
+
+    const amountReceived = exchangeAmount - expectedFee;
+    const vaultFeeTokenAccount = getAssociatedTokenAddressSync(
+      feeMint,
+      feeVestingContext.vaultAccount,
+      true,
+      TOKEN_2022_PROGRAM_ID
+    );
+    const vaultBalance = await getTokenBalance(vaultFeeTokenAccount);
+    logDebug(`Vault received ${vaultBalance} tokens out of ${exchangeAmount} sent`);
+    assert.equal(vaultBalance.toString(), amountReceived.toString());
+
+    const userEscrowAccount = getAssociatedTokenAddressSync(
+      feeVestingContext.escrowTokenMintAccount,
+      userWallet.publicKey,
+      false,
+      TOKEN_2022_PROGRAM_ID
+    );
+    const userEscrowBalance = await getTokenBalance(userEscrowAccount);
+    assert.equal(
+      userEscrowBalance.toString(),
+      amountReceived.toString(),
+      "Escrow should be minted for the amount received by the vault"
+    );
+  });
+
+  it("Closes a drained transfer fee vault holding withheld fees", async () => {
+    const feeBasisPoints = 100; // 1%
+    const exchangeAmount = BigInt(1000000000); // 1 token
+    const feeMintKeypair = anchor.web3.Keypair.generate();
+    const feeMint = feeMintKeypair.publicKey;
+
+    const mintLen = getMintLen([ExtensionType.TransferFeeConfig]);
+    const mintLamports = await provider.connection.getMinimumBalanceForRentExemption(mintLen);
+    await provider.sendAndConfirm(
+      new Transaction().add(
+        anchor.web3.SystemProgram.createAccount({
+          fromPubkey: userWallet.publicKey,
+          newAccountPubkey: feeMint,
+          space: mintLen,
+          lamports: mintLamports,
+          programId: TOKEN_2022_PROGRAM_ID,
+        }),
+        createInitializeTransferFeeConfigInstruction(
+          feeMint,
+          userWallet.publicKey,
+          userWallet.publicKey,
+          feeBasisPoints,
+          exchangeAmount,
+          TOKEN_2022_PROGRAM_ID
+        ),
+        createInitializeMintInstruction(
+          feeMint,
+          9,
+          userWallet.publicKey,
+          null,
+          TOKEN_2022_PROGRAM_ID
+        )
+      ),
+      [userWallet, feeMintKeypair]
+    );
+    const userFeeTokenAccount = await createAssociatedTokenAccount(
+      provider.connection,
+      userWallet,
+      feeMint,
+      userWallet.publicKey,
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+    await mintTo(
+      provider.connection,
+      userWallet,
+      feeMint,
+      userFeeTokenAccount,
+      userWallet,
+      exchangeAmount,
+      [],
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+
+    const closingContext = new VestingContext({
+      valuedToken: { mintAddress: feeMint },
+      user: userWallet.publicKey,
+      backend: backendWallet.publicKey,
+      owner: program_authority.publicKey,
+      programId: program.programId,
+    });
+    const vaultAccounts = {
+      valuedTokenProgram: TOKEN_2022_PROGRAM_ID,
+      backend_data: closingContext.backendDataAccount,
+      vaultAccount: closingContext.vaultAccount,
+      valuedTokenMint: feeMint,
+      escrowTokenMint: closingContext.escrowTokenMintAccount,
+      payer: userWallet.publicKey,
+      backend: backendWallet.publicKey,
+    };
+    const initTx = new Transaction();
+    initTx.add(
+      await program.methods
+        .initEscrowToken({
+          name: "Closing Fee Token",
+          symbol: "esCLS",
+          uri: valuedToken.description.uri,
+          appId: closingContext.appId,
+        })
+        .accounts(vaultAccounts)
+        .instruction()
+    );
+    initTx.add(await program.methods.initVaultTokenAccounts().accounts(vaultAccounts).instruction());
+    const initReceipt = await provider.connection.sendTransaction(initTx, [
+      backendWallet,
+      userWallet,
+    ]);
+    await provider.connection.confirmTransaction(initReceipt);
+
+    await program.methods
+      .exchange(new anchor.BN(exchangeAmount.toString()))
+      .accounts({
+        valuedTokenProgram: TOKEN_2022_PROGRAM_ID,
+        backendData: closingContext.backendDataAccount,
+        vaultAccount: closingContext.vaultAccount,
+        valuedTokenMint: feeMint,
+        escrowTokenMint: closingContext.escrowTokenMintAccount,
+        user: userWallet.publicKey,
+        backend: backendWallet.publicKey,
+      })
+      .signers([backendWallet, userWallet])
+      .rpc();
+    const userEscrowAccount = getAssociatedTokenAddressSync(
+      closingContext.escrowTokenMintAccount,
+      userWallet.publicKey,
+      false,
+      TOKEN_2022_PROGRAM_ID
+    );
+    const escrowAmount = await getTokenBalance(userEscrowAccount);
+
+    // Vest every escrow token and exit, leaving a settled session and the withheld exchange fee
+    const vaultSessionsAccount = closingContext.getVaultSessionsAccount(userWallet.publicKey);
+    const vestingSessionAccount = closingContext.getVaulSessionAccount(
+      vaultSessionsAccount,
+      new anchor.BN(0)
+    );
+    const sessionAccounts = {
+      valuedTokenProgram: TOKEN_2022_PROGRAM_ID,
+      backendData: closingContext.backendDataAccount,
+      vestingSessionsAccount: vaultSessionsAccount,
+      vestingSessionAccount,
+      vaultAccount: closingContext.vaultAccount,
+      valuedTokenMint: feeMint,
+      escrowTokenMint: closingContext.escrowTokenMintAccount,
+      user: userWallet.publicKey,
+      backend: backendWallet.publicKey,
+    };
+    await program.methods
+      .createVestingSession(new anchor.BN(escrowAmount))
+      .accounts(sessionAccounts)
+      .signers([backendWallet, userWallet])
+      .rpc();
+    await program.methods
+      .sessionExit()
+      .accounts(sessionAccounts)
+      .signers([backendWallet, userWallet])
+      .rpc();
+    // Settled sessions still count as open until they are closed
+    await program.methods
+      .closeSession()
+      .accounts(sessionAccounts)
+      .signers([backendWallet, userWallet])
+      .rpc();
+
+    const vaultFeeTokenAccount = getAssociatedTokenAddressSync(
+      feeMint,
+      closingContext.vaultAccount,
+      true,
+      TOKEN_2022_PROGRAM_ID
+    );
+    const vaultFeeAccountData = await getAccount(
+      provider.connection,
+      vaultFeeTokenAccount,
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+    assert.equal(vaultFeeAccountData.amount.toString(), "0");
+    assert(
+      getTransferFeeAmount(vaultFeeAccountData).withheldAmount > BigInt(0),
+      "The vault token account should hold the fee withheld on exchange"
+    );
+
+    const orphanedCollectionAccounts = {
+      backendData: closingContext.backendDataAccount,
+      vestingSessionsAccount: vaultSessionsAccount,
+      vaultAccount: closingContext.vaultAccount,
+      valuedTokenMint: feeMint,
+      escrowTokenMint: closingContext.escrowTokenMintAccount,
+      user: userWallet.publicKey,
+      backend: backendWallet.publicKey,
+    };
+    try {
+      await program.methods
+        .closeOrphanedSessionsCollection()
+        .accounts(orphanedCollectionAccounts)
+        .signers([backendWallet, userWallet])
+        .rpc();
+      assert.fail("Collections of an open vault should be closed through the vault");
+    } catch (error) {
+      assert.include(error.message, "VaultNotClosed");
+    }
+
+    const closeTx = await program.methods
+      .closeVault()
+      .accounts({
+        valuedTokenProgram: TOKEN_2022_PROGRAM_ID,
+        backendData: closingContext.backendDataAccount,
+        vaultAccount: closingContext.vaultAccount,
+        valuedTokenMint: feeMint,
+        escrowTokenMint: closingContext.escrowTokenMintAccount,
+        creator: userWallet.publicKey,
+        backend: backendWallet.publicKey,
+      })
+      .signers([backendWallet, userWallet])
+      .rpc();
+    await provider.connection.confirmTransaction(closeTx);
+    logDebug("Drained vault closed");
+
+    for (const closedAccount of [
+      closingContext.vaultAccount,
+      vaultFeeTokenAccount,
+      getAssociatedTokenAddressSync(
+        closingContext.escrowTokenMintAccount,
+        closingContext.vaultAccount,
+        true,
+        TOKEN_2022_PROGRAM_ID
+      ),
+      closingContext.escrowTokenMintAccount,
+    ]) {
+      assert.isNull(
+        await provider.connection.getAccountInfo(closedAccount),
+        `${closedAccount.toBase58()} should be closed`
+      );
+    }
+    const feeMintData = await getMint(
+      provider.connection,
+      feeMint,
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+    assert(
+      getTransferFeeConfig(feeMintData).withheldAmount > BigInt(0),
+      "Withheld fees should be harvested to the mint"
+    );
+
+    // The emptied collection outlives the vault until the user reclaims its rent
+    await program.methods
+      .closeOrphanedSessionsCollection()
+      .accounts(orphanedCollectionAccounts)
+      .signers([backendWallet, userWallet])
+      .rpc();
+    assert.isNull(await provider.connection.getAccountInfo(vaultSessionsAccount));
+  });
+
+  it("Updates the transfer hook programs trusted by a vault", async () => {
+    const configAccounts = {
+      backendData: vestingContext.backendDataAccount,
+      vaultAccount: vestingContext.vaultAccount,
+      valuedTokenMint: valuedToken.mintAddress,
+      escrowTokenMint: vestingContext.escrowTokenMintAccount,
+      backend: backendWallet.publicKey,
+    };
+    const tooManyHookPrograms = Array.from(
+      { length: 6 },
+      () => anchor.web3.Keypair.generate().publicKey
+    );
+
+    try {
+      await program.methods
+        .updateVaultConfig({
+          sessionsTransferable: null,
+          trustedHookPrograms: tooManyHookPrograms,
+          maxExchangedAmount: null,
+        })
+        .accounts(configAccounts)
+        .signers([backendWallet])
+        .rpc();
+      assert.fail("Should not be able to trust more hook programs than allowed");
+    } catch (error) {
+      assert.include(error.message, "TooManyTrustedHookPrograms");
+    }
+
+    const trustedHookProgram = anchor.web3.Keypair.generate().publicKey;
+    const tx = await program.methods
+      .updateVaultConfig({
+        sessionsTransferable: null,
+        trustedHookPrograms: [trustedHookProgram],
+        maxExchangedAmount: null,
+      })
+      .accounts(configAccounts)
+      .signers([backendWallet])
+      .rpc();
+    await provider.connection.confirmTransaction(tx);
+
+    const vaultData = await program.account.vaultAccount.fetch(vestingContext.vaultAccount);
+    assert.equal(vaultData.trustedHookPrograms.length, 1);
+    assert.equal(vaultData.trustedHookPrograms[0].toBase58(), trustedHookProgram.toBase58());
+    assert.isTrue(vaultData.sessionsTransferable, "Omitted settings should be kept");
+  });
+
+  it("Forwards the extra accounts of an opted-in transfer hook valued token", async () => {
+    const exchangeAmount = BigInt(1000000000); // 1 token
+    const hookMintKeypair = anchor.web3.Keypair.generate();
+    const hookMint = hookMintKeypair.publicKey;
+
+    const mintLen = getMintLen([ExtensionType.TransferHook]);
+    const mintLamports = await provider.connection.getMinimumBalanceForRentExemption(mintLen);
+    await provider.sendAndConfirm(
+      new Transaction().add(
+        anchor.web3.SystemProgram.createAccount({
+          fromPubkey: userWallet.publicKey,
+          newAccountPubkey: hookMint,
+          space: mintLen,
+          lamports: mintLamports,
+          programId: TOKEN_2022_PROGRAM_ID,
+        }),
+        createInitializeTransferHookInstruction(
+          hookMint,
+          userWallet.publicKey,
+          hookProgram.programId,
+          TOKEN_2022_PROGRAM_ID
+        ),
+        createInitializeMintInstruction(
+          hookMint,
+          9,
+          userWallet.publicKey,
+          null,
+          TOKEN_2022_PROGRAM_ID
+        )
+      ),
+      [userWallet, hookMintKeypair]
+    );
+    const extraAccountMetaList = getExtraAccountMetaAddress(hookMint, hookProgram.programId);
+    const counterAccount = PublicKey.findProgramAddressSync(
+      [Buffer.from("counter"), hookMint.toBuffer()],
+      hookProgram.programId
+    )[0];
+    await hookProgram.methods
+      .initializeExtraAccountMetaList()
+      .accounts({ payer: userWallet.publicKey, mint: hookMint })
+      .signers([userWallet])
+      .rpc();
+    const userHookTokenAccount = await createAssociatedTokenAccount(
+      provider.connection,
+      userWallet,
+      hookMint,
+      userWallet.publicKey,
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+    await mintTo(
+      provider.connection,
+      userWallet,
+      hookMint,
+      userHookTokenAccount,
+      userWallet,
+      exchangeAmount,
+      [],
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+
+    const hookContext = new VestingContext({
+      valuedToken: { mintAddress: hookMint },
+      user: userWallet.publicKey,
+      backend: backendWallet.publicKey,
+      owner: program_authority.publicKey,
+      programId: program.programId,
+    });
+    const globalAccounts = {
+      programData: hookContext.programDataAccount,
+      backendData: hookContext.backendDataAccount,
+      admin: changeAuthorityWallet.publicKey,
+    };
+    const vaultAccounts = {
+      valuedTokenProgram: TOKEN_2022_PROGRAM_ID,
+      backend_data: hookContext.backendDataAccount,
+      vaultAccount: hookContext.vaultAccount,
+      valuedTokenMint: hookMint,
+      escrowTokenMint: hookContext.escrowTokenMintAccount,
+      payer: userWallet.publicKey,
+      backend: backendWallet.publicKey,
+    };
+    const initEscrowToken = program.methods
+      .initEscrowToken({
+        name: "Hook Token",
+        symbol: "esHOOK",
+        uri: valuedToken.description.uri,
+        appId: hookContext.appId,
+      })
+      .accounts(vaultAccounts)
+      .signers([backendWallet, userWallet]);
+
+    try {
+      await initEscrowToken.rpc();
+      assert.fail("Transfer hook valued tokens should not be allowed by default");
+    } catch (error) {
+      assert.include(error.message, "UnsupportedTokenExtension");
+    }
+
+    // Opt the vault in to the TransferHook extension, allowed globally only while it is created
+    const transferHookExtension = 14;
+    await program.methods
+      .setAllowedTokenExtensions({ allowedTokenExtensions: [transferHookExtension] })
+      .accounts(globalAccounts)
+      .signers([changeAuthorityWallet])
+      .rpc();
+    await initEscrowToken.rpc();
+    await program.methods
+      .initVaultTokenAccounts()
+      .accounts(vaultAccounts)
+      .signers([backendWallet, userWallet])
+      .rpc();
+    await program.methods
+      .setVaultAllowedTokenExtensions({ allowedTokenExtensions: [transferHookExtension] })
+      .accounts({
+        ...globalAccounts,
+        vaultAccount: hookContext.vaultAccount,
+        valuedTokenMint: hookMint,
+        escrowTokenMint: hookContext.escrowTokenMintAccount,
+      })
+      .signers([changeAuthorityWallet])
+      .rpc();
+    await program.methods
+      .setAllowedTokenExtensions({ allowedTokenExtensions: null })
+      .accounts(globalAccounts)
+      .signers([changeAuthorityWallet])
+      .rpc();
+
+    const exchangeAccounts = {
+      valuedTokenProgram: TOKEN_2022_PROGRAM_ID,
+      backendData: hookContext.backendDataAccount,
+      vaultAccount: hookContext.vaultAccount,
+      valuedTokenMint: hookMint,
+      escrowTokenMint: hookContext.escrowTokenMintAccount,
+      user: userWallet.publicKey,
+      backend: backendWallet.publicKey,
+    };
+    const hookAccounts = [
+      { pubkey: counterAccount, isSigner: false, isWritable: true },
+      { pubkey: hookProgram.programId, isSigner: false, isWritable: false },
+      { pubkey: extraAccountMetaList, isSigner: false, isWritable: false },
+    ];
+
+    try {
+      await program.methods
+        .exchange(new anchor.BN(exchangeAmount.toString()))
+        .accounts(exchangeAccounts)
+        .remainingAccounts(hookAccounts)
+        .signers([backendWallet, userWallet])
+        .rpc();
+      assert.fail("Hook programs should be trusted by the vault before moving tokens");
+    } catch (error) {
+      assert.include(error.message, "UntrustedTransferHook");
+    }
+
+    await program.methods
+      .updateVaultConfig({
+        sessionsTransferable: null,
+        trustedHookPrograms: [hookProgram.programId],
+        maxExchangedAmount: null,
+      })
+      .accounts({
+        backendData: hookContext.backendDataAccount,
+        vaultAccount: hookContext.vaultAccount,
+        valuedTokenMint: hookMint,
+        escrowTokenMint: hookContext.escrowTokenMintAccount,
+        backend: backendWallet.publicKey,
+      })
+      .signers([backendWallet])
+      .rpc();
+
+    try {
+      await program.methods
+        .exchange(new anchor.BN(exchangeAmount.toString()))
+        .accounts(exchangeAccounts)
+        .signers([backendWallet, userWallet])
+        .rpc();
+      assert.fail("Transfers should fail without the extra accounts of the hook");
+    } catch (error) {
+      assert.notInclude(error.message, "UntrustedTransferHook");
+    }
+
+    const exchangeTx = await program.methods
+      .exchange(new anchor.BN(exchangeAmount.toString()))
+      .accounts(exchangeAccounts)
+      .remainingAccounts(hookAccounts)
+      .signers([backendWallet, userWallet])
+      .rpc();
+    await provider.connection.confirmTransaction(exchangeTx);
+    logDebug("Transfer hook token exchanged");
+
+    const counterData = await hookProgram.account.counterAccount.fetch(counterAccount);
+    assert.equal(counterData.transfers.toString(), "1", "The hook should run on the exchange");
+    assert.equal(counterData.amountTransferred.toString(), exchangeAmount.toString());
+    const vaultHookTokenAccount = getAssociatedTokenAddressSync(
+      hookMint,
+      hookContext.vaultAccount,
+      true,
+      TOKEN_2022_PROGRAM_ID
+    );
+    assert.equal(await getTokenBalance(vaultHookTokenAccount), Number(exchangeAmount));
+  });
+
+  it("Configures the token extensions allowed for valued tokens", async () => {
+    const globalAccounts = {
+      programData: vestingContext.programDataAccount,
+      backendData: vestingContext.backendDataAccount,
+    };
+    const vaultAccounts = {
+      ...globalAccounts,
+      vaultAccount: vestingContext.vaultAccount,
+      valuedTokenMint: valuedToken.mintAddress,
+      escrowTokenMint: vestingContext.escrowTokenMintAccount,
+    };
+    // MintCloseAuthority, PermanentDelegate, MetadataPointer and TokenMetadata
+    const allowedTokenExtensions = [3, 12, 18, 19];
+
+    try {
+      await program.methods
+        .setAllowedTokenExtensions({ allowedTokenExtensions })
+        .accounts({ ...globalAccounts, admin: notBackendWallet.publicKey })
+        .signers([notBackendWallet])
+        .rpc();
+      assert.fail("Only admins should be able to change the allowed token extensions");
+    } catch (error) {
+      assert.include(error.message, "UnathorizedToExecute");
+    }
+
+    try {
+      await program.methods
+        .setAllowedTokenExtensions({ allowedTokenExtensions: [999] })
+        .accounts({ ...globalAccounts, admin: changeAuthorityWallet.publicKey })
+        .signers([changeAuthorityWallet])
+        .rpc();
+      assert.fail("Unknown token extensions should be rejected");
+    } catch (error) {
+      assert.include(error.message, "InvalidTokenExtension");
+    }
+
+    const tx = await program.methods
+      .setAllowedTokenExtensions({ allowedTokenExtensions })
+      .accounts({ ...globalAccounts, admin: changeAuthorityWallet.publicKey })
+      .signers([changeAuthorityWallet])
+      .rpc();
+    await provider.connection.confirmTransaction(tx);
+    const backendData = await program.account.backendAccountData.fetch(
+      vestingContext.backendDataAccount
+    );
+    assert.deepEqual(backendData.allowedTokenExtensions, allowedTokenExtensions);
+
+    const vaultTx = await program.methods
+      .setVaultAllowedTokenExtensions({ allowedTokenExtensions: [] })
+      .accounts({ ...vaultAccounts, admin: changeAuthorityWallet.publicKey })
+      .signers([changeAuthorityWallet])
+      .rpc();
+    await provider.connection.confirmTransaction(vaultTx);
+    const vaultData = await program.account.vaultAccount.fetch(vestingContext.vaultAccount);
+    assert.deepEqual(vaultData.allowedTokenExtensions, []);
+
+    // Restore the built-in defaults for the following tests
+    await program.methods
+      .setVaultAllowedTokenExtensions({ allowedTokenExtensions: null })
+      .accounts({ ...vaultAccounts, admin: changeAuthorityWallet.publicKey })
+      .signers([changeAuthorityWallet])
+      .rpc();
+    await program.methods
+      .setAllowedTokenExtensions({ allowedTokenExtensions: null })
+      .accounts({ ...globalAccounts, admin: changeAuthorityWallet.publicKey })
+      .signers([changeAuthorityWallet])
+      .rpc();
+  });
+
+  it("Refuses to operate a vault whose valued token configuration drifted", async () => {
+    const vaultData = await program.account.vaultAccount.fetch(vestingContext.vaultAccount);
+    assert.deepEqual(vaultData.valuedMintConfig.extensions, []);
+    assert.isNull(vaultData.valuedMintConfig.freezeAuthority);
+
+    const freezeAuthority = anchor.web3.Keypair.generate();
+    const driftingMint = await createMint(
+      provider.connection,
+      userWallet,
+      userWallet.publicKey,
+      freezeAuthority.publicKey,
+      9
+    );
+    const userDriftingTokenAccount = await createAssociatedTokenAccount(
+      provider.connection,
+      userWallet,
+      driftingMint,
+      userWallet.publicKey
+    );
+    await mintTo(
+      provider.connection,
+      userWallet,
+      driftingMint,
+      userDriftingTokenAccount,
+      userWallet,
+      BigInt(1000000000)
+    );
+
+    const driftingContext = new VestingContext({
+      valuedToken: { mintAddress: driftingMint },
+      user: userWallet.publicKey,
+      backend: backendWallet.publicKey,
+      owner: program_authority.publicKey,
+      programId: program.programId,
+    });
+    const vaultAccounts = {
+      valuedTokenProgram: valuedToken.mintInfo.owner,
+      backend_data: driftingContext.backendDataAccount,
+      vaultAccount: driftingContext.vaultAccount,
+      valuedTokenMint: driftingMint,
+      escrowTokenMint: driftingContext.escrowTokenMintAccount,
+      payer: userWallet.publicKey,
+      backend: backendWallet.publicKey,
+    };
+
+    const tx = new Transaction();
+    tx.add(
+      await program.methods
+        .initEscrowToken({
+          name: "Drifting Token",
+          symbol: "esDRF",
+          uri: valuedToken.description.uri,
+          appId: driftingContext.appId,
+        })
+        .accounts(vaultAccounts)
+        .instruction()
+    );
+    tx.add(await program.methods.initVaultTokenAccounts().accounts(vaultAccounts).instruction());
+    const txReceipt = await provider.connection.sendTransaction(tx, [backendWallet, userWallet]);
+    await provider.connection.confirmTransaction(txReceipt);
+
+    const driftingVaultData = await program.account.vaultAccount.fetch(
+      driftingContext.vaultAccount
+    );
+    assert.equal(
+      driftingVaultData.valuedMintConfig.freezeAuthority.toBase58(),
+      freezeAuthority.publicKey.toBase58()
+    );
+
+    const newFreezeAuthority = anchor.web3.Keypair.generate();
+    await setAuthority(
+      provider.connection,
+      userWallet,
+      driftingMint,
+      freezeAuthority,
+      AuthorityType.FreezeAccount,
+      newFreezeAuthority.publicKey
+    );
+
+    const exchange = () =>
+      program.methods
+        .exchange(new anchor.BN(500000000))
+        .accounts({
+          valuedTokenProgram: valuedToken.mintInfo.owner,
+          backendData: driftingContext.backendDataAccount,
+          vaultAccount: driftingContext.vaultAccount,
+          valuedTokenMint: driftingMint,
+          escrowTokenMint: driftingContext.escrowTokenMintAccount,
+          user: userWallet.publicKey,
+          backend: backendWallet.publicKey,
+        })
+        .signers([backendWallet, userWallet])
+        .rpc();
+    try {
+      await exchange();
+      assert.fail("Exchange should be refused after the freeze authority changed");
+    } catch (error) {
+      assert.include(error.message, "ValuedMintFreezeAuthorityChanged");
+    }
+
+    // Only admins can accept the new configuration
+    const refreshAccounts = {
+      programData: driftingContext.programDataAccount,
+      backendData: driftingContext.backendDataAccount,
+      vaultAccount: driftingContext.vaultAccount,
+      valuedTokenMint: driftingMint,
+      escrowTokenMint: driftingContext.escrowTokenMintAccount,
+    };
+    try {
+      await program.methods
+        .refreshValuedMintConfig()
+        .accounts({ ...refreshAccounts, admin: notBackendWallet.publicKey })
+        .signers([notBackendWallet])
+        .rpc();
+      assert.fail("Only admins should be able to refresh the valued mint configuration");
+    } catch (error) {
+      assert.include(error.message, "UnathorizedToExecute");
+    }
+    await program.methods
+      .refreshValuedMintConfig()
+      .accounts({ ...refreshAccounts, admin: changeAuthorityWallet.publicKey })
+      .signers([changeAuthorityWallet])
+      .rpc();
+    const refreshedVaultData = await program.account.vaultAccount.fetch(
+      driftingContext.vaultAccount
+    );
+    assert.equal(
+      refreshedVaultData.valuedMintConfig.freezeAuthority.toBase58(),
+      newFreezeAuthority.publicKey.toBase58()
+    );
+    await exchange();
+
+    // Revoking an authority does not need a refresh
+    await setAuthority(
+      provider.connection,
+      userWallet,
+      driftingMint,
+      newFreezeAuthority,
+      AuthorityType.FreezeAccount,
+      null
+    );
+    await exchange();
+  });
+
+  it("Refuses to migrate accounts already using the current layout", async () => {
+    const expectAlreadyMigrated = async (migration: Promise<string>) => {
+      try {
+        await migration;
+        assert.fail("Current accounts should not be migrated again");
+      } catch (error) {
+        assert.include(error.message, "AccountAlreadyMigrated");
+      }
+    };
+
+    await expectAlreadyMigrated(
+      program.methods
+        .migrateBackendData()
+        .accounts({
+          programData: vestingContext.programDataAccount,
+          backendData: vestingContext.backendDataAccount,
+          admin: changeAuthorityWallet.publicKey,
+        })
+        .signers([changeAuthorityWallet])
+        .rpc()
+    );
+    await expectAlreadyMigrated(
+      program.methods
+        .migrateVault()
+        .accounts({
+          programData: vestingContext.programDataAccount,
+          backendData: vestingContext.backendDataAccount,
+          vaultAccount: vestingContext.vaultAccount,
+          valuedTokenMint: valuedToken.mintAddress,
+          escrowTokenMint: vestingContext.escrowTokenMintAccount,
+          admin: changeAuthorityWallet.publicKey,
+        })
+        .signers([changeAuthorityWallet])
+        .rpc()
+    );
+    const vaultSessionsAccount = vestingContext.getVaultSessionsAccount(userWallet.publicKey);
+    const sessionAccounts = {
+      backendData: vestingContext.backendDataAccount,
+      vaultAccount: vestingContext.vaultAccount,
+      valuedTokenMint: valuedToken.mintAddress,
+      escrowTokenMint: vestingContext.escrowTokenMintAccount,
+      user: userWallet.publicKey,
+      vestingSessionsAccount: vaultSessionsAccount,
+      backend: backendWallet.publicKey,
+    };
+    await expectAlreadyMigrated(
+      program.methods
+        .migrateSessionsCollection()
+        .accounts(sessionAccounts)
+        .signers([backendWallet])
+        .rpc()
+    );
+    // Any open session of the collection, found by its vesting_sessions_account field
+    const [openSession] = await program.account.vestingSession.all([
+      { memcmp: { offset: 48, bytes: vaultSessionsAccount.toBase58() } },
+    ]);
+    await expectAlreadyMigrated(
+      program.methods
+        .migrateVestingSession()
+        .accounts({ ...sessionAccounts, vestingSessionAccount: openSession.publicKey })
+        .signers([backendWallet])
+        .rpc()
+    );
+  });
+
+  it("Pro-rates payouts of a vault drained by a permanent delegate", async () => {
+    const depositAmount = BigInt(1000000000); // 1 token
+    const drainedAmount = BigInt(500000000);
+    const delegatedMintKeypair = anchor.web3.Keypair.generate();
+    const delegatedMint = delegatedMintKeypair.publicKey;
+
+    const mintLen = getMintLen([ExtensionType.PermanentDelegate]);
+    const mintLamports = await provider.connection.getMinimumBalanceForRentExemption(mintLen);
+    await provider.sendAndConfirm(
+      new Transaction().add(
+        anchor.web3.SystemProgram.createAccount({
+          fromPubkey: userWallet.publicKey,
+          newAccountPubkey: delegatedMint,
+          space: mintLen,
+          lamports: mintLamports,
+          programId: TOKEN_2022_PROGRAM_ID,
+        }),
+        createInitializePermanentDelegateInstruction(
+          delegatedMint,
+          notBackendWallet.publicKey,
+          TOKEN_2022_PROGRAM_ID
+        ),
+        createInitializeMintInstruction(
+          delegatedMint,
+          9,
+          userWallet.publicKey,
+          null,
+          TOKEN_2022_PROGRAM_ID
+        )
+      ),
+      [userWallet, delegatedMintKeypair]
+    );
+    const userDelegatedTokenAccount = await createAssociatedTokenAccount(
+      provider.connection,
+      userWallet,
+      delegatedMint,
+      userWallet.publicKey,
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+    await mintTo(
+      provider.connection,
+      userWallet,
+      delegatedMint,
+      userDelegatedTokenAccount,
+      userWallet,
+      depositAmount * BigInt(2),
+      [],
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+
+    const delegatedContext = new VestingContext({
+      valuedToken: { mintAddress: delegatedMint },
+      user: userWallet.publicKey,
+      backend: backendWallet.publicKey,
+      owner: program_authority.publicKey,
+      programId: program.programId,
+    });
+    const vaultAccounts = {
+      valuedTokenProgram: TOKEN_2022_PROGRAM_ID,
+      backend_data: delegatedContext.backendDataAccount,
+      vaultAccount: delegatedContext.vaultAccount,
+      valuedTokenMint: delegatedMint,
+      escrowTokenMint: delegatedContext.escrowTokenMintAccount,
+      payer: userWallet.publicKey,
+      backend: backendWallet.publicKey,
+    };
+    const initTx = new Transaction();
+    initTx.add(
+      await program.methods
+        .initEscrowToken({
+          name: "Delegated Token",
+          symbol: "esDLG",
+          uri: valuedToken.description.uri,
+          appId: delegatedContext.appId,
+        })
+        .accounts(vaultAccounts)
+        .instruction()
+    );
+    initTx.add(await program.methods.initVaultTokenAccounts().accounts(vaultAccounts).instruction());
+    const initReceipt = await provider.connection.sendTransaction(initTx, [
+      backendWallet,
+      userWallet,
+    ]);
+    await provider.connection.confirmTransaction(initReceipt);
+
+    const exchangeAccounts = {
+      valuedTokenProgram: TOKEN_2022_PROGRAM_ID,
+      backendData: delegatedContext.backendDataAccount,
+      vaultAccount: delegatedContext.vaultAccount,
+      valuedTokenMint: delegatedMint,
+      escrowTokenMint: delegatedContext.escrowTokenMintAccount,
+      user: userWallet.publicKey,
+      backend: backendWallet.publicKey,
+    };
+    await program.methods
+      .exchange(new anchor.BN(depositAmount.toString()))
+      .accounts(exchangeAccounts)
+      .signers([backendWallet, userWallet])
+      .rpc();
+
+    // Two holders of half of the deposit each
+    const vaultSessionsAccount = delegatedContext.getVaultSessionsAccount(userWallet.publicKey);
+    const holderSessionAccounts = [new anchor.BN(0), new anchor.BN(1)].map((sessionId) =>
+      delegatedContext.getVaulSessionAccount(vaultSessionsAccount, sessionId)
+    );
+    const holderAmount = depositAmount / BigInt(2);
+    for (const sessionAccount of holderSessionAccounts) {
+      await program.methods
+        .createVestingSession(new anchor.BN(holderAmount.toString()))
+        .accounts({
+          backendData: delegatedContext.backendDataAccount,
+          vestingSessionsAccount: vaultSessionsAccount,
+          vestingSessionAccount: sessionAccount,
+          vaultAccount: delegatedContext.vaultAccount,
+          valuedTokenMint: delegatedMint,
+          escrowTokenMint: delegatedContext.escrowTokenMintAccount,
+          user: userWallet.publicKey,
+          backend: backendWallet.publicKey,
+        })
+        .signers([backendWallet, userWallet])
+        .rpc();
+    }
+
+    // The permanent delegate moves half of the vault balance out of the program control
+    const vaultDelegatedTokenAccount = getAssociatedTokenAddressSync(
+      delegatedMint,
+      delegatedContext.vaultAccount,
+      true,
+      TOKEN_2022_PROGRAM_ID
+    );
+    await transferChecked(
+      provider.connection,
+      notBackendWallet,
+      vaultDelegatedTokenAccount,
+      delegatedMint,
+      userDelegatedTokenAccount,
+      notBackendWallet,
+      drainedAmount,
+      9,
+      [],
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+
+    try {
+      await program.methods
+        .exchange(new anchor.BN(depositAmount.toString()))
+        .accounts(exchangeAccounts)
+        .signers([backendWallet, userWallet])
+        .rpc();
+      assert.fail("Exchanges into a drained vault should be refused");
+    } catch (error) {
+      assert.include(error.message, "VaultDegraded");
+    }
+
+    const sessionAccounts = (sessionAccount: PublicKey) => ({
+      valuedTokenProgram: TOKEN_2022_PROGRAM_ID,
+      backendData: delegatedContext.backendDataAccount,
+      vestingSessionsAccount: vaultSessionsAccount,
+      vestingSessionAccount: sessionAccount,
+      vaultAccount: delegatedContext.vaultAccount,
+      valuedTokenMint: delegatedMint,
+      escrowTokenMint: delegatedContext.escrowTokenMintAccount,
+      user: userWallet.publicKey,
+      backend: backendWallet.publicKey,
+    });
+    const exitSession = (sessionAccount: PublicKey) =>
+      program.methods
+        .sessionExit()
+        .accounts(sessionAccounts(sessionAccount))
+        .signers([backendWallet, userWallet])
+        .rpc();
+    const withdrawSession = (sessionAccount: PublicKey) =>
+      program.methods
+        .sessionWithdraw()
+        .accounts(sessionAccounts(sessionAccount))
+        .signers([backendWallet, userWallet])
+        .rpc();
+    const valuedPayout = async (instruction: Promise<string>) => {
+      const balanceBefore = await getTokenBalance(userDelegatedTokenAccount);
+      await instruction;
+      return (await getTokenBalance(userDelegatedTokenAccount)) - balanceBefore;
+    };
+    const [firstSessionAccount, secondSessionAccount] = holderSessionAccounts;
+
+    // Both holders are paid the same share of their claim, whatever the order of their exits
+    const holderShare = (holderAmount * (depositAmount - drainedAmount)) / depositAmount;
+    assert.equal(
+      (await valuedPayout(exitSession(firstSessionAccount))).toString(),
+      holderShare.toString(),
+      "Exit should pay the claim pro-rated by the vault coverage"
+    );
+    try {
+      await withdrawSession(firstSessionAccount);
+      assert.fail("Withdrawing again from a degraded vault should pay nothing more");
+    } catch (error) {
+      assert.include(error.message, "InsufficientWithdrawalAmount");
+    }
+    assert.equal(
+      (await valuedPayout(exitSession(secondSessionAccount))).toString(),
+      holderShare.toString(),
+      "The second holder should get the same share as the first one"
+    );
+
+    let vaultData = await program.account.vaultAccount.fetch(delegatedContext.vaultAccount);
+    assert.isTrue(vaultData.degraded);
+    assert.equal(vaultData.expectedValuedBalance.toString(), drainedAmount.toString());
+    assert.equal(vaultData.owedValuedBalance.toString(), drainedAmount.toString());
+    for (const sessionAccount of holderSessionAccounts) {
+      const sessionData = await program.account.vestingSession.fetch(sessionAccount);
+      assert.equal(sessionData.amountOwed.toString(), (drainedAmount / BigInt(2)).toString());
+    }
+
+    // Once the vault is refilled the owed tokens are paid in full
+    await transferChecked(
+      provider.connection,
+      userWallet,
+      userDelegatedTokenAccount,
+      delegatedMint,
+      vaultDelegatedTokenAccount,
+      userWallet,
+      drainedAmount,
+      9,
+      [],
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+    for (const sessionAccount of holderSessionAccounts) {
+      assert.equal(
+        (await valuedPayout(withdrawSession(sessionAccount))).toString(),
+        (drainedAmount / BigInt(2)).toString(),
+        "The owed tokens should be paid in full once the vault is refilled"
+      );
+    }
+    vaultData = await program.account.vaultAccount.fetch(delegatedContext.vaultAccount);
+    assert.isFalse(vaultData.degraded);
+    assert.equal(vaultData.expectedValuedBalance.toString(), "0");
+    assert.equal(vaultData.owedValuedBalance.toString(), "0");
+  });
+
+  it("Creates a vault with a non transferable escrow token", async () => {
+    const exchangeAmount = new anchor.BN(1000000000); // 1 token
+    const soulboundContext = new VestingContext({
+      valuedToken,
+      user: userWallet.publicKey,
+      backend: backendWallet.publicKey,
+      owner: program_authority.publicKey,
+      programId: program.programId,
+      appId: "test-app-soulbound",
+    });
+    const vaultAccounts = {
+      valuedTokenProgram: valuedToken.mintInfo.owner,
+      backend_data: soulboundContext.backendDataAccount,
+      vaultAccount: soulboundContext.vaultAccount,
+      valuedTokenMint: valuedToken.mintAddress,
+      escrowTokenMint: soulboundContext.escrowTokenMintAccount,
+      payer: userWallet.publicKey,
+      backend: backendWallet.publicKey,
+    };
+
+    // Anyone can send lamports to the escrow mint address before the vault is created
+    await provider.sendAndConfirm(
+      new Transaction().add(
+        anchor.web3.SystemProgram.transfer({
+          fromPubkey: notBackendWallet.publicKey,
+          toPubkey: soulboundContext.escrowTokenMintAccount,
+          lamports: 1000000,
+        })
+      ),
+      [notBackendWallet]
+    );
+
+    const tx = new Transaction();
+    tx.add(
+      await program.methods
+        .initNonTransferableEscrowToken({
+          name: valuedToken.description.name,
+          symbol: "sb" + valuedToken.description.symbol,
+          uri: valuedToken.description.uri,
+          appId: soulboundContext.appId,
+        })
+        .accounts(vaultAccounts)
+        .instruction()
+    );
+    tx.add(await program.methods.initVaultTokenAccounts().accounts(vaultAccounts).instruction());
+    const txReceipt = await provider.connection.sendTransaction(tx, [backendWallet, userWallet]);
+    await provider.connection.confirmTransaction(txReceipt);
+
+    const vaultData = await program.account.vaultAccount.fetch(soulboundContext.vaultAccount);
+    assert.isTrue(vaultData.escrowNonTransferable);
+    const escrowMintInfo = await provider.connection.getAccountInfo(
+      soulboundContext.escrowTokenMintAccount
+    );
+    assert.equal(escrowMintInfo.owner.toBase58(), TOKEN_2022_PROGRAM_ID.toBase58());
+    assert.isAtLeast(
+      escrowMintInfo.lamports,
+      await provider.connection.getMinimumBalanceForRentExemption(escrowMintInfo.data.length),
+      "The pre-funded escrow mint should be topped up to the rent exempt minimum"
+    );
+
+    await program.methods
+      .exchange(exchangeAmount)
+      .accounts({
+        valuedTokenProgram: valuedToken.mintInfo.owner,
+        backendData: soulboundContext.backendDataAccount,
+        vaultAccount: soulboundContext.vaultAccount,
+        valuedTokenMint: valuedToken.mintAddress,
+        escrowTokenMint: soulboundContext.escrowTokenMintAccount,
+        user: userWallet.publicKey,
+        backend: backendWallet.publicKey,
+      })
+      .signers([backendWallet, userWallet])
+      .rpc();
+
+    const userSoulboundAccount = getAssociatedTokenAddressSync(
+      soulboundContext.escrowTokenMintAccount,
+      userWallet.publicKey,
+      false,
+      TOKEN_2022_PROGRAM_ID
+    );
+    assert.equal(
+      (await getTokenBalance(userSoulboundAccount)).toString(),
+      exchangeAmount.toString()
+    );
+
+    const otherSoulboundAccount = await createAssociatedTokenAccount(
+      provider.connection,
+      notBackendWallet,
+      soulboundContext.escrowTokenMintAccount,
+      notBackendWallet.publicKey,
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+    try {
+      await transferChecked(
+        provider.connection,
+        userWallet,
+        userSoulboundAccount,
+        soulboundContext.escrowTokenMintAccount,
+        otherSoulboundAccount,
+        userWallet,
+        BigInt(1),
+        valuedToken.description.decimalPrecision,
+        [],
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      assert.fail("Non transferable escrow tokens should not move between users");
+    } catch (error) {
+      assert.notInclude(error.message, "Non transferable escrow tokens should not move");
+    }
+
+    // Vesting sessions keep working, the escrow is burned instead of moved into the vault
+    const vaultSessionsAccount = soulboundContext.getVaultSessionsAccount(userWallet.publicKey);
+    await program.methods
+      .createVestingSession(exchangeAmount)
+      .accounts({
+        backendData: soulboundContext.backendDataAccount,
+        vestingSessionsAccount: vaultSessionsAccount,
+        vestingSessionAccount: soulboundContext.getVaulSessionAccount(
+          vaultSessionsAccount,
+          new anchor.BN(0)
+        ),
+        vaultAccount: soulboundContext.vaultAccount,
+        valuedTokenMint: valuedToken.mintAddress,
+        escrowTokenMint: soulboundContext.escrowTokenMintAccount,
+        user: userWallet.publicKey,
+        backend: backendWallet.publicKey,
+      })
+      .signers([backendWallet, userWallet])
+      .rpc();
+
+    assert.equal((await getTokenBalance(userSoulboundAccount)).toString(), "0");
+    const escrowMint = await getMint(
+      provider.connection,
+      soulboundContext.escrowTokenMintAccount,
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+    assert.equal(escrowMint.supply.toString(), "0");
+  });
+
+  it("Freezes and thaws escrow token accounts for compliance", async () => {
+    const freezeAccounts = {
+      programData: vestingContext.programDataAccount,
+      backendData: vestingContext.backendDataAccount,
+      vaultAccount: vestingContext.vaultAccount,
+      valuedTokenMint: valuedToken.mintAddress,
+      escrowTokenMint: vestingContext.escrowTokenMintAccount,
+      escrowTokenAccount: userEscrowTokenAccount,
+    };
+    const reason = 7;
+
+    try {
+      await program.methods
+        .freezeEscrowAccount({ reason })
+        .accounts({ ...freezeAccounts, admin: notBackendWallet.publicKey })
+        .signers([notBackendWallet])
+        .rpc();
+      assert.fail("Only admins should be able to freeze escrow accounts");
+    } catch (error) {
+      assert.include(error.message, "UnathorizedToExecute");
+    }
+
+    const freezeTx = await program.methods
+      .freezeEscrowAccount({ reason })
+      .accounts({ ...freezeAccounts, admin: changeAuthorityWallet.publicKey })
+      .signers([changeAuthorityWallet])
+      .rpc();
+    await provider.connection.confirmTransaction(freezeTx);
+    const frozenAccount = await getAccount(
+      provider.connection,
+      userEscrowTokenAccount,
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+    assert.isTrue(frozenAccount.isFrozen);
+
+    try {
+      await program.methods
+        .exchange(new anchor.BN(1000000))
+        .accounts({
+          valuedTokenProgram: valuedToken.mintInfo.owner,
+          backendData: vestingContext.backendDataAccount,
+          vaultAccount: vestingContext.vaultAccount,
+          valuedTokenMint: valuedToken.mintAddress,
+          escrowTokenMint: vestingContext.escrowTokenMintAccount,
+          user: userWallet.publicKey,
+          backend: backendWallet.publicKey,
+        })
+        .signers([backendWallet, userWallet])
+        .rpc();
+      assert.fail("Exchanges into a frozen escrow account should be refused");
+    } catch (error) {
+      assert.include(error.message, "EscrowAccountFrozen");
+    }
+
+    const thawTx = await program.methods
+      .thawEscrowAccount({ reason })
+      .accounts({ ...freezeAccounts, admin: changeAuthorityWallet.publicKey })
+      .signers([changeAuthorityWallet])
+      .rpc();
+    await provider.connection.confirmTransaction(thawTx);
+    const thawedAccount = await getAccount(
+      provider.connection,
+      userEscrowTokenAccount,
+      undefined,
+      TOKEN_2022_PROGRAM_ID
+    );
+    assert.isFalse(thawedAccount.isFrozen);
+  });
+
+  it("Enforces the vault exchanged amount cap", async () => {
+    const configAccounts = {
+      backendData: vestingContext.backendDataAccount,
+      vaultAccount: vestingContext.vaultAccount,
+      valuedTokenMint: valuedToken.mintAddress,
+      escrowTokenMint: vestingContext.escrowTokenMintAccount,
+      backend: backendWallet.publicKey,
+    };
+    const exchangeAccounts = {
+      valuedTokenProgram: valuedToken.mintInfo.owner,
+      backendData: vestingContext.backendDataAccount,
+      vaultAccount: vestingContext.vaultAccount,
+      valuedTokenMint: valuedToken.mintAddress,
+      escrowTokenMint: vestingContext.escrowTokenMintAccount,
+      user: userWallet.publicKey,
+      backend: backendWallet.publicKey,
+    };
+    const remainingCap = new anchor.BN(1000000);
+
+    const vaultData = await program.account.vaultAccount.fetch(vestingContext.vaultAccount);
+    const maxExchangedAmount = vaultData.totalExchangedAmount.add(remainingCap);
+    await program.methods
+      .updateVaultConfig({
+        sessionsTransferable: null,
+        trustedHookPrograms: null,
+        maxExchangedAmount,
+      })
+      .accounts(configAccounts)
+      .signers([backendWallet])
+      .rpc();
+
+    try {
+      await program.methods
+        .exchange(remainingCap.addn(1))
+        .accounts(exchangeAccounts)
+        .signers([backendWallet, userWallet])
+        .rpc();
+      assert.fail("Exchanges above the vault cap should be refused");
+    } catch (error) {
+      assert.include(error.message, "SupplyCapReached");
+    }
+
+    const tx = await program.methods
+      .exchange(remainingCap)
+      .accounts(exchangeAccounts)
+      .signers([backendWallet, userWallet])
+      .rpc();
+    await provider.connection.confirmTransaction(tx);
+    const cappedVaultData = await program.account.vaultAccount.fetch(vestingContext.vaultAccount);
+    assert.equal(
+      cappedVaultData.totalExchangedAmount.toString(),
+      maxExchangedAmount.toString()
+    );
+
+    // Zero removes the cap
+    await program.methods
+      .updateVaultConfig({
+        sessionsTransferable: null,
+        trustedHookPrograms: null,
+        maxExchangedAmount: new anchor.BN(0),
+      })
+      .accounts(configAccounts)
+      .signers([backendWallet])
+      .rpc();
+    const uncappedVaultData = await program.account.vaultAccount.fetch(
+      vestingContext.vaultAccount
+    );
+    assert.isNull(uncappedVaultData.maxExchangedAmount);
+  });
+
+  it("Adds and removes additional escrow token metadata fields", async () => {
+    const metadataAccounts = {
+      backend_data: vestingContext.backendDataAccount,
+      vaultAccount: vestingContext.vaultAccount,
+      valuedTokenMint: valuedToken.mintAddress,
+      escrowTokenMint: vestingContext.escrowTokenMintAccount,
+      payer: userWallet.publicKey,
+      backend: backendWallet.publicKey,
+    };
+    const getAdditionalMetadata = async (): Promise<[string, string][]> => {
+      const mintAccountParsed = await provider.connection.getParsedAccountInfo(
+        vestingContext.escrowTokenMintAccount
+      );
+      const parsedData = mintAccountParsed.value.data as anchor.web3.ParsedAccountData;
+      return parsedData.parsed.info.extensions.find(
+        (extension) => extension.extension === "tokenMetadata"
+      ).state.additionalMetadata;
+    };
+
+    try {
+      await program.methods
+        .changeEscrowMetadata({ paramKey: "k".repeat(33), value: "too long key" })
+        .accounts(metadataAccounts)
+        .signers([backendWallet, userWallet])
+        .rpc();
+      assert.fail("Metadata keys longer than allowed should be rejected");
+    } catch (error) {
+      assert.include(error.message, "InvalidMetadataKeyLength");
+    }
+
+    const addTx = await program.methods
+      .changeEscrowMetadata({ paramKey: "vesting_terms", value: "linear, 6 months" })
+      .accounts(metadataAccounts)
+      .signers([backendWallet, userWallet])
+      .rpc();
+    await provider.connection.confirmTransaction(addTx);
+    assert.deepInclude(await getAdditionalMetadata(), ["vesting_terms", "linear, 6 months"]);
+
+    const payerBalance = await provider.connection.getBalance(userWallet.publicKey);
+    const removeTx = await program.methods
+      .removeEscrowMetadataKey({ key: "vesting_terms" })
+      .accounts(metadataAccounts)
+      .signers([backendWallet, userWallet])
+      .rpc();
+    await provider.connection.confirmTransaction(removeTx);
+    assert.notDeepInclude(await getAdditionalMetadata(), ["vesting_terms", "linear, 6 months"]);
+
+    const mintAccount = await provider.connection.getAccountInfo(
+      vestingContext.escrowTokenMintAccount
+    );
+    const mintMinimumBalance = await provider.connection.getMinimumBalanceForRentExemption(
+      mintAccount.data.length
+    );
+    assert.equal(mintAccount.lamports, mintMinimumBalance, "Excess rent should be refunded");
+    logDebug(
+      `Payer balance changed by ${
+        (await provider.connection.getBalance(userWallet.publicKey)) - payerBalance
+      } lamports`
+    );
+  });
+
+  it("Refunds escrow mint rent surplus when metadata shrinks", async () => {
+    const globalAccounts = {
+      programData: vestingContext.programDataAccount,
+      backendData: vestingContext.backendDataAccount,
+    };
+    const rentRefundWallet = anchor.web3.Keypair.generate();
+    const metadataAccounts = {
+      backend_data: vestingContext.backendDataAccount,
+      vaultAccount: vestingContext.vaultAccount,
+      valuedTokenMint: valuedToken.mintAddress,
+      escrowTokenMint: vestingContext.escrowTokenMintAccount,
+      payer: userWallet.publicKey,
+      backend: backendWallet.publicKey,
+    };
+    const getMintSurplus = async (): Promise<number> => {
+      const mintAccount = await provider.connection.getAccountInfo(
+        vestingContext.escrowTokenMintAccount
+      );
+      const mintMinimumBalance = await provider.connection.getMinimumBalanceForRentExemption(
+        mintAccount.data.length
+      );
+      return mintAccount.lamports - mintMinimumBalance;
+    };
+
+    await program.methods
+      .changeEscrowMetadata({ paramKey: "uri", value: `https://example.com/${"a".repeat(150)}` })
+      .accounts(metadataAccounts)
+      .signers([backendWallet, userWallet])
+      .rpc();
+    assert.equal(await getMintSurplus(), 0, "Growing metadata should only top up the mint");
+
+    // Shrinking a field refunds the payer when no destination is configured
+    const payerBalance = await provider.connection.getBalance(userWallet.publicKey);
+    await program.methods
+      .changeEscrowMetadata({ paramKey: "uri", value: "https://example.com" })
+      .accounts(metadataAccounts)
+      .signers([backendWallet, userWallet])
+      .rpc();
+    assert.equal(await getMintSurplus(), 0, "Surplus rent should be refunded");
+    logDebug(
+      `Payer balance changed by ${
+        (await provider.connection.getBalance(userWallet.publicKey)) - payerBalance
+      } lamports`
+    );
+
+    try {
+      await program.methods
+        .setRentRefundDestination({ rentRefundDestination: rentRefundWallet.publicKey })
+        .accounts({ ...globalAccounts, admin: notBackendWallet.publicKey })
+        .signers([notBackendWallet])
+        .rpc();
+      assert.fail("Only admins should be able to change the rent refund destination");
+    } catch (error) {
+      assert.include(error.message, "UnathorizedToExecute");
+    }
+
+    // Fund the destination so it stays rent exempt whatever the refunded amount
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(rentRefundWallet.publicKey, 1_000_000_000)
+    );
+    await program.methods
+      .setRentRefundDestination({ rentRefundDestination: rentRefundWallet.publicKey })
+      .accounts({ ...globalAccounts, admin: changeAuthorityWallet.publicKey })
+      .signers([changeAuthorityWallet])
+      .rpc();
+
+    await program.methods
+      .changeEscrowMetadata({ paramKey: "uri", value: `https://example.com/${"b".repeat(150)}` })
+      .accounts(metadataAccounts)
+      .signers([backendWallet, userWallet])
+      .rpc();
+
+    try {
+      await program.methods
+        .changeEscrowMetadata({ paramKey: "uri", value: "https://example.com" })
+        .accounts({ ...metadataAccounts, rentRefundDestination: notBackendWallet.publicKey })
+        .signers([backendWallet, userWallet])
+        .rpc();
+      assert.fail("Surplus rent should only go to the configured destination");
+    } catch (error) {
+      assert.include(error.message, "InvalidRentRefundDestination");
+    }
+
+    const shrinkTx = await program.methods
+      .changeEscrowMetadata({ paramKey: "uri", value: "https://example.com" })
+      .accounts({ ...metadataAccounts, rentRefundDestination: rentRefundWallet.publicKey })
+      .signers([backendWallet, userWallet])
+      .rpc();
+    await provider.connection.confirmTransaction(shrinkTx);
+    assert.equal(await getMintSurplus(), 0, "Surplus rent should be refunded");
+    assert.isAbove(
+      await provider.connection.getBalance(rentRefundWallet.publicKey),
+      1_000_000_000,
+      "Configured destination should receive the surplus rent"
+    );
+
+    // Restore the default so later metadata changes refund the payer
+    await program.methods
+      .setRentRefundDestination({ rentRefundDestination: null })
+      .accounts({ ...globalAccounts, admin: changeAuthorityWallet.publicKey })
+      .signers([changeAuthorityWallet])
+      .rpc();
+  });
+
+  it("Hands off the escrow metadata authorities and back to the vault", async () => {
+    const brandWallet = anchor.web3.Keypair.generate();
+    const authorityAccounts = {
+      programData: vestingContext.programDataAccount,
+      backendData: vestingContext.backendDataAccount,
+      vaultAccount: vestingContext.vaultAccount,
+      valuedTokenMint: valuedToken.mintAddress,
+      escrowTokenMint: vestingContext.escrowTokenMintAccount,
+      admin: changeAuthorityWallet.publicKey,
+      creator: userWallet.publicKey,
+    };
+    const metadataAccounts = {
+      backend_data: vestingContext.backendDataAccount,
+      vaultAccount: vestingContext.vaultAccount,
+      valuedTokenMint: valuedToken.mintAddress,
+      escrowTokenMint: vestingContext.escrowTokenMintAccount,
+      payer: userWallet.publicKey,
+      backend: backendWallet.publicKey,
+    };
+    const getAuthorities = async () => {
+      const escrowMint = await getMint(
+        provider.connection,
+        vestingContext.escrowTokenMintAccount,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+      const tokenMetadata = await getTokenMetadata(
+        provider.connection,
+        vestingContext.escrowTokenMintAccount
+      );
+      return {
+        updateAuthority: tokenMetadata.updateAuthority,
+        metadataPointerAuthority: getMetadataPointerState(escrowMint).authority,
+      };
+    };
+
+    try {
+      await program.methods
+        .setEscrowMetadataAuthority({
+          updateAuthority: brandWallet.publicKey,
+          metadataPointerAuthority: brandWallet.publicKey,
+        })
+        .accounts(authorityAccounts)
+        .signers([changeAuthorityWallet])
+        .rpc();
+      assert.fail("The vault creator should consent to the hand off");
+    } catch (error) {
+      assert.include(error.message, "ignature");
+    }
+
+    try {
+      await program.methods
+        .setEscrowMetadataAuthority({
+          updateAuthority: brandWallet.publicKey,
+          metadataPointerAuthority: brandWallet.publicKey,
+        })
+        .accounts({ ...authorityAccounts, admin: notBackendWallet.publicKey })
+        .signers([notBackendWallet, userWallet])
+        .rpc();
+      assert.fail("Only admins should be able to hand off the metadata authorities");
+    } catch (error) {
+      assert.include(error.message, "UnathorizedToExecute");
+    }
+
+    const handOffTx = await program.methods
+      .setEscrowMetadataAuthority({
+        updateAuthority: brandWallet.publicKey,
+        metadataPointerAuthority: brandWallet.publicKey,
+      })
+      .accounts(authorityAccounts)
+      .signers([changeAuthorityWallet, userWallet])
+      .rpc();
+    await provider.connection.confirmTransaction(handOffTx);
+    let authorities = await getAuthorities();
+    assert.equal(authorities.updateAuthority.toBase58(), brandWallet.publicKey.toBase58());
+    assert.equal(authorities.metadataPointerAuthority.toBase58(), brandWallet.publicKey.toBase58());
+
+    try {
+      await program.methods
+        .changeEscrowMetadata({ paramKey: "name", value: "Rebranded" })
+        .accounts(metadataAccounts)
+        .signers([backendWallet, userWallet])
+        .rpc();
+      assert.fail("The program should not change metadata it no longer controls");
+    } catch (error) {
+      assert.include(error.message, "EscrowMetadataAuthorityDelegated");
+    }
+
+    try {
+      await program.methods
+        .setEscrowMetadataAuthority({ updateAuthority: null, metadataPointerAuthority: null })
+        .accounts(authorityAccounts)
+        .signers([changeAuthorityWallet, userWallet])
+        .rpc();
+      assert.fail("The current authority should sign to hand the authorities back");
+    } catch (error) {
+      assert.include(error.message, "InvalidMetadataAuthority");
+    }
+
+    const handBackTx = await program.methods
+      .setEscrowMetadataAuthority({ updateAuthority: null, metadataPointerAuthority: null })
+      .accounts({
+        ...authorityAccounts,
+        currentUpdateAuthority: brandWallet.publicKey,
+        currentMetadataPointerAuthority: brandWallet.publicKey,
+      })
+      .signers([changeAuthorityWallet, userWallet, brandWallet])
+      .rpc();
+    await provider.connection.confirmTransaction(handBackTx);
+    authorities = await getAuthorities();
+    assert.equal(authorities.updateAuthority.toBase58(), vestingContext.vaultAccount.toBase58());
+    assert.equal(
+      authorities.metadataPointerAuthority.toBase58(),
+      vestingContext.vaultAccount.toBase58()
+    );
+    const vaultData = await program.account.vaultAccount.fetch(vestingContext.vaultAccount);
+    assert.isNull(vaultData.metadataUpdateAuthority);
+    assert.isNull(vaultData.metadataPointerAuthority);
+  });
+
+  it("Changes several escrow metadata fields in a single instruction", async () => {
+    const metadataAccounts = {
+      backend_data: vestingContext.backendDataAccount,
+      vaultAccount: vestingContext.vaultAccount,
+      valuedTokenMint: valuedToken.mintAddress,
+      escrowTokenMint: vestingContext.escrowTokenMintAccount,
+      payer: userWallet.publicKey,
+      backend: backendWallet.publicKey,
+    };
+
+    try {
+      await program.methods
+        .changeEscrowMetadataBatch({ updates: [] })
+        .accounts(metadataAccounts)
+        .signers([backendWallet, userWallet])
+        .rpc();
+      assert.fail("Empty batches should be rejected");
+    } catch (error) {
+      assert.include(error.message, "InvalidMetadataBatchSize");
+    }
+
+    // A single invalid update rejects the whole batch
+    try {
+      await program.methods
+        .changeEscrowMetadataBatch({
+          updates: [
+            { paramKey: "name", value: "Batched name" },
+            { paramKey: "symbol", value: "" },
+          ],
+        })
+        .accounts(metadataAccounts)
+        .signers([backendWallet, userWallet])
+        .rpc();
+      assert.fail("Batches with invalid updates should be rejected");
+    } catch (error) {
+      assert.include(error.message, "InvalidMeta");
+    }
+
+    const tx = await program.methods
+      .changeEscrowMetadataBatch({
+        updates: [
+          { paramKey: "name", value: "Batched name" },
+          { paramKey: "symbol", value: "BTCH" },
+          { paramKey: "uri", value: "https://example.com/batched.json" },
+        ],
+      })
+      .accounts(metadataAccounts)
+      .signers([backendWallet, userWallet])
+      .rpc();
+    await provider.connection.confirmTransaction(tx);
+
+    const tokenMetadata = await getTokenMetadata(
+      provider.connection,
+      vestingContext.escrowTokenMintAccount
+    );
+    assert.equal(tokenMetadata.name, "Batched name");
+    assert.equal(tokenMetadata.symbol, "BTCH");
+    assert.equal(tokenMetadata.uri, "https://example.com/batched.json");
+
+    const mintAccount = await provider.connection.getAccountInfo(
+      vestingContext.escrowTokenMintAccount
+    );
+    const mintMinimumBalance = await provider.connection.getMinimumBalanceForRentExemption(
+      mintAccount.data.length
+    );
+    assert.equal(mintAccount.lamports, mintMinimumBalance, "Mint rent should be rebalanced once");
+  });
+
+  it("Validates escrow metadata against the configured rules", async () => {
+    const globalAccounts = {
+      programData: vestingContext.programDataAccount,
+      backendData: vestingContext.backendDataAccount,
+    };
+    const metadataAccounts = {
+      backend_data: vestingContext.backendDataAccount,
+      vaultAccount: vestingContext.vaultAccount,
+      valuedTokenMint: valuedToken.mintAddress,
+      escrowTokenMint: vestingContext.escrowTokenMintAccount,
+      payer: userWallet.publicKey,
+      backend: backendWallet.publicKey,
+    };
+    const expectMetadataError = async (paramKey: string, value: string, errorCode: string) => {
+      try {
+        await program.methods
+          .changeEscrowMetadata({ paramKey, value })
+          .accounts(metadataAccounts)
+          .signers([backendWallet, userWallet])
+          .rpc();
+        assert.fail(`Updating ${paramKey} to ${value} should be rejected`);
+      } catch (error) {
+        assert.include(error.message, errorCode);
+      }
+    };
+
+    // Only the metadata value length is checked until an admin sets rules
+    await expectMetadataError("uri", "u".repeat(201), "MetadataValueTooLong");
+    await program.methods
+      .changeEscrowMetadata({ paramKey: "uri", value: "http://test.com" })
+      .accounts(metadataAccounts)
+      .signers([backendWallet, userWallet])
+      .rpc();
+
+    const metadataRules = {
+      maxNameLength: 24,
+      maxSymbolLength: 8,
+      maxUriLength: 100,
+      allowedUriSchemes: ["https", "ipfs"],
+      asciiSymbol: true,
+      symbolPrefix: "es",
+      symbolSuffix: null,
+    };
+    try {
+      await program.methods
+        .setMetadataRules({ metadataRules })
+        .accounts({ ...globalAccounts, admin: notBackendWallet.publicKey })
+        .signers([notBackendWallet])
+        .rpc();
+      assert.fail("Only admins should be able to change the metadata rules");
+    } catch (error) {
+      assert.include(error.message, "UnathorizedToExecute");
+    }
+
+    try {
+      await program.methods
+        .setMetadataRules({ metadataRules: { ...metadataRules, maxNameLength: 0 } })
+        .accounts({ ...globalAccounts, admin: changeAuthorityWallet.publicKey })
+        .signers([changeAuthorityWallet])
+        .rpc();
+      assert.fail("Inconsistent metadata rules should be rejected");
+    } catch (error) {
+      assert.include(error.message, "InvalidMetadataRules");
+    }
+
+    try {
+      await program.methods
+        .setMetadataRules({ metadataRules: { ...metadataRules, maxUriLength: 201 } })
+        .accounts({ ...globalAccounts, admin: changeAuthorityWallet.publicKey })
+        .signers([changeAuthorityWallet])
+        .rpc();
+      assert.fail("Limits above the maximum metadata value length should be rejected");
+    } catch (error) {
+      assert.include(error.message, "InvalidMetadataRules");
+    }
+
+    await program.methods
+      .setMetadataRules({ metadataRules })
+      .accounts({ ...globalAccounts, admin: changeAuthorityWallet.publicKey })
+      .signers([changeAuthorityWallet])
+      .rpc();
+
+    await expectMetadataError("name", "n".repeat(25), "MetadataNameTooLong");
+    await expectMetadataError("symbol", "es".padEnd(9, "s"), "MetadataSymbolTooLong");
+    await expectMetadataError("symbol", "esTVT€", "NonAsciiMetadataSymbol");
+    await expectMetadataError("symbol", "TVT", "MissingEscrowSymbolPrefix");
+    await expectMetadataError("uri", "changed:https", "UnsupportedMetadataUriScheme");
+    await expectMetadataError("uri", "https://" + "u".repeat(150), "MetadataUriTooLong");
+    await expectMetadataError("uri", "ar://test", "UnsupportedMetadataUriScheme");
+    await program.methods
+      .changeEscrowMetadata({
+        paramKey: "uri",
+        value: "ipfs://bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi",
+      })
+      .accounts(metadataAccounts)
+      .signers([backendWallet, userWallet])
+      .rpc();
+    await program.methods
+      .changeEscrowMetadata({ paramKey: "symbol", value: "esTVT" })
+      .accounts(metadataAccounts)
+      .signers([backendWallet, userWallet])
+      .rpc();
+
+    // Remove the rules
+    await program.methods
+      .setMetadataRules({ metadataRules: null })
+      .accounts({ ...globalAccounts, admin: changeAuthorityWallet.publicKey })
+      .signers([changeAuthorityWallet])
+      .rpc();
+    const backendData = await program.account.backendAccountData.fetch(
+      vestingContext.backendDataAccount
+    );
+    assert.isNull(backendData.metadataRules);
+  });
+
+  it("Emits events carrying the actor, mints, balances, time and slot", async () => {
+    const eventParser = new anchor.EventParser(program.programId, program.coder);
+    const getEvents = async (signature: string) => {
+      await provider.connection.confirmTransaction(signature, "confirmed");
+      const transaction = await provider.connection.getTransaction(signature, {
+        commitment: "confirmed",
+        maxSupportedTransactionVersion: 0,
+      });
+      return [...eventParser.parseLogs(transaction.meta.logMessages)];
+    };
+
+    const backendTx = await program.methods
+      .setBackendAccount({
+        newBackendAccount: backendWallet.publicKey,
+        newAuthority: null,
+      })
+      .accounts({
+        backend_data: vestingContext.backendDataAccount,
+        payer: changeAuthorityWallet.publicKey,
+        programData: vestingContext.programDataAccount,
+      })
+      .signers([changeAuthorityWallet])
+      .rpc();
+    const [backendEvent] = await getEvents(backendTx);
+    assert.equal(backendEvent.name, "backendAccountSetEvent");
+    assert.equal(backendEvent.data.version, eventSchema.version);
+    assert.equal(backendEvent.data.actor.toBase58(), changeAuthorityWallet.publicKey.toBase58());
+    assert.equal(
+      backendEvent.data.backendAccount.toBase58(),
+      backendWallet.publicKey.toBase58()
+    );
+    assert.equal(
+      backendEvent.data.previousBackendAccount.toBase58(),
+      backendWallet.publicKey.toBase58()
+    );
+
+    const exchangeAmount = BigInt(anchor.web3.LAMPORTS_PER_SOL);
+    await valuedToken.mintTokensTo(userWallet, exchangeAmount);
+    const valuedVaultBalance = await getTokenBalance(
+      programVaultTokenAccounts.valuedTokenVault,
+      valuedToken.mintInfo.owner
+    );
+    const exchangeTx = await program.methods
+      .exchange(new anchor.BN(exchangeAmount.toString()))
+      .accounts({
+        valuedTokenProgram: valuedToken.mintInfo.owner,
+        backendData: vestingContext.backendDataAccount,
+        vaultAccount: vestingContext.vaultAccount,
+        valuedTokenMint: valuedToken.mintAddress,
+        escrowTokenMint: vestingContext.escrowTokenMintAccount,
+        user: userWallet.publicKey,
+        backend: backendWallet.publicKey,
+      })
+      .signers([backendWallet, userWallet])
+      .rpc();
+    const exchangeEvent = (await getEvents(exchangeTx)).find(
+      (event) => event.name === "exchangedEvent"
+    );
+    assert.equal(exchangeEvent.data.version, eventSchema.version);
+    assert.equal(exchangeEvent.data.user.toBase58(), userWallet.publicKey.toBase58());
+    assert.equal(exchangeEvent.data.actor.toBase58(), userWallet.publicKey.toBase58());
+    assert.equal(
+      exchangeEvent.data.valuedTokenMint.toBase58(),
+      valuedToken.mintAddress.toBase58()
+    );
+    assert.equal(
+      exchangeEvent.data.escrowTokenMint.toBase58(),
+      vestingContext.escrowTokenMintAccount.toBase58()
+    );
+    assert.equal(
+      exchangeEvent.data.valuedVaultBalanceBefore.toString(),
+      valuedVaultBalance.toString()
+    );
+    assert.equal(
+      exchangeEvent.data.valuedVaultBalanceAfter.toString(),
+      (BigInt(valuedVaultBalance.toString()) + exchangeAmount).toString()
+    );
+    assert.isAbove(exchangeEvent.data.time.toNumber(), 0);
+    assert.isAbove(exchangeEvent.data.slot.toNumber(), 0);
+  });
+
+  it("Keeps event layouts versioned and append-only", async () => {
+    const idl = vestingContractIdl as any;
+    const schemaVersion = idl.constants.find(
+      (constant) => constant.name === "EVENT_SCHEMA_VERSION"
+    );
+    assert.equal(Number(schemaVersion.value), eventSchema.version);
+
+    const layoutOf = (name: string) => {
+      const definition = idl.types.find((type) => type.name === name);
+      assert.isDefined(definition, `${name} is missing from the IDL`);
+      return definition.type.fields;
+    };
+    const pinnedLayouts = { ...eventSchema.events, ...eventSchema.types };
+    for (const [name, pinnedFields] of Object.entries(pinnedLayouts) as [string, any[]][]) {
+      const fields = layoutOf(name);
+      assert.isAtLeast(fields.length, pinnedFields.length, `${name} lost fields`);
+      pinnedFields.forEach((pinnedField, index) => {
+        assert.deepEqual(
+          { name: fields[index].name, type: fields[index].type },
+          pinnedField,
+          `${name}.${pinnedField.name} was moved, renamed or retyped`
+        );
+      });
+      assert.lengthOf(
+        fields,
+        pinnedFields.length,
+        `${name} has unpinned fields, append them to the fixture and bump EVENT_SCHEMA_VERSION`
+      );
+    }
+
+    for (const event of idl.events) {
+      assert.property(eventSchema.events, event.name, `${event.name} is not pinned`);
+      assert.deepEqual(
+        layoutOf(event.name)[0],
+        { name: "version", type: "u8" },
+        `${event.name} does not start with its version`
+      );
+    }
+
+    // Deprecated fields stay in place and say so in the IDL
+    for (const [name, deprecatedFields] of Object.entries(eventSchema.deprecated) as [
+      string,
+      string[]
+    ][]) {
+      for (const fieldName of deprecatedFields) {
+        const field = layoutOf(name).find((field) => field.name === fieldName);
+        assert.match(field.docs?.[0] ?? "", /^Deprecated:/, `${name}.${fieldName} docs`);
+      }
+    }
+
+    // The pre-versioning fields follow the version in their original order
+    for (const [name, baselineFields] of Object.entries(eventSchema.baseline) as [string, any[]][]) {
+      assert.deepEqual(
+        eventSchema.events[name].slice(1, baselineFields.length + 1),
+        baselineFields,
+        `${name} no longer follows the version with its pre-versioning layout`
+      );
+    }
+  });
+
+  it("Decodes events with the layouts of older schema versions", async () => {
+    // A decoder built for an older version, which did not know the balance fields yet
+    const legacyIdl = JSON.parse(JSON.stringify(vestingContractIdl));
+    const legacyExchangedEvent = legacyIdl.types.find(
+      (type) => type.name === "ExchangedEvent"
+    );
+    legacyExchangedEvent.type.fields = eventSchema.events.ExchangedEvent.slice(
+      0,
+      eventSchema.baseline.ExchangedEvent.length + 1
+    );
+    const legacyParser = new anchor.EventParser(
+      program.programId,
+      new anchor.BorshCoder(legacyIdl)
+    );
+
+    const exchangeAmount = BigInt(anchor.web3.LAMPORTS_PER_SOL);
+    await valuedToken.mintTokensTo(userWallet, exchangeAmount);
+    const exchangeTx = await program.methods
+      .exchange(new anchor.BN(exchangeAmount.toString()))
+      .accounts({
+        valuedTokenProgram: valuedToken.mintInfo.owner,
+        backendData: vestingContext.backendDataAccount,
+        vaultAccount: vestingContext.vaultAccount,
+        valuedTokenMint: valuedToken.mintAddress,
+        escrowTokenMint: vestingContext.escrowTokenMintAccount,
+        user: userWallet.publicKey,
+        backend: backendWallet.publicKey,
+      })
+      .signers([backendWallet, userWallet])
+      .rpc();
+    await provider.connection.confirmTransaction(exchangeTx, "confirmed");
+    const transaction = await provider.connection.getTransaction(exchangeTx, {
+      commitment: "confirmed",
+      maxSupportedTransactionVersion: 0,
+    });
+
+    const legacyEvent = [...legacyParser.parseLogs(transaction.meta.logMessages)].find(
+      (event) => event.name === "exchangedEvent"
+    );
+    assert.equal(
+      legacyEvent.data.vaultAccount.toBase58(),
+      vestingContext.vaultAccount.toBase58()
+    );
+    assert.equal(legacyEvent.data.amount.toString(), exchangeAmount.toString());
+    assert.equal(legacyEvent.data.version, eventSchema.version);
+    assert.notProperty(legacyEvent.data, "valuedVaultBalanceBefore");
+  });
+
   // // Additional security tests
   // it("Handles rapid sequential withdrawals correctly", async () => {
   //   // Create a new vesting session